@@ -0,0 +1,577 @@
+//! Just enough of the zip and tar formats to ship bundles and symbols: notarization uploads,
+//! `symbols.zip` and the archives of `cargo jk package`.
+//!
+//! Entries keep their Unix modes, in the external attributes of zip entries and the mode of tar
+//! headers, so directories, symlinks and the executable bit survive as they are on disk. A
+//! directory added under its own name is laid out as `ditto -c -k --keepParent` would. Every entry
+//! is dated 1980-01-01, so the same files always make the same archive.
+
+use std::{io::Write, path::Path};
+
+use flate2::{
+    Compression,
+    write::{DeflateEncoder, GzEncoder},
+};
+
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+/// Version 2.0, made on Unix
+const VERSION_MADE_BY: u16 = (3 << 8) | 20;
+const VERSION_NEEDED: u16 = 20;
+/// File names are UTF-8
+const FLAG_UTF8: u16 = 0x0800;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+/// 1980-01-01 00:00, the earliest DOS date
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+const TAR_BLOCK: usize = 512;
+/// 1980-01-01 00:00 UTC, the date of zip entries
+const TAR_MTIME: u64 = 315_532_800;
+const TAR_NAME_SIZE: usize = 100;
+const TAR_PREFIX_SIZE: usize = 155;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+struct Entry {
+    /// Path in the archive; directories end with `/`
+    name: String,
+    mode: u32,
+    /// File contents, or the target of a symlink
+    data: Vec<u8>,
+}
+
+/// An archive being put together in memory.
+#[derive(Default)]
+pub struct Archive {
+    entries: Vec<Entry>,
+}
+
+impl Archive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `path` as `name`, with everything below it if it is a directory.
+    pub fn add(&mut self, path: &Path, name: &str) -> Result<(), String> {
+        collect(path, name, &mut self.entries)
+    }
+
+    /// Adds `path` at the top level under its own file name.
+    pub fn add_top_level(&mut self, path: &Path) -> Result<(), String> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| format!("{} has no file name", path.display()))?
+            .to_string_lossy()
+            .into_owned();
+        self.add(path, &name)
+    }
+
+    /// Adds an empty directory `name`, for the files added below it.
+    pub fn add_directory(&mut self, name: &str) {
+        self.entries.push(Entry {
+            name: format!("{name}/"),
+            mode: S_IFDIR | 0o755,
+            data: Vec::new(),
+        });
+    }
+
+    /// The archive as a zip file, deflating the files that get smaller for it.
+    pub fn zip(&self) -> Result<Vec<u8>, String> {
+        let too_large = |_| "the archive is too large for a zip file without Zip64".to_string();
+        let mut zip = Vec::new();
+        let mut central = Vec::new();
+        for entry in &self.entries {
+            let offset = u32::try_from(zip.len()).map_err(too_large)?;
+            let size = u32::try_from(entry.data.len()).map_err(too_large)?;
+            let crc = crc32(&entry.data);
+            let name = entry.name.as_bytes();
+            let deflated = match entry.mode & S_IFMT {
+                S_IFREG => deflate(&entry.data)?,
+                _ => None,
+            };
+            let (method, data) = match &deflated {
+                Some(deflated) => (METHOD_DEFLATED, deflated),
+                None => (METHOD_STORED, &entry.data),
+            };
+            let compressed_size = data.len() as u32;
+
+            put_u32(&mut zip, LOCAL_FILE_HEADER);
+            put_u16(&mut zip, VERSION_NEEDED);
+            put_u16(&mut zip, FLAG_UTF8);
+            put_u16(&mut zip, method);
+            put_u16(&mut zip, 0); // time
+            put_u16(&mut zip, DOS_DATE);
+            put_u32(&mut zip, crc);
+            put_u32(&mut zip, compressed_size);
+            put_u32(&mut zip, size);
+            put_u16(&mut zip, name.len() as u16);
+            put_u16(&mut zip, 0); // extra field
+            zip.extend_from_slice(name);
+            zip.extend_from_slice(data);
+
+            put_u32(&mut central, CENTRAL_DIRECTORY_HEADER);
+            put_u16(&mut central, VERSION_MADE_BY);
+            put_u16(&mut central, VERSION_NEEDED);
+            put_u16(&mut central, FLAG_UTF8);
+            put_u16(&mut central, method);
+            put_u16(&mut central, 0);
+            put_u16(&mut central, DOS_DATE);
+            put_u32(&mut central, crc);
+            put_u32(&mut central, compressed_size);
+            put_u32(&mut central, size);
+            put_u16(&mut central, name.len() as u16);
+            put_u16(&mut central, 0); // extra field
+            put_u16(&mut central, 0); // comment
+            put_u16(&mut central, 0); // disk
+            put_u16(&mut central, 0); // internal attributes
+            put_u32(&mut central, entry.mode << 16);
+            put_u32(&mut central, offset);
+            central.extend_from_slice(name);
+        }
+
+        let count = u16::try_from(self.entries.len()).map_err(|_| {
+            "the archive has too many files for a zip file without Zip64".to_string()
+        })?;
+        let central_offset = u32::try_from(zip.len()).map_err(too_large)?;
+        let central_size = central.len() as u32;
+        zip.extend(central);
+        put_u32(&mut zip, END_OF_CENTRAL_DIRECTORY);
+        put_u16(&mut zip, 0);
+        put_u16(&mut zip, 0);
+        put_u16(&mut zip, count);
+        put_u16(&mut zip, count);
+        put_u32(&mut zip, central_size);
+        put_u32(&mut zip, central_offset);
+        put_u16(&mut zip, 0); // comment
+        Ok(zip)
+    }
+
+    /// The archive as a gzipped ustar file, with pax headers for names ustar cannot hold.
+    pub fn tar_gz(&self) -> Result<Vec<u8>, String> {
+        let mut tar = Vec::new();
+        for entry in &self.entries {
+            let (type_flag, link, data) = match entry.mode & S_IFMT {
+                S_IFDIR => (b'5', &[][..], &[][..]),
+                S_IFLNK => (b'2', &entry.data[..], &[][..]),
+                _ => (b'0', &[][..], &entry.data[..]),
+            };
+            let name = entry.name.as_bytes();
+            let split = ustar_split(name);
+            let mut pax = Vec::new();
+            if split.is_none() {
+                pax.extend(pax_record("path", name));
+            }
+            if link.len() > TAR_NAME_SIZE {
+                pax.extend(pax_record("linkpath", link));
+            }
+            if !pax.is_empty() {
+                let header = tar_header(b"PaxHeader", &[], 0o644, pax.len() as u64, b'x', &[]);
+                tar.extend(header);
+                tar.extend(&pax);
+                tar.resize(tar.len().next_multiple_of(TAR_BLOCK), 0);
+            }
+            // With a pax path, the ustar name only needs to be a recognizable stand-in
+            let (prefix, name) =
+                split.unwrap_or((&[], &name[name.len().saturating_sub(TAR_NAME_SIZE)..]));
+            let link = &link[..link.len().min(TAR_NAME_SIZE)];
+            tar.extend(tar_header(
+                name,
+                prefix,
+                entry.mode & 0o7777,
+                data.len() as u64,
+                type_flag,
+                link,
+            ));
+            tar.extend(data);
+            tar.resize(tar.len().next_multiple_of(TAR_BLOCK), 0);
+        }
+        // Two empty blocks end the archive
+        tar.resize(tar.len() + 2 * TAR_BLOCK, 0);
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::best());
+        gz.write_all(&tar).map_err(|e| e.to_string())?;
+        gz.finish().map_err(|e| e.to_string())
+    }
+}
+
+/// Adds `path` as `name`, then everything below it in name order.
+fn collect(path: &Path, name: &str, entries: &mut Vec<Entry>) -> Result<(), String> {
+    let error = |e: std::io::Error| format!("cannot read {}: {e}", path.display());
+    let metadata = std::fs::symlink_metadata(path).map_err(error)?;
+    if metadata.is_symlink() {
+        let target = std::fs::read_link(path).map_err(error)?;
+        entries.push(Entry {
+            name: name.to_string(),
+            mode: S_IFLNK | 0o755,
+            data: target.to_string_lossy().into_owned().into_bytes(),
+        });
+    } else if metadata.is_dir() {
+        entries.push(Entry {
+            name: format!("{name}/"),
+            mode: S_IFDIR | 0o755,
+            data: Vec::new(),
+        });
+        let mut children = std::fs::read_dir(path)
+            .map_err(error)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(error)?;
+        children.sort();
+        for child in children {
+            collect(
+                &path.join(&child),
+                &format!("{name}/{}", child.to_string_lossy()),
+                entries,
+            )?;
+        }
+    } else {
+        entries.push(Entry {
+            name: name.to_string(),
+            mode: S_IFREG | permissions(&metadata, name),
+            data: std::fs::read(path).map_err(error)?,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &std::fs::Metadata, _name: &str) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o777
+}
+
+/// Hosts without Unix permissions: only the bundle executable is executable.
+#[cfg(not(unix))]
+fn permissions(_metadata: &std::fs::Metadata, name: &str) -> u32 {
+    if name.contains("/Contents/MacOS/") {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+/// Raw deflate of `data`, or `None` if that would not make it smaller.
+fn deflate(data: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).map_err(|e| e.to_string())?;
+    let deflated = encoder.finish().map_err(|e| e.to_string())?;
+    Ok((deflated.len() < data.len()).then_some(deflated))
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend(value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend(value.to_le_bytes());
+}
+
+/// Splits a name into the ustar prefix and name fields at a `/`, if it fits.
+fn ustar_split(name: &[u8]) -> Option<(&[u8], &[u8])> {
+    if name.len() <= TAR_NAME_SIZE {
+        return Some((&[], name));
+    }
+    // A directory's trailing slash cannot be the split point
+    let trimmed = name.strip_suffix(b"/").unwrap_or(name);
+    (0..trimmed.len())
+        .rev()
+        .filter(|&index| trimmed[index] == b'/')
+        .map(|index| (&name[..index], &name[index + 1..]))
+        .find(|(prefix, name)| prefix.len() <= TAR_PREFIX_SIZE && name.len() <= TAR_NAME_SIZE)
+}
+
+/// A pax extended header record: `<length> <key>=<value>\n`, the length counting itself.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest + rest.to_string().len();
+    if length.to_string().len() != rest.to_string().len() {
+        length += 1;
+    }
+    let mut record = format!("{length} {key}=").into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+fn tar_header(
+    name: &[u8],
+    prefix: &[u8],
+    mode: u32,
+    size: u64,
+    type_flag: u8,
+    link: &[u8],
+) -> [u8; TAR_BLOCK] {
+    let mut header = [0; TAR_BLOCK];
+    let mut put = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    put(0, name);
+    put(100, format!("{mode:07o}\0").as_bytes());
+    put(108, b"0000000\0"); // uid
+    put(116, b"0000000\0"); // gid
+    put(124, format!("{size:011o}\0").as_bytes());
+    put(136, format!("{TAR_MTIME:011o}\0").as_bytes());
+    put(148, b"        "); // checksum, counted as spaces
+    put(156, &[type_flag]);
+    put(157, link);
+    put(257, b"ustar\x0000"); // magic and version
+    put(345, prefix);
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_like_zip() {
+        // The check value of CRC-32/ISO-HDLC
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn pax_records_count_their_own_length() {
+        // Lengths around 10 and 100 change the number of digits of the length itself
+        for size in 0..300 {
+            let record = pax_record("path", &vec![b'a'; size]);
+            let (length, _) = std::str::from_utf8(&record)
+                .unwrap()
+                .split_once(' ')
+                .unwrap();
+            assert_eq!(
+                length.parse::<usize>().unwrap(),
+                record.len(),
+                "value of {size} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn splits_long_names_for_ustar() {
+        let name = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        assert_eq!(
+            ustar_split(name.as_bytes()),
+            Some(("d".repeat(120).as_bytes(), "f".repeat(90).as_bytes()))
+        );
+        assert_eq!(
+            ustar_split(format!("{}/", "d".repeat(101)).as_bytes()),
+            None
+        );
+        assert_eq!(ustar_split("f".repeat(101).as_bytes()), None);
+    }
+
+    /// What an entry reads back as.
+    #[cfg(unix)]
+    #[derive(Debug, PartialEq)]
+    enum Read {
+        Directory,
+        File { mode: u32, data: Vec<u8> },
+        Symlink(String),
+    }
+
+    /// A plugin bundle with an executable, a framework's symlinks, a name too long for ustar and
+    /// a symlink target too long for it.
+    #[cfg(unix)]
+    fn bundle(dir: &Path) -> (std::path::PathBuf, std::collections::BTreeMap<String, Read>) {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        let long_name = format!("Presets/{}.ffx", "Glow ".repeat(24));
+        let long_target = format!("{}Shared/Glow.aex", "../".repeat(40));
+        let split_name = format!("{}/{}.png", "Localized".repeat(8), "Thumbnail".repeat(8));
+        let executable = b"\xcf\xfa\xed\xfe".repeat(1024);
+
+        let bundle = dir.join("Glow.plugin");
+        let contents = bundle.join("Contents");
+        let framework = contents.join("Frameworks/Glow.framework");
+        let resources = contents.join("Resources");
+        for dir in [
+            contents.join("MacOS"),
+            framework.join("Versions/A"),
+            resources.join("Presets"),
+            resources.join("Localized".repeat(8)),
+        ] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        let files = [
+            (contents.join("Info.plist"), 0o644, &b"<plist/>"[..]),
+            (contents.join("MacOS/Glow"), 0o755, &executable[..]),
+            (framework.join("Versions/A/Glow"), 0o755, &executable[..]),
+            (resources.join(&long_name), 0o600, b"preset"),
+            (resources.join(&split_name), 0o644, b"png"),
+        ];
+        for (path, mode, data) in files {
+            std::fs::write(&path, data).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        symlink("A", framework.join("Versions/Current")).unwrap();
+        symlink("Versions/Current/Glow", framework.join("Glow")).unwrap();
+        symlink(&long_target, resources.join("Shared")).unwrap();
+
+        let file = |mode, data: &[u8]| Read::File {
+            mode,
+            data: data.to_vec(),
+        };
+        let link = |target: &str| Read::Symlink(target.to_string());
+        let expected = [
+            ("Glow.plugin/", Read::Directory),
+            ("Glow.plugin/Contents/", Read::Directory),
+            ("Glow.plugin/Contents/Info.plist", file(0o644, b"<plist/>")),
+            ("Glow.plugin/Contents/MacOS/", Read::Directory),
+            ("Glow.plugin/Contents/MacOS/Glow", file(0o755, &executable)),
+            ("Glow.plugin/Contents/Frameworks/", Read::Directory),
+            (
+                "Glow.plugin/Contents/Frameworks/Glow.framework/",
+                Read::Directory,
+            ),
+            (
+                "Glow.plugin/Contents/Frameworks/Glow.framework/Glow",
+                link("Versions/Current/Glow"),
+            ),
+            (
+                "Glow.plugin/Contents/Frameworks/Glow.framework/Versions/",
+                Read::Directory,
+            ),
+            (
+                "Glow.plugin/Contents/Frameworks/Glow.framework/Versions/A/",
+                Read::Directory,
+            ),
+            (
+                "Glow.plugin/Contents/Frameworks/Glow.framework/Versions/A/Glow",
+                file(0o755, &executable),
+            ),
+            (
+                "Glow.plugin/Contents/Frameworks/Glow.framework/Versions/Current",
+                link("A"),
+            ),
+            ("Glow.plugin/Contents/Resources/", Read::Directory),
+            ("Glow.plugin/Contents/Resources/Presets/", Read::Directory),
+            ("Glow.plugin/Contents/Resources/Shared", link(&long_target)),
+        ]
+        .into_iter()
+        .map(|(name, read)| (name.to_string(), read))
+        .chain([
+            (
+                format!("Glow.plugin/Contents/Resources/{long_name}"),
+                file(0o600, b"preset"),
+            ),
+            (
+                format!("Glow.plugin/Contents/Resources/{}/", "Localized".repeat(8)),
+                Read::Directory,
+            ),
+            (
+                format!("Glow.plugin/Contents/Resources/{split_name}"),
+                file(0o644, b"png"),
+            ),
+        ])
+        .collect();
+        (bundle, expected)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn zips_bundles_with_their_modes_and_symlinks() {
+        use std::io::Read as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (bundle, expected) = bundle(dir.path());
+        let mut archive = Archive::new();
+        archive.add_top_level(&bundle).unwrap();
+        let zip = archive.zip().unwrap();
+
+        let mut reader = zip::ZipArchive::new(std::io::Cursor::new(&zip)).unwrap();
+        let mut read = std::collections::BTreeMap::new();
+        for index in 0..reader.len() {
+            // Reading to the end checks the CRC-32
+            let mut file = reader.by_index(index).unwrap();
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).unwrap();
+            let date = file.last_modified().unwrap();
+            assert_eq!((date.year(), date.month(), date.day()), (1980, 1, 1));
+            let mode = file.unix_mode().unwrap();
+            let entry = match mode & S_IFMT {
+                S_IFDIR => Read::Directory,
+                S_IFLNK => Read::Symlink(String::from_utf8(data).unwrap()),
+                S_IFREG => Read::File {
+                    mode: mode & 0o7777,
+                    data,
+                },
+                _ => panic!("{} has mode {mode:o}", file.name()),
+            };
+            assert_eq!(file.is_dir(), entry == Read::Directory, "{}", file.name());
+            assert_eq!(file.is_symlink(), matches!(entry, Read::Symlink(_)));
+            if file.name().ends_with("/MacOS/Glow") {
+                assert_eq!(file.compression(), zip::CompressionMethod::Deflated);
+            }
+            read.insert(file.name().to_string(), entry);
+        }
+        assert_eq!(read, expected);
+        assert_eq!(archive.zip().unwrap(), zip);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tars_bundles_with_their_modes_and_symlinks() {
+        use std::io::Read as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (bundle, expected) = bundle(dir.path());
+        let mut archive = Archive::new();
+        archive.add_top_level(&bundle).unwrap();
+        let tar_gz = archive.tar_gz().unwrap();
+
+        let mut tar = Vec::new();
+        flate2::read::GzDecoder::new(&tar_gz[..])
+            .read_to_end(&mut tar)
+            .unwrap();
+        // The long name and the long link target only fit in pax headers
+        assert!(tar.windows(6).any(|window| window == b" path="));
+        assert!(tar.windows(10).any(|window| window == b" linkpath="));
+
+        let mut reader = tar::Archive::new(&tar[..]);
+        let mut read = std::collections::BTreeMap::new();
+        for entry in reader.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_str().unwrap().to_string();
+            let header = entry.header();
+            assert_eq!(header.mtime().unwrap(), TAR_MTIME);
+            let mode = header.mode().unwrap();
+            let read_entry = match header.entry_type() {
+                tar::EntryType::Directory => {
+                    assert_eq!(mode, 0o755);
+                    Read::Directory
+                }
+                tar::EntryType::Symlink => {
+                    let target = entry.link_name().unwrap().unwrap();
+                    Read::Symlink(target.to_str().unwrap().to_string())
+                }
+                tar::EntryType::Regular => {
+                    let mut data = Vec::new();
+                    entry.read_to_end(&mut data).unwrap();
+                    Read::File { mode, data }
+                }
+                other => panic!("{name} is a {other:?}"),
+            };
+            read.insert(name, read_entry);
+        }
+        assert_eq!(read, expected);
+    }
+}
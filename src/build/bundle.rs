@@ -0,0 +1,293 @@
+//! macOS `.plugin` bundle layout, independent of the host.
+//!
+//! Building and signing the binary need Apple's tools, but laying out the bundle does not: given
+//! a Mach-O executable, the PiPL resource fork and the plugin manifest, [`assemble`] writes
+//! `Contents/MacOS`, `Contents/Resources`, `PkgInfo` and `Info.plist` on any OS, and [`verify`]
+//! checks an existing bundle has all of them.
+
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use plist::Value;
+
+use crate::{PluginManifest, build::info_plist::info_plist, error::JkError, pipl};
+
+/// `PkgInfo` of an After Effects plugin: package type `eFKT`, creator `FXTC`.
+const PKG_INFO: &str = "eFKTFXTC";
+
+/// Magic numbers of 64-bit Mach-O files (as stored, little endian) and fat binaries.
+const MACH_O_MAGIC: [[u8; 4]; 2] = [[0xcf, 0xfa, 0xed, 0xfe], [0xca, 0xfe, 0xba, 0xbe]];
+
+/// The already built parts of a bundle.
+#[derive(Debug, Clone)]
+pub struct BundleContents {
+    /// Mach-O binary, thin or universal; becomes `Contents/MacOS/<plugin_name>`
+    pub executable: PathBuf,
+    /// PiPL resource fork; becomes `Contents/Resources/<plugin_name>.rsrc`
+    pub rsrc: PathBuf,
+    /// Files and directories copied into `Contents/Resources`
+    pub resources: Vec<PathBuf>,
+}
+
+impl BundleContents {
+    /// The executable and resource fork, with the resources the manifest lists for macOS.
+    pub fn new(manifest: &PluginManifest, executable: PathBuf, rsrc: PathBuf) -> Self {
+        BundleContents {
+            executable,
+            rsrc,
+            resources: manifest
+                .resources
+                .iter()
+                .chain(&manifest.macos.resources)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Paths of an assembled bundle.
+#[derive(Debug, Clone)]
+pub struct MacBundle {
+    /// The `.plugin` directory
+    pub path: PathBuf,
+    /// `Contents/MacOS/<plugin_name>`
+    pub executable: PathBuf,
+}
+
+/// Writes `<out_dir>/<plugin_name>.plugin` from `contents`, replacing any previous bundle.
+pub fn assemble(
+    out_dir: &Path,
+    manifest: &PluginManifest,
+    contents: &BundleContents,
+) -> Result<MacBundle, JkError> {
+    let plugin_name = &manifest.plugin_name;
+    // Not `with_extension`, which would cut a name like `Glow 1.5` at its dot
+    let path = out_dir.join(format!("{plugin_name}.plugin"));
+    if path.exists() {
+        std::fs::remove_dir_all(&path).map_err(JkError::bundle(&path))?;
+    }
+
+    let resources = path.join("Contents/Resources");
+    let macos = path.join("Contents/MacOS");
+    std::fs::create_dir_all(&resources).map_err(JkError::bundle(&resources))?;
+    std::fs::create_dir_all(&macos).map_err(JkError::bundle(&macos))?;
+
+    let pkg_info = path.join("Contents/PkgInfo");
+    std::fs::write(&pkg_info, PKG_INFO).map_err(JkError::bundle(&pkg_info))?;
+
+    let info_plist_path = path.join("Contents/Info.plist");
+    Value::Dictionary(info_plist(manifest)?)
+        .to_file_xml(&info_plist_path)
+        .map_err(|e| JkError::Bundle {
+            path: info_plist_path.clone(),
+            source: std::io::Error::other(e),
+        })?;
+
+    let rsrc = resources.join(format!("{plugin_name}.rsrc"));
+    std::fs::copy(&contents.rsrc, &rsrc).map_err(JkError::bundle(&rsrc))?;
+
+    let executable = macos.join(plugin_name);
+    std::fs::copy(&contents.executable, &executable).map_err(JkError::bundle(&executable))?;
+    // A merged universal binary, or one built elsewhere, may not have the executable bit
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755))
+            .map_err(JkError::bundle(&executable))?;
+    }
+
+    super::copy_resources(&contents.resources, &resources)?;
+    Ok(MacBundle { path, executable })
+}
+
+/// Checks that `path` is a complete plugin bundle: `PkgInfo`, an `Info.plist` naming a Mach-O
+/// executable in `Contents/MacOS`, and a PiPL resource in `Contents/Resources`.
+pub fn verify(path: &Path) -> Result<MacBundle, JkError> {
+    let invalid = |message: String| JkError::InvalidBundle {
+        path: path.to_path_buf(),
+        message,
+    };
+
+    let pkg_info = std::fs::read_to_string(path.join("Contents/PkgInfo"))
+        .map_err(|e| invalid(format!("cannot read Contents/PkgInfo: {e}")))?;
+    if pkg_info != PKG_INFO {
+        return Err(invalid(format!(
+            "Contents/PkgInfo is {pkg_info:?}, expected {PKG_INFO:?}"
+        )));
+    }
+
+    let info_plist = Value::from_file(path.join("Contents/Info.plist"))
+        .map_err(|e| invalid(format!("cannot read Contents/Info.plist: {e}")))?;
+    let string = |key: &str| {
+        info_plist
+            .as_dictionary()
+            .and_then(|dict| dict.get(key))
+            .and_then(Value::as_string)
+            .ok_or_else(|| invalid(format!("Contents/Info.plist has no {key}")))
+    };
+    for key in ["CFBundleIdentifier", "CFBundleShortVersionString"] {
+        string(key)?;
+    }
+    let package_type = string("CFBundlePackageType")?;
+    if package_type != "eFKT" {
+        return Err(invalid(format!(
+            "CFBundlePackageType is {package_type:?}, expected \"eFKT\""
+        )));
+    }
+
+    let executable = path
+        .join("Contents/MacOS")
+        .join(string("CFBundleExecutable")?);
+    let mut magic = [0; 4];
+    std::fs::File::open(&executable)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .map_err(|e| invalid(format!("cannot read {}: {e}", executable.display())))?;
+    if !MACH_O_MAGIC.contains(&magic) {
+        return Err(invalid(format!(
+            "{} is not a 64-bit Mach-O file",
+            executable.display()
+        )));
+    }
+
+    pipl::read_plugin(path).map_err(|e| invalid(e.to_string()))?;
+    Ok(MacBundle {
+        path: path.to_path_buf(),
+        executable,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build::info_plist::tests::manifest,
+        package::MacOsMetadata,
+        pipl::{ByteOrder, Pipl, RESOURCE_ID, rsrc},
+    };
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/fixtures/macho/{name}"))
+    }
+
+    /// Writes a resource fork holding an empty PiPL, and a resource directory, into `dir`.
+    fn contents(dir: &Path) -> BundleContents {
+        let rsrc = dir.join("Glow.rsrc");
+        let pipl = Pipl {
+            properties: Vec::new(),
+        }
+        .encode(ByteOrder::Big);
+        std::fs::write(
+            &rsrc,
+            rsrc::write_resource_fork(*b"PiPL", RESOURCE_ID, &pipl),
+        )
+        .unwrap();
+        let presets = dir.join("Presets");
+        std::fs::create_dir(&presets).unwrap();
+        std::fs::write(presets.join("Soft.ffx"), "preset").unwrap();
+        BundleContents {
+            executable: fixture("libmachot.dylib"),
+            rsrc,
+            resources: vec![presets],
+        }
+    }
+
+    #[test]
+    fn assembles_a_bundle_that_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest(MacOsMetadata::default());
+        let bundle = assemble(dir.path(), &manifest, &contents(dir.path())).unwrap();
+
+        let path = dir.path().join("Glow.plugin");
+        assert_eq!(bundle.path, path);
+        assert_eq!(bundle.executable, path.join("Contents/MacOS/Glow"));
+        assert_eq!(
+            std::fs::read(&bundle.executable).unwrap(),
+            std::fs::read(fixture("libmachot.dylib")).unwrap()
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = bundle.executable.metadata().unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+        assert_eq!(
+            std::fs::read_to_string(path.join("Contents/PkgInfo")).unwrap(),
+            "eFKTFXTC"
+        );
+        let info_plist = Value::from_file(path.join("Contents/Info.plist")).unwrap();
+        assert_eq!(
+            info_plist.into_dictionary(),
+            Some(crate::build::info_plist::info_plist(&manifest).unwrap())
+        );
+        assert!(path.join("Contents/Resources/Glow.rsrc").is_file());
+        assert_eq!(
+            std::fs::read_to_string(path.join("Contents/Resources/Presets/Soft.ffx")).unwrap(),
+            "preset"
+        );
+
+        let verified = verify(&path).unwrap();
+        assert_eq!(verified.executable, bundle.executable);
+    }
+
+    #[test]
+    fn replaces_a_previous_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let stale = dir.path().join("Glow.plugin/Contents/Resources/Stale.txt");
+        std::fs::create_dir_all(stale.parent().unwrap()).unwrap();
+        std::fs::write(&stale, "stale").unwrap();
+        let manifest = manifest(MacOsMetadata::default());
+        assemble(dir.path(), &manifest, &contents(dir.path())).unwrap();
+        assert!(!stale.exists());
+    }
+
+    fn rejection(path: &Path) -> String {
+        match verify(path) {
+            Err(JkError::InvalidBundle { message, .. }) => message,
+            other => panic!("expected InvalidBundle, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_incomplete_bundles() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest(MacOsMetadata::default());
+        let bundle = assemble(dir.path(), &manifest, &contents(dir.path())).unwrap();
+        let info_plist = bundle.path.join("Contents/Info.plist");
+
+        // An Info.plist naming another executable
+        let mut dict = crate::build::info_plist::info_plist(&manifest).unwrap();
+        dict.insert("CFBundleExecutable".to_string(), "Other".into());
+        Value::Dictionary(dict).to_file_xml(&info_plist).unwrap();
+        let message = rejection(&bundle.path);
+        assert!(message.starts_with("cannot read"), "{message}");
+        assert!(message.contains("Contents/MacOS/Other"), "{message}");
+
+        // An Info.plist without the key
+        let mut dict = crate::build::info_plist::info_plist(&manifest).unwrap();
+        dict.remove("CFBundleExecutable");
+        Value::Dictionary(dict).to_file_xml(&info_plist).unwrap();
+        assert_eq!(
+            rejection(&bundle.path),
+            "Contents/Info.plist has no CFBundleExecutable"
+        );
+
+        // The named executable missing
+        Value::Dictionary(crate::build::info_plist::info_plist(&manifest).unwrap())
+            .to_file_xml(&info_plist)
+            .unwrap();
+        std::fs::remove_file(&bundle.executable).unwrap();
+        assert!(rejection(&bundle.path).contains("Contents/MacOS/Glow"));
+
+        // Not a Mach-O file
+        std::fs::write(&bundle.executable, "#!/bin/sh\n").unwrap();
+        assert!(rejection(&bundle.path).ends_with("is not a 64-bit Mach-O file"));
+
+        // No PkgInfo
+        std::fs::copy(fixture("libmachot.dylib"), &bundle.executable).unwrap();
+        verify(&bundle.path).unwrap();
+        std::fs::remove_file(bundle.path.join("Contents/PkgInfo")).unwrap();
+        assert!(rejection(&bundle.path).starts_with("cannot read Contents/PkgInfo"));
+    }
+}
@@ -0,0 +1,338 @@
+//! Universal (fat) Mach-O binaries, written without `lipo`.
+//!
+//! A fat binary is a big-endian header listing each architecture's cputype, cpusubtype, offset,
+//! size and alignment, followed by the thin Mach-O files themselves, each at an offset aligned
+//! to its page size.
+
+use std::path::Path;
+
+use crate::error::JkError;
+
+/// Magic of a fat binary, stored big endian.
+const FAT_MAGIC: u32 = 0xcafe_babe;
+/// Magic of a 64-bit Mach-O file, stored in the file's (little endian) byte order.
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+
+const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+
+/// Size of `fat_header` and of each `fat_arch`.
+const FAT_HEADER_SIZE: usize = 8;
+const FAT_ARCH_SIZE: usize = 20;
+
+/// Largest slice alignment lipo accepts (`MAXSECTALIGN`), as a power of two.
+const MAX_ALIGN: u32 = 15;
+
+/// CPU type and slice alignment (a power of two, as lipo picks it) of an Apple target triple.
+fn cpu_type(target: &str) -> Option<(u32, u32)> {
+    match target.split('-').next()? {
+        "x86_64" => Some((CPU_TYPE_X86_64, 12)),
+        "aarch64" => Some((CPU_TYPE_ARM64, 14)),
+        _ => None,
+    }
+}
+
+/// A thin Mach-O file to put in a fat binary.
+#[derive(Debug, Clone, Copy)]
+pub struct Slice<'a> {
+    pub path: &'a Path,
+    /// Target triple the file must have been built for
+    pub target: &'a str,
+}
+
+/// One architecture of a fat binary.
+#[derive(Debug, Clone)]
+pub(crate) struct ThinSlice {
+    pub cpu_type: u32,
+    pub cpu_subtype: u32,
+    /// Alignment of the slice in the fat file, as a power of two
+    pub align: u32,
+    pub data: Vec<u8>,
+}
+
+/// Merges `slices` into the fat binary `output`, in the given order.
+///
+/// Each input must be a thin 64-bit Mach-O file of its target's architecture; a fat file, a
+/// 32-bit one or one built for another architecture is rejected instead of being merged.
+pub fn create(slices: &[Slice], output: &Path) -> Result<(), JkError> {
+    let mut thin: Vec<ThinSlice> = Vec::with_capacity(slices.len());
+    for slice in slices {
+        let invalid = |message: String| JkError::InvalidMachO {
+            path: slice.path.to_path_buf(),
+            message,
+        };
+        let (expected, align) = cpu_type(slice.target).ok_or_else(|| {
+            invalid(format!(
+                "{} is not an architecture universal binaries support",
+                slice.target
+            ))
+        })?;
+        let data = std::fs::read(slice.path).map_err(|e| invalid(e.to_string()))?;
+        let (cpu_type, cpu_subtype) = thin_header(&data).map_err(invalid)?;
+        if cpu_type != expected {
+            return Err(invalid(format!(
+                "it was built for {}, not {}",
+                architecture_name(cpu_type),
+                super::architecture(slice.target)
+            )));
+        }
+        if thin.iter().any(|other| other.cpu_type == cpu_type) {
+            return Err(invalid(format!(
+                "another slice is already {}",
+                architecture_name(cpu_type)
+            )));
+        }
+        thin.push(ThinSlice {
+            cpu_type,
+            cpu_subtype,
+            align,
+            data,
+        });
+    }
+
+    let fat = write(&thin).map_err(|message| JkError::InvalidMachO {
+        path: output.to_path_buf(),
+        message,
+    })?;
+    std::fs::write(output, fat).map_err(JkError::bundle(output))
+}
+
+/// Splits a fat binary into its slices, or returns `None` if `data` is not one.
+pub(crate) fn read(data: &[u8]) -> Option<Result<Vec<ThinSlice>, String>> {
+    let word = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| "the fat header is truncated".to_string())
+    };
+    if word(0).ok()? != FAT_MAGIC {
+        return None;
+    }
+    let slices = (|| {
+        let count = word(4)? as usize;
+        (0..count)
+            .map(|i| {
+                let arch = FAT_HEADER_SIZE + i * FAT_ARCH_SIZE;
+                let (offset, size) = (word(arch + 8)? as usize, word(arch + 12)? as usize);
+                let data = data
+                    .get(offset..offset + size)
+                    .ok_or_else(|| format!("slice {i} is outside the file"))?;
+                let align = word(arch + 16)?;
+                if align > MAX_ALIGN {
+                    return Err(format!(
+                        "slice {i} is aligned to 2^{align} bytes, more than 2^{MAX_ALIGN}"
+                    ));
+                }
+                Ok(ThinSlice {
+                    cpu_type: word(arch)?,
+                    cpu_subtype: word(arch + 4)?,
+                    align,
+                    data: data.to_vec(),
+                })
+            })
+            .collect()
+    })();
+    Some(slices)
+}
+
+/// Lays out `slices` as a fat binary, each at an offset aligned as it asks.
+pub(crate) fn write(slices: &[ThinSlice]) -> Result<Vec<u8>, String> {
+    let too_large = |_| "the slices do not fit in a 32-bit fat binary".to_string();
+    let mut fat = Vec::new();
+    fat.extend(FAT_MAGIC.to_be_bytes());
+    fat.extend((slices.len() as u32).to_be_bytes());
+    let mut offset = FAT_HEADER_SIZE + FAT_ARCH_SIZE * slices.len();
+    let mut offsets = Vec::with_capacity(slices.len());
+    for slice in slices {
+        offset = offset.next_multiple_of(1 << slice.align);
+        offsets.push(offset);
+        fat.extend(slice.cpu_type.to_be_bytes());
+        fat.extend(slice.cpu_subtype.to_be_bytes());
+        fat.extend(u32::try_from(offset).map_err(too_large)?.to_be_bytes());
+        fat.extend(
+            u32::try_from(slice.data.len())
+                .map_err(too_large)?
+                .to_be_bytes(),
+        );
+        fat.extend(slice.align.to_be_bytes());
+        offset += slice.data.len();
+    }
+
+    for (slice, offset) in slices.iter().zip(offsets) {
+        fat.resize(offset, 0);
+        fat.extend_from_slice(&slice.data);
+    }
+    Ok(fat)
+}
+
+/// CPU type and subtype of a thin 64-bit little-endian Mach-O file.
+fn thin_header(data: &[u8]) -> Result<(u32, u32), String> {
+    let word = |index: usize| {
+        data.get(index * 4..index * 4 + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| "the file is too short for a Mach-O header".to_string())
+    };
+    match word(0)? {
+        MH_MAGIC_64 => Ok((word(1)?, word(2)?)),
+        magic if magic.swap_bytes() == FAT_MAGIC => {
+            Err("it is already a universal binary, not a single architecture".to_string())
+        }
+        0xfeed_face => Err("it is a 32-bit Mach-O file".to_string()),
+        _ => Err("it is not a Mach-O file".to_string()),
+    }
+}
+
+pub(crate) fn architecture_name(cpu_type: u32) -> String {
+    match cpu_type {
+        CPU_TYPE_X86_64 => "x86_64".to_string(),
+        CPU_TYPE_ARM64 => "arm64".to_string(),
+        other => format!("CPU type 0x{other:x}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/macho")
+            .join(name)
+    }
+
+    fn be(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Merges the x86_64 and arm64 fixtures into a fat file in `dir`.
+    fn merge(dir: &Path) -> Result<Vec<u8>, JkError> {
+        let (x86_64, arm64) = (fixture("x86_64.o"), fixture("arm64.o"));
+        let output = dir.join("universal");
+        create(
+            &[
+                Slice {
+                    path: &x86_64,
+                    target: "x86_64-apple-darwin",
+                },
+                Slice {
+                    path: &arm64,
+                    target: "aarch64-apple-darwin",
+                },
+            ],
+            &output,
+        )?;
+        Ok(std::fs::read(output).unwrap())
+    }
+
+    #[test]
+    fn merges_x86_64_and_arm64() {
+        let dir = tempfile::tempdir().unwrap();
+        let fat = merge(dir.path()).unwrap();
+        let x86_64 = std::fs::read(fixture("x86_64.o")).unwrap();
+        let arm64 = std::fs::read(fixture("arm64.o")).unwrap();
+
+        assert_eq!(be(&fat, 0), FAT_MAGIC);
+        assert_eq!(be(&fat, 4), 2);
+        // cputype, cpusubtype (CPU_SUBTYPE_X86_64_ALL, CPU_SUBTYPE_ARM64_ALL), offset, size, align
+        let x86_64_arch = [CPU_TYPE_X86_64, 3, 0x1000, x86_64.len() as u32, 12];
+        let arm64_arch = [CPU_TYPE_ARM64, 0, 0x4000, arm64.len() as u32, 14];
+        for (i, field) in x86_64_arch.into_iter().chain(arm64_arch).enumerate() {
+            assert_eq!(
+                be(&fat, FAT_HEADER_SIZE + i * 4),
+                field,
+                "fat_arch word {i}"
+            );
+        }
+        assert_eq!(&fat[0x1000..0x1000 + x86_64.len()], x86_64);
+        assert_eq!(&fat[0x4000..], arm64);
+        assert!(
+            fat[FAT_HEADER_SIZE + 2 * FAT_ARCH_SIZE..0x1000]
+                .iter()
+                .all(|&b| b == 0)
+        );
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let fat = merge(dir.path()).unwrap();
+        let slices = read(&fat).unwrap().unwrap();
+        assert_eq!(
+            slices
+                .iter()
+                .map(|slice| (slice.cpu_type, slice.cpu_subtype, slice.align))
+                .collect::<Vec<_>>(),
+            [(CPU_TYPE_X86_64, 3, 12), (CPU_TYPE_ARM64, 0, 14)]
+        );
+        assert_eq!(write(&slices).unwrap(), fat);
+        assert!(read(&std::fs::read(fixture("arm64.o")).unwrap()).is_none());
+    }
+
+    #[test]
+    fn rejects_alignments_lipo_would_not_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut fat = merge(dir.path()).unwrap();
+        let align = FAT_HEADER_SIZE + FAT_ARCH_SIZE + 16;
+        for (value, expected) in [
+            (15, None),
+            (16, Some("slice 1 is aligned to 2^16 bytes, more than 2^15")),
+            (64, Some("slice 1 is aligned to 2^64 bytes, more than 2^15")),
+        ] {
+            fat[align..align + 4].copy_from_slice(&u32::to_be_bytes(value));
+            match read(&fat).unwrap() {
+                Ok(slices) => {
+                    assert_eq!(expected, None);
+                    write(&slices).unwrap();
+                }
+                Err(message) => assert_eq!(Some(message.as_str()), expected),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_a_slice_of_the_wrong_architecture() {
+        let dir = tempfile::tempdir().unwrap();
+        let arm64 = fixture("arm64.o");
+        let error = create(
+            &[Slice {
+                path: &arm64,
+                target: "x86_64-apple-darwin",
+            }],
+            &dir.path().join("universal"),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(&error, JkError::InvalidMachO { message, .. } if message == "it was built for arm64, not x86_64"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn rejects_inputs_that_are_not_thin_64_bit() {
+        let dir = tempfile::tempdir().unwrap();
+        let universal = dir.path().join("input");
+        std::fs::write(&universal, merge(dir.path()).unwrap()).unwrap();
+        let i386 = fixture("i386.o");
+        for (path, expected) in [
+            (
+                &universal,
+                "it is already a universal binary, not a single architecture",
+            ),
+            (&i386, "it is a 32-bit Mach-O file"),
+        ] {
+            let error = create(
+                &[Slice {
+                    path,
+                    target: "x86_64-apple-darwin",
+                }],
+                &dir.path().join("output"),
+            )
+            .unwrap_err();
+            assert!(
+                matches!(&error, JkError::InvalidMachO { message, .. } if message == expected),
+                "{error}"
+            );
+        }
+    }
+}
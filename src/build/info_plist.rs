@@ -0,0 +1,360 @@
+//! The `Contents/Info.plist` of a macOS plugin bundle.
+//!
+//! The keys are generated from the plugin manifest, then `info_plist_template` and the
+//! `info_plist` table of `[package.metadata.jk_plugin.macos]` are merged over them, in that
+//! order. Neither may change `CFBundleExecutable` or `CFBundleIdentifier`, which the build and
+//! the signer rely on; they follow `plugin_name` and `identifier`.
+
+use std::{io::Cursor, path::Path};
+
+use plist::{Dictionary, Value};
+
+use crate::{PluginManifest, error::JkError};
+
+/// `LSMinimumSystemVersion` when neither the manifest nor `MACOSX_DEPLOYMENT_TARGET` sets one;
+/// the oldest macOS rustc targets on x86_64.
+const DEFAULT_MINIMUM_SYSTEM_VERSION: &str = "10.12";
+
+/// Keys that only the manifest sets, with the metadata key that sets them.
+const MANIFEST_KEYS: [(&str, &str); 2] = [
+    ("CFBundleExecutable", "plugin_name"),
+    ("CFBundleIdentifier", "identifier"),
+];
+
+/// Why `key` cannot be set to `value` over the generated keys, if it cannot.
+fn manifest_key_conflict(dict: &Dictionary, key: &str, value: &Value) -> Option<String> {
+    let (_, setting) = MANIFEST_KEYS.iter().find(|(name, _)| *name == key)?;
+    let generated = dict.get(key)?;
+    (generated != value).then(|| {
+        format!(
+            "`{key}` is set by `{setting}` and must stay `{}`",
+            generated.as_string().unwrap_or_default()
+        )
+    })
+}
+
+/// Builds the `Info.plist` of the plugin's bundle.
+pub fn info_plist(manifest: &PluginManifest) -> Result<Dictionary, JkError> {
+    let version = &manifest.version;
+    let short_version = format!("{}.{}.{}", version.major, version.minor, version.patch);
+    let minimum_system_version = manifest
+        .macos
+        .minimum_system_version
+        .clone()
+        .or_else(|| std::env::var("MACOSX_DEPLOYMENT_TARGET").ok())
+        .unwrap_or_else(|| DEFAULT_MINIMUM_SYSTEM_VERSION.to_string());
+
+    let mut dict = Dictionary::new();
+    let mut insert = |key: &str, value: &str| {
+        dict.insert(key.to_string(), Value::String(value.to_string()));
+    };
+    insert("CFBundleInfoDictionaryVersion", "6.0");
+    insert("CFBundlePackageType", "eFKT");
+    insert("CFBundleSignature", "FXTC");
+    insert("CFBundleIdentifier", &manifest.identifier);
+    insert("CFBundleExecutable", &manifest.plugin_name);
+    insert("CFBundleName", &manifest.plugin_name);
+    insert("CFBundleDisplayName", &manifest.display_name);
+    insert("CFBundleShortVersionString", &short_version);
+    insert("CFBundleVersion", &version.to_string());
+    insert("LSMinimumSystemVersion", &minimum_system_version);
+    if let Some(copyright) = &manifest.copyright {
+        insert("NSHumanReadableCopyright", copyright);
+    }
+
+    if let Some(path) = &manifest.macos.info_plist_template {
+        let template = read_template(path)?;
+        let variables = [
+            ("EXECUTABLE_NAME", manifest.plugin_name.as_str()),
+            ("PRODUCT_NAME", &manifest.plugin_name),
+            ("PRODUCT_BUNDLE_IDENTIFIER", &manifest.identifier),
+            ("MARKETING_VERSION", &short_version),
+            ("CURRENT_PROJECT_VERSION", &version.to_string()),
+            ("MACOSX_DEPLOYMENT_TARGET", &minimum_system_version),
+        ];
+        for (key, mut value) in template {
+            substitute(&mut value, &variables);
+            if let Some(message) = manifest_key_conflict(&dict, &key, &value) {
+                return Err(JkError::InfoPlistTemplate {
+                    path: path.clone(),
+                    message,
+                });
+            }
+            dict.insert(key, value);
+        }
+    }
+
+    for (key, value) in &manifest.macos.info_plist {
+        let value = to_plist(value).ok_or_else(|| JkError::InvalidManifest {
+            package: manifest.package.clone(),
+            message: format!("`macos.info_plist.{key}` cannot be stored in an Info.plist"),
+        })?;
+        if let Some(message) = manifest_key_conflict(&dict, key, &value) {
+            return Err(JkError::InvalidManifest {
+                package: manifest.package.clone(),
+                message: format!("`macos.info_plist.{key}`: {message}"),
+            });
+        }
+        dict.insert(key.clone(), value);
+    }
+    Ok(dict)
+}
+
+/// Reads an `Info.plist` template, XML or binary.
+fn read_template(path: &Path) -> Result<Dictionary, JkError> {
+    let invalid = |message: String| JkError::InfoPlistTemplate {
+        path: path.to_path_buf(),
+        message,
+    };
+    let data = std::fs::read(path).map_err(|e| invalid(e.to_string()))?;
+    match Value::from_reader(Cursor::new(data)).map_err(|e| invalid(e.to_string()))? {
+        Value::Dictionary(dict) => Ok(dict),
+        _ => Err(invalid("the top level is not a dictionary".to_string())),
+    }
+}
+
+/// Expands the Xcode build settings `$(NAME)` and `${NAME}` that templates copied from Xcode
+/// projects use. Unknown settings are left alone.
+fn substitute(value: &mut Value, variables: &[(&str, &str)]) {
+    match value {
+        Value::String(string) => {
+            for (name, replacement) in variables {
+                for pattern in [format!("$({name})"), format!("${{{name}}}")] {
+                    *string = string.replace(&pattern, replacement);
+                }
+            }
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| substitute(value, variables)),
+        Value::Dictionary(dict) => dict
+            .values_mut()
+            .for_each(|value| substitute(value, variables)),
+        _ => {}
+    }
+}
+
+/// Converts a TOML value, as cargo metadata reports it, to a plist value.
+fn to_plist(value: &serde_json::Value) -> Option<Value> {
+    Some(match value {
+        serde_json::Value::Null => return None,
+        serde_json::Value::Bool(b) => Value::Boolean(*b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i.into()),
+            None => Value::Real(n.as_f64()?),
+        },
+        serde_json::Value::String(s) => Value::String(s.clone()),
+        serde_json::Value::Array(values) => {
+            Value::Array(values.iter().map(to_plist).collect::<Option<_>>()?)
+        }
+        serde_json::Value::Object(table) => Value::Dictionary(
+            table
+                .iter()
+                .map(|(key, value)| Some((key.clone(), to_plist(value)?)))
+                .collect::<Option<_>>()?,
+        ),
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use cargo_metadata::semver::Version;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        package::{MacOsMetadata, WindowsMetadata},
+        pipl::PluginKind,
+    };
+
+    /// The manifest of a plugin `Glow`, with the given macOS settings.
+    pub(crate) fn manifest(macos: MacOsMetadata) -> PluginManifest {
+        PluginManifest {
+            package: "glow".to_string(),
+            manifest_path: "/work/glow/Cargo.toml".into(),
+            plugin_name: "Glow".to_string(),
+            identifier: "com.example.glow".to_string(),
+            version: Version::parse("1.2.3-beta.1").unwrap(),
+            display_name: "Soft Glow".to_string(),
+            category: None,
+            match_name: "Glow".to_string(),
+            kind: PluginKind::Effect,
+            min_host_version: None,
+            copyright: Some("(c) Example".to_string()),
+            description: None,
+            authors: Vec::new(),
+            resources: Vec::new(),
+            documents: Vec::new(),
+            aesdk_root: None,
+            pipl: None,
+            macos: MacOsMetadata {
+                minimum_system_version: Some("11.0".to_string()),
+                ..macos
+            },
+            windows: WindowsMetadata::default(),
+        }
+    }
+
+    fn string<'a>(dict: &'a Dictionary, key: &str) -> &'a str {
+        dict.get(key).and_then(Value::as_string).unwrap()
+    }
+
+    /// Writes `plist` as the template of a manifest.
+    fn with_template(dir: &Path, plist: &str) -> PluginManifest {
+        let path = dir.join("Info.plist");
+        std::fs::write(&path, plist).unwrap();
+        manifest(MacOsMetadata {
+            info_plist_template: Some(path),
+            ..MacOsMetadata::default()
+        })
+    }
+
+    fn template(entries: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+{entries}
+</dict>
+</plist>"#
+        )
+    }
+
+    #[test]
+    fn generates_the_keys_from_the_manifest() {
+        let dict = info_plist(&manifest(MacOsMetadata::default())).unwrap();
+        for (key, value) in [
+            ("CFBundleInfoDictionaryVersion", "6.0"),
+            ("CFBundlePackageType", "eFKT"),
+            ("CFBundleSignature", "FXTC"),
+            ("CFBundleIdentifier", "com.example.glow"),
+            ("CFBundleExecutable", "Glow"),
+            ("CFBundleName", "Glow"),
+            ("CFBundleDisplayName", "Soft Glow"),
+            ("CFBundleShortVersionString", "1.2.3"),
+            ("CFBundleVersion", "1.2.3-beta.1"),
+            ("LSMinimumSystemVersion", "11.0"),
+            ("NSHumanReadableCopyright", "(c) Example"),
+        ] {
+            assert_eq!(string(&dict, key), value, "{key}");
+        }
+        assert_eq!(dict.len(), 11);
+    }
+
+    #[test]
+    fn merges_the_template_with_build_settings_expanded() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = with_template(
+            dir.path(),
+            &template(
+                "<key>CFBundleExecutable</key><string>$(EXECUTABLE_NAME)</string>
+<key>CFBundleIdentifier</key><string>${PRODUCT_BUNDLE_IDENTIFIER}</string>
+<key>CFBundleDisplayName</key><string>$(PRODUCT_NAME) $(MARKETING_VERSION)</string>
+<key>GlowBuild</key><array><string>$(CURRENT_PROJECT_VERSION) on $(MACOSX_DEPLOYMENT_TARGET)</string></array>
+<key>GlowOther</key><dict><key>Setting</key><string>$(SDKROOT)</string></dict>",
+            ),
+        );
+        let dict = info_plist(&manifest).unwrap();
+        assert_eq!(string(&dict, "CFBundleExecutable"), "Glow");
+        assert_eq!(string(&dict, "CFBundleIdentifier"), "com.example.glow");
+        assert_eq!(string(&dict, "CFBundleDisplayName"), "Glow 1.2.3");
+        assert_eq!(
+            dict.get("GlowBuild").unwrap(),
+            &Value::Array(vec!["1.2.3-beta.1 on 11.0".into()])
+        );
+        // Settings cargo-jk does not know are left alone
+        let other = dict
+            .get("GlowOther")
+            .and_then(Value::as_dictionary)
+            .unwrap();
+        assert_eq!(string(other, "Setting"), "$(SDKROOT)");
+        assert_eq!(string(&dict, "CFBundlePackageType"), "eFKT");
+    }
+
+    #[test]
+    fn merges_extra_keys_over_the_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = with_template(
+            dir.path(),
+            &template("<key>CFBundleDisplayName</key><string>Template</string>"),
+        );
+        let extra = json!({
+            "CFBundleDisplayName": "Extra",
+            "NSHighResolutionCapable": true,
+            "GlowScale": 1.5,
+            "GlowLevels": [1, 2],
+            "GlowOptions": { "Name": "glow" },
+        });
+        manifest.macos.info_plist = extra.as_object().unwrap().clone();
+        let dict = info_plist(&manifest).unwrap();
+        assert_eq!(string(&dict, "CFBundleDisplayName"), "Extra");
+        assert_eq!(
+            dict.get("NSHighResolutionCapable"),
+            Some(&Value::Boolean(true))
+        );
+        assert_eq!(dict.get("GlowScale"), Some(&Value::Real(1.5)));
+        assert_eq!(
+            dict.get("GlowLevels"),
+            Some(&Value::Array(vec![1.into(), 2.into()]))
+        );
+        let options = dict.get("GlowOptions").and_then(Value::as_dictionary);
+        assert_eq!(string(options.unwrap(), "Name"), "glow");
+    }
+
+    #[test]
+    fn keeps_the_executable_and_identifier_of_the_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        for (key, value) in [
+            ("CFBundleExecutable", "Other"),
+            ("CFBundleIdentifier", "com.example.other"),
+        ] {
+            let template = with_template(
+                dir.path(),
+                &template(&format!("<key>{key}</key><string>{value}</string>")),
+            );
+            match info_plist(&template) {
+                Err(JkError::InfoPlistTemplate { message, .. }) => {
+                    assert!(
+                        message.starts_with(&format!("`{key}` is set by")),
+                        "{message}"
+                    )
+                }
+                other => panic!("expected InfoPlistTemplate, got {other:?}"),
+            }
+
+            let mut manifest = manifest(MacOsMetadata::default());
+            manifest.macos.info_plist = json!({ key: value }).as_object().unwrap().clone();
+            match info_plist(&manifest) {
+                Err(JkError::InvalidManifest { message, .. }) => {
+                    assert!(
+                        message.starts_with(&format!("`macos.info_plist.{key}`")),
+                        "{message}"
+                    )
+                }
+                other => panic!("expected InvalidManifest, got {other:?}"),
+            }
+        }
+
+        // Setting them to the generated values is harmless
+        let mut manifest = manifest(MacOsMetadata::default());
+        manifest.macos.info_plist = json!({ "CFBundleExecutable": "Glow" })
+            .as_object()
+            .unwrap()
+            .clone();
+        assert!(info_plist(&manifest).is_ok());
+    }
+
+    #[test]
+    fn rejects_values_a_plist_cannot_hold() {
+        let mut manifest = manifest(MacOsMetadata::default());
+        manifest.macos.info_plist = json!({ "GlowLevels": [1, null] })
+            .as_object()
+            .unwrap()
+            .clone();
+        assert!(matches!(
+            info_plist(&manifest),
+            Err(JkError::InvalidManifest { message, .. }) if message.contains("GlowLevels")
+        ));
+    }
+}
@@ -1,12 +1,119 @@
-#[cfg(target_os = "windows")]
-#[path = "windows.rs"]
-mod os_post_build_process;
+//! Turning the built cdylib into a plugin, for the platform it was built for rather than the
+//! host, so Windows plugins can be cross-compiled and macOS bundles assembled anywhere.
 
-#[cfg(target_os = "macos")]
-#[path = "macos.rs"]
-mod os_post_build_process;
+mod macos;
+mod windows;
 
-#[cfg(not(any(target_os = "windows", target_os = "macos")))]
-compile_error!("mv_platform: unsupported operating system");
+pub mod bundle;
+pub mod fat;
+pub mod info_plist;
+pub mod symbols;
 
-pub use os_post_build_process::*;
+use std::path::{Path, PathBuf};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    BuildOptions, PiplResources, PluginManifest, build::symbols::Symbols, error::JkError,
+    pipl::Platform,
+};
+
+/// What the platform post-build step produced.
+#[derive(Debug, Clone)]
+pub struct PluginBundle {
+    /// The `.plugin` bundle or `.aex` file
+    pub path: PathBuf,
+    /// The plugin binary itself (the bundle executable, or the `.aex` file)
+    pub executable: PathBuf,
+    /// The cdylib cargo built, or the universal library merged from several
+    pub cdylib: PathBuf,
+    /// Target triples the binary was built for
+    pub targets: Vec<String>,
+    /// How the plugin was code signed
+    pub signing: SigningStatus,
+    /// Debug symbols, when asked for with `--symbols`
+    pub symbols: Option<Symbols>,
+}
+
+/// Whether and how a plugin was code signed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SigningStatus {
+    /// Whether the plugin carries a code signature
+    pub signed: bool,
+    /// Backend that signed the plugin (`builtin`, `codesign`, `signtool`, ...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// Signing identity (`-` for ad-hoc signatures)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// Whether the signature was checked after signing
+    #[serde(default)]
+    pub verified: bool,
+}
+
+/// Turns the cdylibs built for each target into the plugin: a `.plugin` bundle on macOS, with
+/// the binaries of several architectures merged into a universal one, or an `.aex` on Windows.
+pub fn post_build_process(
+    options: &BuildOptions,
+    target_dir: &Path,
+    cdylibs: &[(String, PathBuf)],
+    manifest: &PluginManifest,
+    pipl: Option<&PiplResources>,
+) -> Result<PluginBundle, JkError> {
+    let (target, filename) = cdylibs.first().expect("at least one target is built");
+    match Platform::from_target(target) {
+        Some(Platform::MacOs) => {
+            macos::post_build_process(options, target_dir, cdylibs, manifest, pipl)
+        }
+        Some(Platform::Windows) => {
+            windows::post_build_process(options, target_dir, filename, manifest, target)
+        }
+        None => Err(JkError::UnsupportedTarget(target.to_string())),
+    }
+}
+
+/// Directory finished plugins are staged in, apart from cargo's own outputs so that copying and
+/// signing them never touches a file cargo fingerprints: `<target-dir>/jk/<profile>/<target>`.
+///
+/// The profile directory is named like cargo's (`debug` for `dev`), and universal macOS plugins
+/// use the target `universal-apple-darwin`.
+pub fn dist_dir(target_dir: &Path, profile_dir: &str, target: &str) -> PathBuf {
+    target_dir.join("jk").join(profile_dir).join(target)
+}
+
+/// Architecture name of a target triple, as Apple and Adobe spell it.
+pub fn architecture(target: &str) -> String {
+    match target.split('-').next().unwrap_or(target) {
+        "aarch64" => "arm64".to_string(),
+        "x86_64" => "x86_64".to_string(),
+        arch => arch.to_string(),
+    }
+}
+
+/// Copies the manifest's extra resources (files or whole directories) into `destination`.
+pub fn copy_resources<'a>(
+    resources: impl IntoIterator<Item = &'a PathBuf>,
+    destination: &Path,
+) -> Result<(), JkError> {
+    for resource in resources {
+        let name = resource
+            .file_name()
+            .ok_or_else(|| JkError::InvalidArtifactPath(resource.clone()))?;
+        copy_recursively(resource, &destination.join(name))?;
+    }
+    Ok(())
+}
+
+fn copy_recursively(from: &Path, to: &Path) -> Result<(), JkError> {
+    if from.is_dir() {
+        std::fs::create_dir_all(to).map_err(JkError::bundle(to))?;
+        for entry in std::fs::read_dir(from).map_err(JkError::bundle(from))? {
+            let entry = entry.map_err(JkError::bundle(from))?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        std::fs::copy(from, to).map_err(JkError::bundle(to))?;
+    }
+    Ok(())
+}
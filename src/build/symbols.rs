@@ -0,0 +1,684 @@
+//! Debug symbols of built plugins, collected with `--symbols`.
+//!
+//! macOS plugins get a `.dSYM` bundle made by `dsymutil` from the bundle executable, holding every
+//! architecture of a universal binary. Windows plugins get the PDB cargo wrote, named after the
+//! `.aex`; the PDB name recorded in the `.aex` is rewritten to match, since debuggers look the PDB
+//! up by that name before matching it by GUID and age.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use clap::ValueEnum;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{EventHandler, EventKind, archive::Archive, build::fat, error::JkError};
+
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const LC_UUID: u32 = 0x1b;
+const MACH_HEADER_SIZE: usize = 32;
+
+const PE_SIGNATURE: &[u8] = b"PE\0\0";
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+/// Offset of `CheckSum` in the optional header, the same for PE32 and PE32+
+const CHECKSUM_OFFSET: usize = 64;
+/// Index of the debug directory among the data directories
+const DEBUG_DIRECTORY: usize = 6;
+const DEBUG_DIRECTORY_ENTRY_SIZE: usize = 28;
+const IMAGE_DEBUG_TYPE_CODEVIEW: u32 = 2;
+const SECTION_HEADER_SIZE: usize = 40;
+/// `RSDS`, a PDB 7.0 CodeView record: signature, GUID and age, then the PDB name
+const CODEVIEW_PDB70: &[u8] = b"RSDS";
+const CODEVIEW_HEADER_SIZE: usize = 24;
+
+/// Name of the archive `--symbols=zip` writes next to the plugins.
+pub const SYMBOLS_ARCHIVE: &str = "symbols.zip";
+
+/// What `--symbols` collects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SymbolsMode {
+    /// The `.dSYM` bundle or `.pdb` file, next to the plugin
+    Files,
+    /// The same, also packed into `symbols.zip`
+    Zip,
+}
+
+/// Debug symbols collected for a plugin.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Symbols {
+    /// The `.dSYM` bundle or `.pdb` file
+    pub path: PathBuf,
+    /// IDs crash reporters match the symbols to the plugin binary by, one per architecture
+    pub ids: Vec<DebugId>,
+    /// `symbols.zip` the symbols were packed into
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive: Option<PathBuf>,
+}
+
+/// The ID of one architecture's debug information.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct DebugId {
+    /// Architecture (`x86_64`, `arm64`)
+    pub architecture: String,
+    /// Mach-O UUID or PDB GUID, e.g. `4C4C44A4-5555-3144-A189-3536119FAE8C`
+    pub id: String,
+    /// Age of the PDB, which symbol servers append to the GUID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age: Option<u32>,
+}
+
+/// Runs `dsymutil` on the executable of `bundle`, writing `<bundle>.dSYM` next to it.
+///
+/// For a universal executable, dsymutil would merge the architectures with `lipo`, so it is run
+/// for one architecture at a time and the DWARF files are merged here instead.
+pub fn dsym(
+    bundle: &Path,
+    executable: &Path,
+    events: &EventHandler,
+    package: &str,
+) -> Result<Symbols, JkError> {
+    let mut name = bundle
+        .file_name()
+        .ok_or_else(|| JkError::InvalidArtifactPath(bundle.to_path_buf()))?
+        .to_os_string();
+    name.push(".dSYM");
+    let dsym = bundle.with_file_name(name);
+    // dsymutil would otherwise leave the DWARF of a previous executable name behind
+    if dsym.exists() {
+        std::fs::remove_dir_all(&dsym).map_err(JkError::bundle(&dsym))?;
+    }
+    let error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |message: String| JkError::DebugSymbols { path, message }
+    };
+    let data = std::fs::read(executable).map_err(|e| error(executable)(e.to_string()))?;
+    let expected = macho_ids(&data).map_err(error(executable))?;
+    let dwarf =
+        Path::new("Contents/Resources/DWARF").join(executable.file_name().unwrap_or_default());
+
+    match fat::read(&data) {
+        Some(slices) => {
+            let mut parts = Vec::new();
+            let mut merged = Vec::new();
+            for slice in slices.map_err(error(executable))? {
+                let architecture = fat::architecture_name(slice.cpu_type);
+                let part = dsym.with_extension(format!("{architecture}.dSYM"));
+                dsymutil(executable, Some(&architecture), &part, events, package)?;
+                let part_dwarf = part.join(&dwarf);
+                let data =
+                    std::fs::read(&part_dwarf).map_err(|e| error(&part_dwarf)(e.to_string()))?;
+                merged.push(fat::ThinSlice { data, ..slice });
+                parts.push(part);
+            }
+            let fat = fat::write(&merged).map_err(error(&dsym))?;
+            // The first architecture's dSYM becomes the merged one
+            for (index, part) in parts.iter().enumerate() {
+                match index {
+                    0 => std::fs::rename(part, &dsym).map_err(JkError::bundle(&dsym))?,
+                    _ => std::fs::remove_dir_all(part).map_err(JkError::bundle(part))?,
+                }
+            }
+            std::fs::write(dsym.join(&dwarf), fat).map_err(JkError::bundle(&dsym))?;
+        }
+        None => dsymutil(executable, None, &dsym, events, package)?,
+    }
+
+    // The UUIDs are read back from the dSYM, so one that does not belong to the binary fails here
+    let ids = std::fs::read(dsym.join(&dwarf))
+        .map_err(|e| e.to_string())
+        .and_then(|data| macho_ids(&data))
+        .map_err(error(&dsym))?;
+    if ids != expected {
+        return Err(error(&dsym)(
+            "its UUIDs do not match the plugin binary".to_string(),
+        ));
+    }
+    events.emit(EventKind::SymbolsCollected {
+        package: package.to_string(),
+        path: dsym.clone(),
+    });
+    Ok(Symbols {
+        path: dsym,
+        ids,
+        archive: None,
+    })
+}
+
+fn dsymutil(
+    executable: &Path,
+    architecture: Option<&str>,
+    output: &Path,
+    events: &EventHandler,
+    package: &str,
+) -> Result<(), JkError> {
+    let mut command = Command::new("dsymutil");
+    if let Some(architecture) = architecture {
+        command.arg("--arch").arg(architecture);
+    }
+    command.arg(executable).arg("-o").arg(output);
+    events.emit(EventKind::ToolStarted {
+        package: package.to_string(),
+        command: format!("{command:?}"),
+    });
+    let status = command.status().map_err(JkError::spawn(&command))?;
+    if !status.success() {
+        return Err(JkError::ToolFailed {
+            tool: "dsymutil".to_string(),
+            status,
+        });
+    }
+    Ok(())
+}
+
+/// Copies the PDB cargo wrote for `dll` next to `aex`, named after it, and points the CodeView
+/// record of `aex` at the new name. Must run before `aex` is signed.
+///
+/// The name is written over the old one in place, so a plugin name longer than the crate name
+/// keeps the PDB's original name instead.
+pub fn pdb(
+    dll: &Path,
+    aex: &Path,
+    target: &str,
+    events: &EventHandler,
+    package: &str,
+) -> Result<Symbols, JkError> {
+    let source = dll.with_extension("pdb");
+    if !source.is_file() {
+        return Err(JkError::DebugSymbols {
+            path: source,
+            message: "cargo did not write a PDB next to the DLL".to_string(),
+        });
+    }
+    let error = |message: String| JkError::DebugSymbols {
+        path: aex.to_path_buf(),
+        message,
+    };
+    let mut data = std::fs::read(aex).map_err(|e| error(e.to_string()))?;
+    let record = codeview(&data).map_err(error)?;
+
+    let renamed = aex.with_extension("pdb");
+    let renamed = renamed.file_name().unwrap_or_default().to_string_lossy();
+    let name = if renamed.len() < record.capacity {
+        let name = &mut data[record.name..record.name + record.capacity];
+        name.fill(0);
+        name[..renamed.len()].copy_from_slice(renamed.as_bytes());
+        if let Some(checksum) = record.checksum {
+            let sum = pe_checksum(&data, checksum);
+            data[checksum..checksum + 4].copy_from_slice(&sum.to_le_bytes());
+        }
+        std::fs::write(aex, &data).map_err(|e| error(e.to_string()))?;
+        renamed.into_owned()
+    } else {
+        // The recorded name may still be a full path from the machine that linked it
+        let original = record
+            .pdb_name
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or_default();
+        events.emit(EventKind::Warning {
+            package: package.to_string(),
+            message: format!(
+                "{renamed} does not fit in the CodeView record of {}; the PDB keeps its name {original}",
+                aex.display()
+            ),
+            location: None,
+        });
+        original.to_string()
+    };
+    let destination = aex.with_file_name(name);
+    std::fs::copy(&source, &destination).map_err(JkError::bundle(&destination))?;
+    events.emit(EventKind::SymbolsCollected {
+        package: package.to_string(),
+        path: destination.clone(),
+    });
+
+    Ok(Symbols {
+        path: destination,
+        ids: vec![DebugId {
+            architecture: super::architecture(target),
+            id: guid_string(&record.guid),
+            age: Some(record.age),
+        }],
+        archive: None,
+    })
+}
+
+/// Formats a PDB GUID as debuggers show it: the first three fields are little-endian integers,
+/// the last eight bytes are written in order.
+fn guid_string(guid: &[u8; 16]) -> String {
+    let [a0, a1, a2, a3, b0, b1, c0, c1, d @ ..] = *guid;
+    let [d0, d1, e @ ..] = d;
+    format!(
+        "{:08X}-{:04X}-{:04X}-{}-{}",
+        u32::from_le_bytes([a0, a1, a2, a3]),
+        u16::from_le_bytes([b0, b1]),
+        u16::from_le_bytes([c0, c1]),
+        hex(&[d0, d1]),
+        hex(&e),
+    )
+}
+
+/// Packs the symbols into a `symbols.zip` in each directory they were collected in, replacing
+/// any previous one, and records the archive in them.
+pub fn pack<'a>(
+    symbols: impl IntoIterator<Item = &'a mut Symbols>,
+    events: &EventHandler,
+) -> Result<(), JkError> {
+    let mut archives: BTreeMap<PathBuf, Vec<&mut Symbols>> = BTreeMap::new();
+    for symbols in symbols {
+        let dir = symbols.path.parent().unwrap_or(Path::new("")).to_path_buf();
+        archives.entry(dir).or_default().push(symbols);
+    }
+    for (dir, symbols) in archives {
+        let path = dir.join(SYMBOLS_ARCHIVE);
+        let error = |message| JkError::DebugSymbols {
+            path: path.clone(),
+            message,
+        };
+        let mut zip = Archive::new();
+        for symbols in &symbols {
+            zip.add_top_level(&symbols.path).map_err(error)?;
+        }
+        let data = zip.zip().map_err(error)?;
+        std::fs::write(&path, data).map_err(JkError::bundle(&path))?;
+        events.emit(EventKind::SymbolsPacked { path: path.clone() });
+        for symbols in symbols {
+            symbols.archive = Some(path.clone());
+        }
+    }
+    Ok(())
+}
+
+/// The `LC_UUID` of every architecture of a thin or fat Mach-O file.
+fn macho_ids(data: &[u8]) -> Result<Vec<DebugId>, String> {
+    match fat::read(data) {
+        Some(slices) => slices?.iter().map(|slice| macho_id(&slice.data)).collect(),
+        None => macho_id(data).map(|id| vec![id]),
+    }
+}
+
+fn macho_id(data: &[u8]) -> Result<DebugId, String> {
+    let u32_at = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or("its load commands are truncated")
+    };
+    if data.len() < MACH_HEADER_SIZE || u32_at(0)? != MH_MAGIC_64 {
+        return Err("it is not a 64-bit Mach-O file".to_string());
+    }
+    let mut offset = MACH_HEADER_SIZE;
+    for _ in 0..u32_at(16)? {
+        if u32_at(offset)? == LC_UUID {
+            let uuid = data
+                .get(offset + 8..offset + 24)
+                .ok_or("its LC_UUID is truncated")?;
+            return Ok(DebugId {
+                architecture: fat::architecture_name(u32_at(4)?),
+                id: format!(
+                    "{}-{}-{}-{}-{}",
+                    hex(&uuid[0..4]),
+                    hex(&uuid[4..6]),
+                    hex(&uuid[6..8]),
+                    hex(&uuid[8..10]),
+                    hex(&uuid[10..16]),
+                ),
+                age: None,
+            });
+        }
+        offset += (u32_at(offset + 4)? as usize).max(8);
+    }
+    Err("it has no LC_UUID".to_string())
+}
+
+/// Where the PDB 7.0 CodeView record of a PE file is, and what it says.
+struct CodeView {
+    guid: [u8; 16],
+    age: u32,
+    pdb_name: String,
+    /// File offset of the PDB name
+    name: usize,
+    /// Bytes available for the PDB name, including its NUL
+    capacity: usize,
+    /// File offset of the PE checksum, if the file has one that must be kept up to date
+    checksum: Option<usize>,
+}
+
+fn codeview(data: &[u8]) -> Result<CodeView, String> {
+    let u16_at = |offset: usize| {
+        data.get(offset..offset + 2)
+            .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or("it is truncated")
+    };
+    let u32_at = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or("it is truncated")
+    };
+    if !data.starts_with(b"MZ") {
+        return Err("it is not a PE file".to_string());
+    }
+    let pe = u32_at(0x3c)? as usize;
+    if data.get(pe..pe + 4) != Some(PE_SIGNATURE) {
+        return Err("it is not a PE file".to_string());
+    }
+    let coff = pe + 4;
+    let sections = u16_at(coff + 2)? as usize;
+    let optional_header = coff + 20;
+    let section_table = optional_header + u16_at(coff + 16)? as usize;
+    let (directory_count, directories) = match u16_at(optional_header)? {
+        PE32_MAGIC => (optional_header + 92, optional_header + 96),
+        PE32_PLUS_MAGIC => (optional_header + 108, optional_header + 112),
+        magic => {
+            return Err(format!(
+                "it has an unknown optional header magic {magic:#x}"
+            ));
+        }
+    };
+    if (u32_at(directory_count)? as usize) <= DEBUG_DIRECTORY {
+        return Err("it has no debug directory".to_string());
+    }
+    let entry = directories + DEBUG_DIRECTORY * 8;
+    let (rva, size) = (u32_at(entry)?, u32_at(entry + 4)? as usize);
+
+    // The directory is given by its virtual address; find the section it is loaded from
+    let debug_directory = (0..sections)
+        .map(|index| section_table + index * SECTION_HEADER_SIZE)
+        .find_map(|header| {
+            let virtual_size = u32_at(header + 8).ok()?;
+            let virtual_address = u32_at(header + 12).ok()?;
+            let raw_size = u32_at(header + 16).ok()?;
+            let raw_pointer = u32_at(header + 20).ok()?;
+            let offset = rva
+                .checked_sub(virtual_address)
+                .filter(|&offset| offset < virtual_size.max(raw_size))?;
+            Some(
+                raw_pointer
+                    .checked_add(offset)
+                    .map(|pointer| pointer as usize)
+                    .ok_or("its debug directory is past the end of the file"),
+            )
+        })
+        .filter(|_| rva != 0)
+        .ok_or("it has no debug directory")??;
+
+    for entry in (0..size / DEBUG_DIRECTORY_ENTRY_SIZE)
+        .map(|index| debug_directory + index * DEBUG_DIRECTORY_ENTRY_SIZE)
+    {
+        if u32_at(entry + 12)? != IMAGE_DEBUG_TYPE_CODEVIEW {
+            continue;
+        }
+        let (record_size, record) = (u32_at(entry + 16)? as usize, u32_at(entry + 24)? as usize);
+        let record_data = data
+            .get(record..record + record_size)
+            .ok_or("its CodeView record is outside the file")?;
+        let Some((header, name)) = record_data.split_first_chunk::<CODEVIEW_HEADER_SIZE>() else {
+            continue;
+        };
+        if name.is_empty() || !header.starts_with(CODEVIEW_PDB70) {
+            continue;
+        }
+        let [_, _, _, _, guid @ .., a0, a1, a2, a3] = *header;
+        let checksum = optional_header + CHECKSUM_OFFSET;
+        return Ok(CodeView {
+            guid,
+            age: u32::from_le_bytes([a0, a1, a2, a3]),
+            pdb_name: String::from_utf8_lossy(name.split(|&b| b == 0).next().unwrap_or_default())
+                .into_owned(),
+            name: record + CODEVIEW_HEADER_SIZE,
+            capacity: name.len(),
+            checksum: (u32_at(checksum)? != 0).then_some(checksum),
+        });
+    }
+    Err("it has no PDB 7.0 CodeView record".to_string())
+}
+
+/// The PE image checksum: a folded 16-bit sum of the file, skipping the checksum itself, plus
+/// the file size.
+fn pe_checksum(data: &[u8], checksum: usize) -> u32 {
+    let mut sum = 0u32;
+    for (index, word) in data.chunks(2).enumerate() {
+        if (checksum..checksum + 4).contains(&(index * 2)) {
+            continue;
+        }
+        sum += u16::from_le_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32;
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    ((sum & 0xffff) + (sum >> 16)).wrapping_add(data.len() as u32)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(path: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(path)
+    }
+
+    /// Offset of the optional header checksum in the DLL fixture.
+    const FIXTURE_CHECKSUM: usize = 0xd0;
+
+    #[test]
+    fn reads_the_codeview_record() {
+        let data = std::fs::read(fixture("pe/machot.dll")).unwrap();
+        let record = codeview(&data).unwrap();
+        // As `llvm-readobj --coff-debug-directory` reports it
+        assert_eq!(
+            record.guid,
+            [
+                0x4d, 0xf5, 0xcc, 0xd5, 0x3b, 0xd8, 0xcd, 0xc0, 0x4c, 0x4c, 0x44, 0x20, 0x50, 0x44,
+                0x42, 0x2e
+            ]
+        );
+        assert_eq!(record.age, 1);
+        assert_eq!(record.pdb_name, "machot.pdb");
+        assert_eq!(
+            &data[record.name..record.name + record.capacity],
+            b"machot.pdb\0"
+        );
+        assert_eq!(record.checksum, Some(FIXTURE_CHECKSUM));
+        assert_eq!(
+            guid_string(&record.guid),
+            "D5CCF54D-D83B-C0CD-4C4C-44205044422E"
+        );
+    }
+
+    #[test]
+    fn checksums_like_the_linker() {
+        // Linked with `/RELEASE`, so lld wrote the checksum
+        let data = std::fs::read(fixture("pe/machot.dll")).unwrap();
+        let stored = u32::from_le_bytes(
+            data[FIXTURE_CHECKSUM..FIXTURE_CHECKSUM + 4]
+                .try_into()
+                .unwrap(),
+        );
+        assert_eq!(stored, 0x12dc);
+        assert_eq!(pe_checksum(&data, FIXTURE_CHECKSUM), stored);
+    }
+
+    #[test]
+    fn renames_the_pdb_after_the_aex() {
+        let dir = tempfile::tempdir().unwrap();
+        let (dll, aex) = (dir.path().join("machot.dll"), dir.path().join("Glow.aex"));
+        std::fs::copy(fixture("pe/machot.dll"), &dll).unwrap();
+        std::fs::copy(fixture("pe/machot.dll"), &aex).unwrap();
+        std::fs::write(dir.path().join("machot.pdb"), b"PDB").unwrap();
+
+        let symbols = pdb(
+            &dll,
+            &aex,
+            "x86_64-pc-windows-msvc",
+            &EventHandler::default(),
+            "machot",
+        )
+        .unwrap();
+        assert_eq!(symbols.path, dir.path().join("Glow.pdb"));
+        assert_eq!(std::fs::read(&symbols.path).unwrap(), b"PDB");
+        assert_eq!(
+            symbols.ids,
+            [DebugId {
+                architecture: "x86_64".to_string(),
+                id: "D5CCF54D-D83B-C0CD-4C4C-44205044422E".to_string(),
+                age: Some(1),
+            }]
+        );
+
+        let data = std::fs::read(&aex).unwrap();
+        let record = codeview(&data).unwrap();
+        assert_eq!(record.pdb_name, "Glow.pdb");
+        assert_eq!(
+            &data[record.name..record.name + record.capacity],
+            b"Glow.pdb\0\0\0"
+        );
+        // Computed independently over the patched file
+        assert_eq!(
+            &data[FIXTURE_CHECKSUM..FIXTURE_CHECKSUM + 4],
+            &0xb852u32.to_le_bytes()
+        );
+        // The DLL cargo fingerprints is left alone
+        assert_eq!(
+            std::fs::read(&dll).unwrap(),
+            std::fs::read(fixture("pe/machot.dll")).unwrap()
+        );
+    }
+
+    #[test]
+    fn keeps_the_pdb_name_when_the_new_one_does_not_fit() {
+        let dir = tempfile::tempdir().unwrap();
+        let (dll, aex) = (
+            dir.path().join("machot.dll"),
+            dir.path().join("Glow 1.5.aex"),
+        );
+        std::fs::copy(fixture("pe/machot.dll"), &dll).unwrap();
+        std::fs::copy(fixture("pe/machot.dll"), &aex).unwrap();
+        std::fs::write(dir.path().join("machot.pdb"), b"PDB").unwrap();
+
+        let symbols = pdb(
+            &dll,
+            &aex,
+            "x86_64-pc-windows-msvc",
+            &EventHandler::default(),
+            "machot",
+        )
+        .unwrap();
+        assert_eq!(symbols.path, dir.path().join("machot.pdb"));
+        assert_eq!(
+            std::fs::read(&aex).unwrap(),
+            std::fs::read(fixture("pe/machot.dll")).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_files_without_a_codeview_record() {
+        let data = std::fs::read(fixture("pe/machot.dll")).unwrap();
+        assert!(codeview(b"\x7fELF").is_err());
+        assert_eq!(
+            codeview(&data[..0x100]).err().as_deref(),
+            Some("it is truncated")
+        );
+
+        // Point the debug directory entry at a record that is not RSDS
+        let mut patched = data.clone();
+        let record = codeview(&data).unwrap().name - CODEVIEW_HEADER_SIZE;
+        patched[record..record + 4].copy_from_slice(b"NB10");
+        assert_eq!(
+            codeview(&patched).err().as_deref(),
+            Some("it has no PDB 7.0 CodeView record")
+        );
+    }
+
+    #[test]
+    fn survives_section_headers_past_the_address_space() {
+        let data = std::fs::read(fixture("pe/machot.dll")).unwrap();
+        let record = codeview(&data).unwrap();
+        // The `.text` section header, patched to claim addresses the debug directory is not at
+        let text = 0x180;
+        let patch = |virtual_address: u32, virtual_size: u32, raw_pointer: u32| {
+            let mut patched = data.clone();
+            for (offset, value) in [(8, virtual_size), (12, virtual_address), (20, raw_pointer)] {
+                patched[text + offset..text + offset + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            patched
+        };
+
+        let wrapping = patch(0xffff_ff00, 0x1000, 0x400);
+        assert_eq!(codeview(&wrapping).unwrap().name, record.name);
+
+        // Covering the debug directory at 0x2000, from a raw offset that overflows
+        let overflowing = patch(0x1ff0, 0x100, 0xffff_fff8);
+        assert_eq!(
+            codeview(&overflowing).err().as_deref(),
+            Some("its debug directory is past the end of the file")
+        );
+    }
+
+    /// Needs LLVM's `dsymutil`; run with `cargo test -- --ignored` where it is installed.
+    #[test]
+    #[ignore = "needs dsymutil"]
+    fn merges_the_dsym_of_every_architecture() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("Glow 1.5.plugin");
+        let executable = bundle.join("Contents/MacOS/Glow 1.5");
+        std::fs::create_dir_all(executable.parent().unwrap()).unwrap();
+        let (x86_64, arm64) = (
+            fixture("macho/libmachot.dylib"),
+            fixture("macho/libmachot.arm64.dylib"),
+        );
+        fat::create(
+            &[
+                fat::Slice {
+                    path: &x86_64,
+                    target: "x86_64-apple-darwin",
+                },
+                fat::Slice {
+                    path: &arm64,
+                    target: "aarch64-apple-darwin",
+                },
+            ],
+            &executable,
+        )
+        .unwrap();
+
+        let symbols = dsym(&bundle, &executable, &EventHandler::default(), "machot").unwrap();
+        assert_eq!(symbols.path, dir.path().join("Glow 1.5.plugin.dSYM"));
+        let ids = symbols
+            .ids
+            .iter()
+            .map(|id| (id.architecture.as_str(), id.id.as_str()))
+            .collect::<Vec<_>>();
+        // As `llvm-objdump --macho --private-headers` reports the fixtures' LC_UUIDs
+        assert_eq!(
+            ids,
+            [
+                ("x86_64", "4C4C44E6-5555-3144-A100-A01DA99D044D"),
+                ("arm64", "4C4C44A4-5555-3144-A189-3536119FAE8C"),
+            ]
+        );
+
+        let dwarf = std::fs::read(symbols.path.join("Contents/Resources/DWARF/Glow 1.5")).unwrap();
+        let slices = fat::read(&dwarf).unwrap().unwrap();
+        assert_eq!(
+            slices
+                .iter()
+                .map(|slice| fat::architecture_name(slice.cpu_type))
+                .collect::<Vec<_>>(),
+            ["x86_64", "arm64"]
+        );
+        // Only the merged dSYM is left
+        let mut entries = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, ["Glow 1.5.plugin", "Glow 1.5.plugin.dSYM"]);
+    }
+}
@@ -1,19 +1,60 @@
 use std::path::{Path, PathBuf};
 
-use crate::{command::Build, JkPluginMetadata};
+use crate::{
+    BuildOptions, EventKind, PluginManifest,
+    build::{PluginBundle, symbols},
+    error::JkError,
+    sign,
+};
 
 pub fn post_build_process<P: AsRef<Path>>(
-    _build: &Build,
+    options: &BuildOptions,
+    target_dir: &Path,
     filename: P,
-    _package_name: &str,
-    jk_plugin_metadata: &JkPluginMetadata,
-) -> PathBuf {
+    manifest: &PluginManifest,
+    target: &str,
+) -> Result<PluginBundle, JkError> {
     let dllfilepath = filename.as_ref().to_path_buf();
-    let dllfiledir = dllfilepath.parent().unwrap();
-    // rename the DLL file to the plugin name
-    let new_dll_path: PathBuf = dllfiledir.join(&jk_plugin_metadata.plugin_name).with_extension("aex");
-    std::fs::rename(dllfilepath, &new_dll_path).expect("Failed to rename DLL file");
-    eprintln!("Renamed DLL to: {}", new_dll_path.display());
+    let dist_dir = super::dist_dir(target_dir, options.cargo.profile_dir(), target);
+    std::fs::create_dir_all(&dist_dir).map_err(JkError::bundle(&dist_dir))?;
+    // Copied rather than renamed, so cargo still finds its DLL up to date; the copy is signed
+    let new_dll_path: PathBuf = dist_dir.join(format!("{}.aex", manifest.plugin_name));
+    std::fs::copy(&dllfilepath, &new_dll_path).map_err(JkError::bundle(&new_dll_path))?;
+    super::copy_resources(
+        manifest.resources.iter().chain(&manifest.windows.resources),
+        &dist_dir,
+    )?;
+    // The PDB name is patched into the .aex, which signing must come after
+    let symbols = match options.symbols {
+        Some(_) => Some(symbols::pdb(
+            &dllfilepath,
+            &new_dll_path,
+            target,
+            &options.events,
+            &manifest.package,
+        )?),
+        None => None,
+    };
+    options.events.emit(EventKind::BundleCreated {
+        package: manifest.package.clone(),
+        path: new_dll_path.clone(),
+    });
 
-    new_dll_path
+    let signing = sign::sign_windows_plugin(&options.config.sign.windows, &new_dll_path)?;
+    if signing.signed {
+        options.events.emit(EventKind::Signed {
+            package: manifest.package.clone(),
+            path: new_dll_path.clone(),
+            identity: signing.identity.clone().unwrap_or_default(),
+        });
+    }
+
+    Ok(PluginBundle {
+        path: new_dll_path.clone(),
+        executable: new_dll_path,
+        cdylib: dllfilepath,
+        targets: vec![target.to_string()],
+        signing,
+        symbols,
+    })
 }
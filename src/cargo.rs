@@ -0,0 +1,362 @@
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{EventHandler, EventKind, error::JkError};
+
+/// Runs `rustc` (or `$RUSTC`) with `args` and returns what it printed.
+fn rustc(args: &[&str]) -> Result<String, JkError> {
+    let mut command = Command::new(env::var_os("RUSTC").unwrap_or("rustc".into()));
+    command.args(args);
+    let output = command.output().map_err(JkError::spawn(&command))?;
+    if !output.status.success() {
+        return Err(JkError::ToolFailed {
+            tool: "rustc".to_string(),
+            status: output.status,
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Returns the target triple of the host, which cargo builds for when no `--target` is given.
+pub fn host_triple() -> Result<String, JkError> {
+    rustc(&["-vV"])?
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(|host| host.trim().to_string())
+        .ok_or_else(|| JkError::HostTriple("`rustc -vV` did not report a host".to_string()))
+}
+
+/// Installs the standard library of those `targets` the toolchain does not have yet.
+///
+/// The sysroot is looked at first, so `rustup` only runs (and only needs to exist) when a
+/// target is actually missing.
+pub fn ensure_targets(
+    targets: &[&str],
+    events: &EventHandler,
+    package: &str,
+) -> Result<(), JkError> {
+    let sysroot = PathBuf::from(rustc(&["--print", "sysroot"])?.trim());
+    let missing: Vec<_> = targets
+        .iter()
+        .filter(|target| {
+            !sysroot
+                .join("lib/rustlib")
+                .join(target)
+                .join("lib")
+                .is_dir()
+        })
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let mut command = Command::new("rustup");
+    command.arg("target").arg("add").args(missing);
+    events.emit(EventKind::ToolStarted {
+        package: package.to_string(),
+        command: format!("{command:?}"),
+    });
+    let status = command.status().map_err(JkError::spawn(&command))?;
+    if !status.success() {
+        return Err(JkError::ToolFailed {
+            tool: "rustup".to_string(),
+            status,
+        });
+    }
+    Ok(())
+}
+
+/// Options forwarded to every `cargo` invocation of the pipeline.
+#[derive(Debug, Clone, Default)]
+pub struct CargoOptions {
+    /// Build artifacts in release mode, with optimizations
+    pub release: bool,
+    /// Build artifacts with the specified profile
+    pub profile: Option<String>,
+    /// Space or comma separated list of features to activate
+    pub features: Vec<String>,
+    /// Activate all available features
+    pub all_features: bool,
+    /// Do not activate the `default` feature
+    pub no_default_features: bool,
+    /// Directory for all generated artifacts
+    pub target_dir: Option<PathBuf>,
+    /// Assert that `Cargo.lock` will remain unchanged
+    pub locked: bool,
+    /// Run without accessing the network
+    pub offline: bool,
+    /// Number of parallel jobs
+    pub jobs: Option<u32>,
+    /// Target triple to build for, instead of the host
+    pub target: Option<String>,
+    /// Extra arguments passed to rustc for the plugin crate
+    pub rustc_args: Vec<String>,
+}
+
+impl CargoOptions {
+    /// Name of the cargo profile the build uses.
+    pub fn profile(&self) -> &str {
+        match &self.profile {
+            Some(profile) => profile,
+            None if self.release => "release",
+            None => "dev",
+        }
+    }
+
+    /// Name of the directory under the target directory that cargo writes the profile to.
+    pub fn profile_dir(&self) -> &str {
+        match self.profile() {
+            "dev" | "test" => "debug",
+            "bench" => "release",
+            profile => profile,
+        }
+    }
+
+    /// The triple the plugin is built for: `target`, or else the host.
+    pub fn target_triple(&self) -> Result<String, JkError> {
+        match &self.target {
+            Some(target) => Ok(target.clone()),
+            None => host_triple(),
+        }
+    }
+
+    /// Whether the profile is an optimized (non-`dev`) one.
+    pub fn is_release(&self) -> bool {
+        !matches!(self.profile(), "dev" | "test")
+    }
+
+    /// Creates the `cargo` command that builds `package`, with JSON messages on stdout.
+    ///
+    /// Plain `cargo build` is used unless extra rustc arguments were given or there are files to
+    /// link, such as Windows resources, in which case `cargo rustc --lib` passes them to the
+    /// plugin crate only. With `diagnostics`, compiler diagnostics are included in the JSON
+    /// messages instead of being rendered by cargo.
+    pub fn command(
+        &self,
+        package: &str,
+        manifest_path: Option<&Path>,
+        target: Option<&str>,
+        link_args: &[PathBuf],
+        diagnostics: bool,
+    ) -> Command {
+        let mut command = Command::new("cargo");
+        if self.rustc_args.is_empty() && link_args.is_empty() {
+            command.arg("build");
+        } else {
+            command.arg("rustc").arg("--lib");
+        }
+        command.arg("--package").arg(package);
+        if let Some(manifest_path) = manifest_path {
+            command.arg("--manifest-path").arg(manifest_path);
+        }
+        if let Some(target) = target {
+            command.arg("--target").arg(target);
+        }
+        match &self.profile {
+            Some(profile) => {
+                command.arg("--profile").arg(profile);
+            }
+            None if self.release => {
+                command.arg("--release");
+            }
+            None => {}
+        }
+        for features in &self.features {
+            command.arg("--features").arg(features);
+        }
+        if self.all_features {
+            command.arg("--all-features");
+        }
+        if self.no_default_features {
+            command.arg("--no-default-features");
+        }
+        if let Some(target_dir) = &self.target_dir {
+            command.arg("--target-dir").arg(target_dir);
+        }
+        if self.locked {
+            command.arg("--locked");
+        }
+        if self.offline {
+            command.arg("--offline");
+        }
+        if let Some(jobs) = self.jobs {
+            command.arg("--jobs").arg(jobs.to_string());
+        }
+        command.arg("--message-format");
+        command.arg(if diagnostics {
+            "json"
+        } else {
+            "json-render-diagnostics"
+        });
+        if !self.rustc_args.is_empty() || !link_args.is_empty() {
+            command.arg("--").args(&self.rustc_args);
+            for arg in link_args {
+                let mut link_arg = OsString::from("link-arg=");
+                link_arg.push(arg);
+                command.arg("-C").arg(link_arg);
+            }
+        }
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn builds_the_package_with_json_messages() {
+        let command = CargoOptions::default().command("glow", None, None, &[], false);
+        assert_eq!(command.get_program(), "cargo");
+        assert_eq!(
+            args(&command),
+            [
+                "build",
+                "--package",
+                "glow",
+                "--message-format",
+                "json-render-diagnostics"
+            ]
+        );
+
+        let options = CargoOptions {
+            release: true,
+            target_dir: Some("/work/target".into()),
+            locked: true,
+            offline: true,
+            jobs: Some(4),
+            ..CargoOptions::default()
+        };
+        let command = options.command(
+            "glow",
+            Some(Path::new("plugins/glow/Cargo.toml")),
+            Some("x86_64-pc-windows-msvc"),
+            &[],
+            true,
+        );
+        assert_eq!(
+            args(&command),
+            [
+                "build",
+                "--package",
+                "glow",
+                "--manifest-path",
+                "plugins/glow/Cargo.toml",
+                "--target",
+                "x86_64-pc-windows-msvc",
+                "--release",
+                "--target-dir",
+                "/work/target",
+                "--locked",
+                "--offline",
+                "--jobs",
+                "4",
+                "--message-format",
+                "json"
+            ]
+        );
+    }
+
+    #[test]
+    fn forwards_the_profile_over_release() {
+        let options = CargoOptions {
+            release: true,
+            profile: Some("dist".to_string()),
+            ..CargoOptions::default()
+        };
+        assert_eq!(options.profile(), "dist");
+        assert_eq!(options.profile_dir(), "dist");
+        assert_eq!(
+            args(&options.command("glow", None, None, &[], false)),
+            [
+                "build",
+                "--package",
+                "glow",
+                "--profile",
+                "dist",
+                "--message-format",
+                "json-render-diagnostics"
+            ]
+        );
+    }
+
+    #[test]
+    fn forwards_features() {
+        let options = CargoOptions {
+            features: vec!["gpu".to_string(), "simd avx2".to_string()],
+            all_features: true,
+            no_default_features: true,
+            ..CargoOptions::default()
+        };
+        assert_eq!(
+            args(&options.command("glow", None, None, &[], false)),
+            [
+                "build",
+                "--package",
+                "glow",
+                "--features",
+                "gpu",
+                "--features",
+                "simd avx2",
+                "--all-features",
+                "--no-default-features",
+                "--message-format",
+                "json-render-diagnostics"
+            ]
+        );
+    }
+
+    #[test]
+    fn links_files_and_passes_rustc_args_to_the_library_only() {
+        let resources = [
+            PathBuf::from("/work/target/jk/pipl/glow/Glow.res"),
+            PathBuf::from("/work/target/jk/versioninfo/glow/Glow.version.res"),
+        ];
+        let command = CargoOptions::default().command("glow", None, None, &resources, false);
+        assert_eq!(
+            args(&command),
+            [
+                "rustc",
+                "--lib",
+                "--package",
+                "glow",
+                "--message-format",
+                "json-render-diagnostics",
+                "--",
+                "-C",
+                "link-arg=/work/target/jk/pipl/glow/Glow.res",
+                "-C",
+                "link-arg=/work/target/jk/versioninfo/glow/Glow.version.res"
+            ]
+        );
+
+        let options = CargoOptions {
+            rustc_args: vec!["-C".to_string(), "target-cpu=native".to_string()],
+            ..CargoOptions::default()
+        };
+        assert_eq!(
+            args(&options.command("glow", None, None, &[], false)),
+            [
+                "rustc",
+                "--lib",
+                "--package",
+                "glow",
+                "--message-format",
+                "json-render-diagnostics",
+                "--",
+                "-C",
+                "target-cpu=native"
+            ]
+        );
+    }
+}
@@ -0,0 +1,607 @@
+//! Validation of the plugin manifest, run by `cargo jk check-metadata` and before every build.
+//!
+//! Problems point at the offending line of `Cargo.toml`, so a typo in the metadata is reported
+//! there instead of surfacing as After Effects refusing to load the plugin.
+
+use std::{fmt, path::PathBuf};
+
+use cargo_metadata::Package;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use toml::de::{DeTable, DeValue};
+
+use crate::{
+    build::info_plist,
+    manifest::{self, PluginManifest},
+    package,
+    pipl::EffectVersion,
+};
+
+/// Longest match name, effect name or category After Effects accepts (`PF_MAX_EFFECT_NAME_LEN`).
+pub const MAX_NAME_LEN: usize = 31;
+
+/// Characters that cannot appear in a macOS bundle or Windows file name.
+const ILLEGAL_FILE_NAME_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+/// Names Windows reserves for devices, with or without an extension.
+const RESERVED_FILE_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A position in a `Cargo.toml`, 1-based.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Something wrong with a package's plugin metadata.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Problem {
+    pub severity: Severity,
+    pub package: String,
+    pub message: String,
+    pub location: Option<Location>,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{severity}: {}", self.message)?;
+        if let Some(location) = &self.location {
+            write!(
+                f,
+                "\n  --> {}:{}:{}",
+                location.path.display(),
+                location.line,
+                location.column
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the plugin metadata of `packages`. Identifiers and match names must also be unique
+/// among the plugin packages of `members`, the whole workspace.
+pub fn check_packages(packages: &[Package], members: &[Package]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut manifests = Vec::new();
+    for package in packages {
+        let source = Source::read(package);
+        if let Some(manifest) = check_package(package, &source, &mut problems) {
+            manifests.push((manifest, source));
+        }
+    }
+
+    let others: Vec<PluginManifest> = members
+        .iter()
+        .filter(|member| package::has_plugin_metadata(member))
+        .filter(|member| !packages.iter().any(|p| p.id == member.id))
+        .filter_map(|member| PluginManifest::resolve(member).ok())
+        .collect();
+    let all: Vec<&PluginManifest> = manifests.iter().map(|(m, _)| m).chain(&others).collect();
+    for (manifest, source) in &manifests {
+        let duplicate = |value: fn(&PluginManifest) -> &str| {
+            all.iter()
+                .filter(|other| other.package != manifest.package)
+                .find(|other| value(other) == value(manifest))
+                .map(|other| other.package.clone())
+        };
+        if let Some(other) = duplicate(|m| &m.identifier) {
+            problems.push(source.error(
+                format!(
+                    "identifier `{}` is also used by `{other}`",
+                    manifest.identifier
+                ),
+                &["identifier"],
+            ));
+        }
+        if let Some(other) = duplicate(|m| &m.match_name) {
+            problems.push(source.error(
+                format!(
+                    "match name `{}` is also used by `{other}`",
+                    manifest.match_name
+                ),
+                source.key_or(&["match_name"], &["plugin_name"]),
+            ));
+        }
+    }
+    problems
+}
+
+/// Checks one package, returning its manifest if it could be resolved.
+fn check_package(
+    package: &Package,
+    source: &Source,
+    problems: &mut Vec<Problem>,
+) -> Option<PluginManifest> {
+    let Some(metadata) = package.metadata.get("jk_plugin") else {
+        problems.push(source.error(
+            format!(
+                "no [package.metadata.jk_plugin] section in {}",
+                package.manifest_path
+            ),
+            &[],
+        ));
+        return None;
+    };
+
+    for unknown in manifest::unknown_keys(metadata, manifest::METADATA_KEYS, &[]) {
+        let mut message = format!("unused manifest key `jk_plugin.{}`", unknown.path.join("."));
+        if let Some(suggestion) = unknown.suggestion {
+            message.push_str(&format!("; did you mean `{suggestion}`?"));
+        }
+        let path: Vec<&str> = unknown.path.iter().map(String::as_str).collect();
+        problems.push(Problem {
+            severity: Severity::Warning,
+            ..source.key_error(message, &path)
+        });
+    }
+
+    let manifest = match PluginManifest::resolve(package) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            problems.push(source.error(e.to_string(), &[]));
+            return None;
+        }
+    };
+
+    if !is_reverse_dns(&manifest.identifier) {
+        problems.push(source.error(
+            format!(
+                "identifier `{}` is not a reverse-DNS name such as `com.example.plugin`",
+                manifest.identifier
+            ),
+            &["identifier"],
+        ));
+    }
+    if let Err(reason) = check_file_name(&manifest.plugin_name) {
+        problems.push(source.error(
+            format!(
+                "plugin_name `{}` cannot be used as a file name: {reason}",
+                manifest.plugin_name
+            ),
+            &["plugin_name"],
+        ));
+    }
+    if manifest.match_name.len() > MAX_NAME_LEN {
+        problems.push(source.error(
+            format!(
+                "match name `{}` is longer than After Effects' limit of {MAX_NAME_LEN} characters",
+                manifest.match_name
+            ),
+            source.key_or(&["match_name"], &["plugin_name"]),
+        ));
+    }
+    for (key, value) in [
+        ("display_name", Some(&manifest.display_name)),
+        ("category", manifest.category.as_ref()),
+    ] {
+        if let Some(value) = value.filter(|v| v.len() > MAX_NAME_LEN) {
+            problems.push(Problem {
+                severity: Severity::Warning,
+                ..source.error(
+                    format!(
+                        "{key} `{value}` is longer than {MAX_NAME_LEN} characters and will be truncated by After Effects"
+                    ),
+                    source.key_or(&[key], &["plugin_name"]),
+                )
+            });
+        }
+    }
+    if let Err(e) = info_plist::info_plist(&manifest) {
+        problems.push(source.error(
+            e.to_string(),
+            source.key_or(&["macos", "info_plist_template"], &["macos", "info_plist"]),
+        ));
+    }
+    if let Err(reason) = EffectVersion::from_version(&manifest.version).check() {
+        problems.push(source.error(
+            format!(
+                "plugin version {} does not fit the PiPL: {reason}",
+                manifest.version
+            ),
+            &["version"],
+        ));
+    }
+    Some(manifest)
+}
+
+/// Whether `identifier` looks like `com.example.plugin`.
+pub(crate) fn is_reverse_dns(identifier: &str) -> bool {
+    let mut labels = identifier.split('.');
+    labels.clone().count() >= 2
+        && labels.all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+pub(crate) fn check_file_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("it is empty".to_string());
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| ILLEGAL_FILE_NAME_CHARS.contains(c) || c.is_control())
+    {
+        return Err(format!("it contains {c:?}"));
+    }
+    if name.starts_with(' ') || name.ends_with(' ') || name.ends_with('.') {
+        return Err("it starts or ends with a space, or ends with a period".to_string());
+    }
+    let stem = name.split('.').next().unwrap_or(name);
+    if RESERVED_FILE_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        return Err(format!("`{stem}` is a reserved name on Windows"));
+    }
+    Ok(())
+}
+
+/// A package's `Cargo.toml`, for pointing problems at its lines.
+struct Source {
+    package: String,
+    path: PathBuf,
+    text: Option<String>,
+}
+
+impl Source {
+    fn read(package: &Package) -> Self {
+        let path: PathBuf = package.manifest_path.clone().into();
+        Source {
+            package: package.name.to_string(),
+            text: std::fs::read_to_string(&path).ok(),
+            path,
+        }
+    }
+
+    /// An error at the value of `key` under `[package.metadata.jk_plugin]`, or at the section
+    /// itself if the key is not there.
+    fn error(&self, message: String, key: &[&str]) -> Problem {
+        self.problem(message, key, false)
+    }
+
+    /// An error at the key `key` itself rather than its value.
+    fn key_error(&self, message: String, key: &[&str]) -> Problem {
+        self.problem(message, key, true)
+    }
+
+    fn problem(&self, message: String, key: &[&str], at_key: bool) -> Problem {
+        Problem {
+            severity: Severity::Error,
+            package: self.package.clone(),
+            message,
+            location: Some(self.locate(key, at_key).unwrap_or_else(|| Location {
+                path: self.path.clone(),
+                line: 1,
+                column: 1,
+            })),
+        }
+    }
+
+    /// `key` if the manifest sets it, else `fallback`, whose value the key defaults to.
+    fn key_or<'a>(&self, key: &'a [&'a str], fallback: &'a [&'a str]) -> &'a [&'a str] {
+        if self.locate(key, false).is_some() {
+            key
+        } else {
+            fallback
+        }
+    }
+
+    fn locate(&self, key: &[&str], at_key: bool) -> Option<Location> {
+        let text = self.text.as_deref()?;
+        let document = DeTable::parse(text).ok()?;
+        let mut span = document.span();
+        let mut table = Some(document.get_ref());
+        let names: Vec<&str> = ["package", "metadata", "jk_plugin"]
+            .into_iter()
+            .chain(key.iter().copied())
+            .collect();
+        for (i, name) in names.iter().enumerate() {
+            let (found_key, value) = table?.iter().find(|(k, _)| k.get_ref() == name)?;
+            span = if at_key && i == names.len() - 1 {
+                found_key.span()
+            } else {
+                value.span()
+            };
+            table = match value.get_ref() {
+                DeValue::Table(inner) => Some(inner),
+                _ => None,
+            };
+        }
+        let offset = span.start.min(text.len());
+        let before = &text[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        Some(Location {
+            path: self.path.clone(),
+            line,
+            column,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use cargo_metadata::MetadataCommand;
+
+    use super::*;
+
+    /// Line of `Cargo.toml` the first metadata line is on.
+    const METADATA_LINE: usize = 10;
+
+    /// A workspace of plugin packages, each with the given `[package.metadata.jk_plugin]` lines.
+    fn workspace(root: &Path, members: &[(&str, &str)]) -> Vec<Package> {
+        let names: Vec<String> = members
+            .iter()
+            .map(|(name, _)| format!("{name:?}"))
+            .collect();
+        std::fs::write(
+            root.join("Cargo.toml"),
+            format!(
+                "[workspace]\nmembers = [{}]\nresolver = \"2\"\n",
+                names.join(", ")
+            ),
+        )
+        .unwrap();
+        for (name, metadata) in members {
+            let dir = root.join(name);
+            std::fs::create_dir_all(dir.join("src")).unwrap();
+            std::fs::write(dir.join("src/lib.rs"), "").unwrap();
+            std::fs::write(
+                dir.join("Cargo.toml"),
+                format!(
+                    "[package]\nname = {name:?}\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+                     [lib]\ncrate-type = [\"cdylib\"]\n\n\
+                     [package.metadata.jk_plugin]\n{metadata}\n"
+                ),
+            )
+            .unwrap();
+        }
+        let mut packages = MetadataCommand::new()
+            .manifest_path(root.join("Cargo.toml"))
+            .no_deps()
+            .exec()
+            .unwrap()
+            .workspace_packages()
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        packages.sort_by_key(|package| {
+            members
+                .iter()
+                .position(|(name, _)| package.name.as_str() == *name)
+        });
+        packages
+    }
+
+    /// Checks the first package of a workspace of `members`.
+    fn check(members: &[(&str, &str)]) -> Vec<Problem> {
+        let root = tempfile::tempdir().unwrap();
+        let packages = workspace(root.path(), members);
+        check_packages(&packages[..1], &packages)
+    }
+
+    /// The severity, message and line of each problem.
+    fn summary(problems: &[Problem]) -> Vec<(Severity, String, usize)> {
+        problems
+            .iter()
+            .map(|problem| {
+                let location = problem.location.as_ref().unwrap();
+                (problem.severity, problem.message.clone(), location.line)
+            })
+            .collect()
+    }
+
+    const GLOW: &str = "plugin_name = \"Glow\"\nidentifier = \"com.example.glow\"";
+
+    #[test]
+    fn accepts_valid_metadata() {
+        assert_eq!(summary(&check(&[("glow", GLOW)])), []);
+    }
+
+    #[test]
+    fn limits_names_to_31_characters() {
+        let name = "A".repeat(MAX_NAME_LEN);
+        let metadata = format!("{GLOW}\nmatch_name = \"{name}\"\ncategory = \"{name}\"");
+        assert_eq!(summary(&check(&[("glow", &metadata)])), []);
+
+        let long = "A".repeat(MAX_NAME_LEN + 1);
+        let metadata = format!(
+            "{GLOW}\nmatch_name = \"{long}\"\ndisplay_name = \"{long}\"\ncategory = \"{long}\""
+        );
+        assert_eq!(
+            summary(&check(&[("glow", &metadata)])),
+            [
+                (
+                    Severity::Error,
+                    format!(
+                        "match name `{long}` is longer than After Effects' limit of 31 characters"
+                    ),
+                    METADATA_LINE + 2
+                ),
+                (
+                    Severity::Warning,
+                    format!(
+                        "display_name `{long}` is longer than 31 characters and will be truncated by After Effects"
+                    ),
+                    METADATA_LINE + 3
+                ),
+                (
+                    Severity::Warning,
+                    format!(
+                        "category `{long}` is longer than 31 characters and will be truncated by After Effects"
+                    ),
+                    METADATA_LINE + 4
+                ),
+            ]
+        );
+
+        // The match and display names default to the plugin name, so that is where they are
+        let metadata = format!("plugin_name = \"{long}\"\nidentifier = \"com.example.glow\"");
+        let problems = check(&[("glow", &metadata)]);
+        assert_eq!(
+            problems
+                .iter()
+                .map(|problem| {
+                    let location = problem.location.as_ref().unwrap();
+                    (problem.severity, location.line, location.column)
+                })
+                .collect::<Vec<_>>(),
+            [
+                (Severity::Error, METADATA_LINE, 15),
+                (Severity::Warning, METADATA_LINE, 15)
+            ]
+        );
+    }
+
+    #[test]
+    fn requires_reverse_dns_identifiers() {
+        for identifier in ["com.example.glow", "com.example-company.glow2", "io.glow"] {
+            assert!(is_reverse_dns(identifier), "{identifier}");
+        }
+        for identifier in [
+            "glow",
+            "com..glow",
+            "com.example.",
+            "com.-example.glow",
+            "com.example.glow_fx",
+            "com.example.glöw",
+        ] {
+            assert!(!is_reverse_dns(identifier), "{identifier}");
+        }
+
+        let problems = check(&[("glow", "plugin_name = \"Glow\"\nidentifier = \"glow\"")]);
+        assert_eq!(
+            summary(&problems),
+            [(
+                Severity::Error,
+                "identifier `glow` is not a reverse-DNS name such as `com.example.plugin`"
+                    .to_string(),
+                METADATA_LINE + 1
+            )]
+        );
+        let location = problems[0].location.as_ref().unwrap();
+        assert_eq!(location.column, 14);
+        assert!(location.path.ends_with("glow/Cargo.toml"));
+    }
+
+    #[test]
+    fn requires_plugin_names_that_are_file_names() {
+        for name in ["Glow", "Glow 2", "Glow.v2", "Consolation", "グロー"] {
+            assert_eq!(check_file_name(name), Ok(()), "{name}");
+        }
+        for (name, reason) in [
+            ("", "it is empty"),
+            ("  ", "it is empty"),
+            ("Glow/Blur", "it contains '/'"),
+            ("Glow: Pro", "it contains ':'"),
+            ("Glow?", "it contains '?'"),
+            ("Glow\t", "it contains '\\t'"),
+            (
+                " Glow",
+                "it starts or ends with a space, or ends with a period",
+            ),
+            (
+                "Glow.",
+                "it starts or ends with a space, or ends with a period",
+            ),
+            ("CON", "`CON` is a reserved name on Windows"),
+            ("com1.fx", "`com1` is a reserved name on Windows"),
+        ] {
+            assert_eq!(check_file_name(name), Err(reason.to_string()), "{name:?}");
+        }
+
+        let problems = check(&[(
+            "glow",
+            "plugin_name = \"Glow/Blur\"\nidentifier = \"com.example.glow\"\nmatch_name = \"Glow\"",
+        )]);
+        assert_eq!(
+            summary(&problems),
+            [(
+                Severity::Error,
+                "plugin_name `Glow/Blur` cannot be used as a file name: it contains '/'"
+                    .to_string(),
+                METADATA_LINE
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_identifiers_and_match_names_used_by_other_members() {
+        let problems = check(&[
+            ("glow", GLOW),
+            (
+                "glow2",
+                "plugin_name = \"Glow\"\nidentifier = \"com.example.glow\"",
+            ),
+        ]);
+        assert_eq!(
+            summary(&problems),
+            [
+                (
+                    Severity::Error,
+                    "identifier `com.example.glow` is also used by `glow2`".to_string(),
+                    METADATA_LINE + 1
+                ),
+                // Both match names default to the plugin name
+                (
+                    Severity::Error,
+                    "match name `Glow` is also used by `glow2`".to_string(),
+                    METADATA_LINE
+                ),
+            ]
+        );
+
+        let problems = check(&[
+            ("glow", &format!("{GLOW}\nmatch_name = \"Example Glow\"")),
+            (
+                "blur",
+                "plugin_name = \"Blur\"\nidentifier = \"com.example.blur\"\nmatch_name = \"Example Glow\"",
+            ),
+        ]);
+        assert_eq!(
+            summary(&problems),
+            [(
+                Severity::Error,
+                "match name `Example Glow` is also used by `blur`".to_string(),
+                METADATA_LINE + 2
+            )]
+        );
+    }
+
+    #[test]
+    fn warns_about_unknown_keys_at_the_key() {
+        let problems = check(&[(
+            "glow",
+            &format!("{GLOW}\n\n[package.metadata.jk_plugin.pipl]\n  out_flag2 = 0"),
+        )]);
+        assert_eq!(
+            summary(&problems),
+            [(
+                Severity::Warning,
+                "unused manifest key `jk_plugin.pipl.out_flag2`; did you mean `out_flags2`?"
+                    .to_string(),
+                METADATA_LINE + 4
+            )]
+        );
+        let location = problems[0].location.as_ref().unwrap();
+        assert_eq!(location.column, 3);
+    }
+}
@@ -1,6 +1,13 @@
+use std::path::PathBuf;
+
 use clap::{Args, Parser, Subcommand};
 
-/// Simple program to greet a person
+use crate::{
+    CargoOptions, PackageSelection, build::symbols::SymbolsMode, dist::ArchiveFormat,
+    package::MacArch, scaffold::Template,
+};
+
+/// Cargo plugin to build JK (Adobe After Effects) plugins
 #[derive(Parser, Debug)]
 #[command(name = "cargo", bin_name = "cargo")]
 pub enum Cargo {
@@ -14,7 +21,8 @@ pub struct Input {
     #[command(subcommand)]
     pub cmd: JKCommand,
 
-    #[arg(long, global = true)]
+    /// Override a cargo-jk configuration value (KEY=VALUE)
+    #[arg(long, global = true, value_name = "KEY=VALUE")]
     pub config: Vec<String>,
 }
 
@@ -26,36 +34,291 @@ pub enum JKCommand {
     MV(MV),
     /// Command to build and install a JK plugin
     Install(Install),
+    /// Print the JSON Schema of the `--format json` output
+    Schema(Schema),
+    /// Assemble a macOS plugin bundle from an already built binary, on any host
+    Bundle(Bundle),
+    /// Create a new plugin crate
+    New(New),
+    /// Make an existing crate a plugin
+    Init(Init),
+    /// Check the plugin metadata in Cargo.toml without building
+    CheckMetadata(CheckMetadata),
+    /// Notarize a signed macOS plugin bundle and staple the ticket to it
+    Notarize(Notarize),
+    /// Write distributable archives of built plugins
+    Package(Package),
+    /// Inspect PiPL resources
+    #[command(subcommand)]
+    Pipl(PiplCommand),
 }
 
 #[derive(Args, Debug)]
 pub struct Build {
-    #[arg(long, default_value = "none")]
-    pub format: Format,
+    /// Output format [default: none, or `format` from the cargo-jk config]
+    #[arg(long)]
+    pub format: Option<Format>,
+    #[command(flatten)]
+    pub cargo: CargoArgs,
+    #[command(flatten)]
+    pub packages: PackageArgs,
+}
+
+/// Options forwarded to `cargo build`
+#[derive(Args, Debug)]
+pub struct CargoArgs {
     /// Build artifacts in release mode, with optimizations
     #[arg(long, default_value_t = false)]
     pub release: bool,
+    /// Build artifacts with the specified profile
+    #[arg(long, value_name = "PROFILE-NAME", conflicts_with = "release")]
+    pub profile: Option<String>,
+    /// Space or comma separated list of features to activate
+    #[arg(short = 'F', long)]
+    pub features: Vec<String>,
+    /// Activate all available features
+    #[arg(long)]
+    pub all_features: bool,
+    /// Do not activate the `default` feature
+    #[arg(long)]
+    pub no_default_features: bool,
+    /// Directory for all generated artifacts
+    #[arg(long, value_name = "DIRECTORY")]
+    pub target_dir: Option<PathBuf>,
+    /// Assert that `Cargo.lock` will remain unchanged
+    #[arg(long)]
+    pub locked: bool,
+    /// Run without accessing the network
+    #[arg(long)]
+    pub offline: bool,
+    /// Number of parallel jobs
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<u32>,
+    /// Build for the target triple, e.g. x86_64-pc-windows-msvc
+    #[arg(long, value_name = "TRIPLE")]
+    pub target: Option<String>,
+    /// Architectures of a macOS plugin [default: `macos.arch` from the metadata]
+    #[arg(long, value_enum, conflicts_with = "target")]
+    pub arch: Option<MacArch>,
+    /// Collect debug symbols (.dSYM or .pdb) next to the plugin; `--symbols=zip` also packs them
+    /// into symbols.zip
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "files"
+    )]
+    pub symbols: Option<SymbolsMode>,
+    /// Extra arguments passed to rustc for the plugin crate
+    #[arg(last = true, value_name = "RUSTC_ARGS")]
+    pub rustc_args: Vec<String>,
+}
+
+impl From<CargoArgs> for CargoOptions {
+    fn from(args: CargoArgs) -> Self {
+        CargoOptions {
+            release: args.release,
+            profile: args.profile,
+            features: args.features,
+            all_features: args.all_features,
+            no_default_features: args.no_default_features,
+            target_dir: args.target_dir,
+            locked: args.locked,
+            offline: args.offline,
+            jobs: args.jobs,
+            target: args.target,
+            rustc_args: args.rustc_args,
+        }
+    }
+}
+
+/// Package selection, same as `cargo build`
+#[derive(Args, Debug)]
+pub struct PackageArgs {
+    /// Package to build (can be specified multiple times)
+    #[arg(short, long = "package", value_name = "SPEC")]
+    pub package: Vec<String>,
+    /// Build all workspace members that have [package.metadata.jk_plugin]
+    #[arg(long, conflicts_with = "package")]
+    pub workspace: bool,
+    /// Path to Cargo.toml
+    #[arg(long, value_name = "PATH")]
+    pub manifest_path: Option<PathBuf>,
+}
+
+impl From<PackageArgs> for PackageSelection {
+    fn from(args: PackageArgs) -> Self {
+        PackageSelection {
+            packages: args.package,
+            workspace: args.workspace,
+            manifest_path: args.manifest_path,
+        }
+    }
 }
 
 #[derive(Args, Debug)]
 pub struct MV {
     /// The source file to move
     pub src: String,
+    /// Directory to move the file into [default: Adobe's MediaCore plug-in directory]
+    #[arg(long, value_name = "DIRECTORY")]
+    pub destination: Option<PathBuf>,
 }
 
 #[derive(Args, Debug)]
 pub struct Install {
-    /// Build artifacts in release mode, with optimizations
-    #[arg(long, default_value_t = false)]
+    /// Output format [default: none, or `format` from the cargo-jk config]
+    #[arg(long)]
+    pub format: Option<Format>,
+    #[command(flatten)]
+    pub cargo: CargoArgs,
+    #[command(flatten)]
+    pub packages: PackageArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct Bundle {
+    /// Mach-O binary (thin or universal) built for the plugin
+    #[arg(long, value_name = "PATH")]
+    pub executable: PathBuf,
+    /// PiPL resource fork [default: generated from [package.metadata.jk_plugin.pipl]]
+    #[arg(long, value_name = "PATH")]
+    pub rsrc: Option<PathBuf>,
+    /// Directory to write the bundle to [default: <target-dir>/jk/bundle]
+    #[arg(long, value_name = "DIRECTORY")]
+    pub out_dir: Option<PathBuf>,
+    /// Package the binary was built from
+    #[arg(short, long = "package", value_name = "SPEC")]
+    pub package: Option<String>,
+    /// Path to Cargo.toml
+    #[arg(long, value_name = "PATH")]
+    pub manifest_path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct Notarize {
+    /// The signed .plugin bundle
+    pub plugin: PathBuf,
+    /// Wait for the verdict, but leave the bundle as it is
+    #[arg(long)]
+    pub no_staple: bool,
+    /// Output format; `json` prints the submission ID and status
+    #[arg(long)]
+    pub format: Option<Format>,
+}
+
+#[derive(Args, Debug)]
+pub struct Package {
+    /// Built .plugin bundles and .aex files [default: every plugin `cargo jk build` staged under
+    /// <target-dir>/jk/<profile>]
+    pub plugins: Vec<PathBuf>,
+    /// Package plugins built in release mode
+    #[arg(long)]
     pub release: bool,
+    /// Package plugins built with the specified profile
+    #[arg(long, value_name = "PROFILE-NAME", conflicts_with = "release")]
+    pub profile: Option<String>,
+    /// Directory the plugins were built in
+    #[arg(long, value_name = "DIRECTORY")]
+    pub target_dir: Option<PathBuf>,
+    /// Archive format
+    #[arg(long, value_enum, default_value_t = ArchiveFormat::Zip)]
+    pub archive: ArchiveFormat,
+    /// Directory to write the archives to [default: <target-dir>/jk/package]
+    #[arg(long, value_name = "DIRECTORY")]
+    pub out_dir: Option<PathBuf>,
+    /// Output format; `json` prints the archives and their digests
+    #[arg(long)]
+    pub format: Option<Format>,
+    #[command(flatten)]
+    pub packages: PackageArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct New {
+    /// Directory to create the crate in
+    pub path: PathBuf,
+    /// Package name [default: the directory name]
+    #[arg(long)]
+    pub name: Option<String>,
+    #[command(flatten)]
+    pub plugin: PluginArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct Init {
+    /// Directory of the crate
+    #[arg(default_value = ".")]
+    pub path: PathBuf,
+    #[command(flatten)]
+    pub plugin: PluginArgs,
+}
+
+/// The plugin a new crate starts as
+#[derive(Args, Debug)]
+pub struct PluginArgs {
+    /// Bundle identifier, e.g. com.example.myeffect [default: com.example.<package name>]
+    #[arg(long)]
+    pub identifier: Option<String>,
+    /// Plugin name [default: the package name in title case]
+    #[arg(long)]
+    pub plugin_name: Option<String>,
+    /// Kind of plugin to generate
+    #[arg(long, value_enum, default_value_t)]
+    pub template: Template,
+}
+
+#[derive(Args, Debug)]
+pub struct CheckMetadata {
+    /// Output format; `json` prints the problems found as a JSON array
+    #[arg(long)]
+    pub format: Option<Format>,
+    #[command(flatten)]
+    pub packages: PackageArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct Schema {
+    /// The result document to print the schema of
+    #[arg(value_enum, default_value = "build")]
+    pub document: SchemaDocument,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum SchemaDocument {
+    /// Output of `cargo jk build --format json`
+    Build,
+    /// Output of `cargo jk install --format json`
+    Install,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PiplCommand {
+    /// Print the PiPL embedded in a plugin (.plugin bundle, .aex, .rsrc or .res)
+    Dump(PiplDump),
+}
+
+#[derive(Args, Debug)]
+pub struct PiplDump {
+    /// The plugin or resource file to read
+    pub path: PathBuf,
+    /// Output format [default: text]
+    #[arg(long)]
+    pub format: Option<Format>,
 }
 
 use clap::ValueEnum;
+use serde::Deserialize;
 
-#[derive(Clone, Debug, ValueEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Format {
     /// Output in JSON format
     Json,
+    /// Stream progress events as newline-delimited JSON
+    Ndjson,
     /// No output format specified
     None,
 }
@@ -0,0 +1,424 @@
+//! Layered cargo-jk configuration.
+//!
+//! Settings are read from, in increasing order of precedence:
+//!
+//! 1. `~/.config/cargo-jk/config.toml` (`$XDG_CONFIG_HOME/cargo-jk/config.toml` if set)
+//! 2. the nearest `.cargo-jk.toml` in the current directory or one of its parents
+//! 3. the `AESDK_ROOT` environment variable
+//! 4. `--config key=value` command line overrides
+//!
+//! Relative paths are resolved against the directory of the file that set them, or the current
+//! directory for command line overrides.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use crate::{command::Format, error::JkError};
+
+/// File name of the per-workspace configuration file.
+pub const WORKSPACE_CONFIG_FILE: &str = ".cargo-jk.toml";
+
+/// Keys whose values are paths, resolved relative to the layer that set them.
+const PATH_KEYS: &[&str] = &[
+    "aesdk_root",
+    "install.destination",
+    "sign.macos.p12_file",
+    "sign.macos.p12_password_file",
+    "sign.macos.entitlements",
+    "sign.windows.pfx_file",
+    "sign.windows.pfx_password_file",
+    "notarize.key_file",
+];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Root directory of the After Effects SDK
+    pub aesdk_root: Option<PathBuf>,
+    /// Cargo profile used when neither `--release` nor `--profile` is given
+    pub profile: Option<String>,
+    /// Output format used when `--format` is not given
+    pub format: Option<Format>,
+    pub install: InstallConfig,
+    pub sign: SignConfig,
+    pub notarize: NotarizeConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct InstallConfig {
+    /// Directory plugins are installed into, instead of Adobe's shared MediaCore directory
+    pub destination: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SignConfig {
+    pub macos: MacSignConfig,
+    pub windows: WindowsSignConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MacSignConfig {
+    pub backend: MacSignBackend,
+    /// Keychain identity `codesign` signs with; `-` (the default) signs ad-hoc
+    pub identity: Option<String>,
+    /// PKCS#12 file with the certificate and private key `rcodesign` signs with; `rcodesign`
+    /// must be installed, as cargo-jk does not write certificate signatures itself
+    pub p12_file: Option<PathBuf>,
+    /// File holding the password of `p12_file`
+    pub p12_password_file: Option<PathBuf>,
+    /// Enable the hardened runtime (default `true`)
+    pub hardened_runtime: Option<bool>,
+    /// Ask Apple's timestamp server to countersign identity signatures (default `true`)
+    pub timestamp: Option<bool>,
+    /// Entitlements plist embedded in the signature
+    pub entitlements: Option<PathBuf>,
+}
+
+/// What signs macOS bundles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MacSignBackend {
+    /// `rcodesign` with `p12_file`, `codesign` with `identity`, otherwise `builtin`
+    #[default]
+    Auto,
+    /// Ad-hoc signatures written by cargo-jk itself, on any host
+    Builtin,
+    /// Apple's `codesign`, on macOS only
+    Codesign,
+    /// `rcodesign` from the apple-codesign project, on any host
+    Rcodesign,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct WindowsSignConfig {
+    pub backend: WindowsSignBackend,
+    /// SHA-1 thumbprint of a certificate in the Windows certificate store, for `signtool`
+    pub thumbprint: Option<String>,
+    /// PFX (PKCS#12) file with the certificate and private key
+    pub pfx_file: Option<PathBuf>,
+    /// File holding the password of `pfx_file`
+    pub pfx_password_file: Option<PathBuf>,
+    /// RFC 3161 timestamp server countersigning the signature
+    pub timestamp_url: Option<String>,
+    /// Program and arguments of the `command` backend; `{file}` is replaced by the file to sign
+    /// and `{timestamp_url}` by `timestamp_url`
+    pub command: Option<Vec<String>>,
+    /// Check the signature after signing (default `true`)
+    pub verify: Option<bool>,
+}
+
+/// What signs Windows plugins.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowsSignBackend {
+    /// `command` if set, `signtool` with `thumbprint`, `signtool` (on Windows) or `osslsigncode`
+    /// with `pfx_file`, otherwise unsigned
+    #[default]
+    Auto,
+    /// Leave plugins unsigned
+    None,
+    /// Microsoft's `signtool`, on Windows only
+    Signtool,
+    /// `osslsigncode`, on any host
+    Osslsigncode,
+    /// The program in `command`, e.g. a client of an HSM or cloud signing service
+    Command,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NotarizeConfig {
+    /// ID of the App Store Connect API key
+    pub key_id: Option<String>,
+    /// Issuer ID of the App Store Connect API key
+    pub issuer_id: Option<String>,
+    /// The API key's private key, `AuthKey_<key_id>.p8`
+    pub key_file: Option<PathBuf>,
+    /// Base URL of the notary REST API [default: Apple's]
+    pub base_url: Option<String>,
+    /// S3 endpoint submissions are uploaded to [default: Apple's bucket region]
+    pub upload_url: Option<String>,
+    /// CloudKit endpoint tickets are looked up at [default: Apple's]
+    pub ticket_url: Option<String>,
+    /// Seconds between status checks (default 15)
+    pub poll_interval: Option<u64>,
+    /// Seconds to wait for a verdict before giving up (default 3600)
+    pub timeout: Option<u64>,
+}
+
+impl Config {
+    /// Loads the configuration for a build started in `dir`.
+    pub fn load(dir: &Path, overrides: &[String]) -> Result<Config, JkError> {
+        Config::load_layers(
+            config_home().as_deref(),
+            dir,
+            std::env::var_os("AESDK_ROOT"),
+            overrides,
+        )
+    }
+
+    /// [`Config::load`] with the user's configuration directory and `AESDK_ROOT` given.
+    fn load_layers(
+        config_home: Option<&Path>,
+        dir: &Path,
+        aesdk_root: Option<OsString>,
+        overrides: &[String],
+    ) -> Result<Config, JkError> {
+        let mut merged = Table::new();
+
+        let mut files = Vec::new();
+        if let Some(config_home) = config_home {
+            files.push(config_home.join("cargo-jk").join("config.toml"));
+        }
+        if let Some(workspace) = dir
+            .ancestors()
+            .map(|d| d.join(WORKSPACE_CONFIG_FILE))
+            .find(|p| p.is_file())
+        {
+            files.push(workspace);
+        }
+
+        for path in files.iter().filter(|p| p.is_file()) {
+            let text = std::fs::read_to_string(path).map_err(|e| JkError::Config {
+                path: path.clone(),
+                message: e.to_string(),
+            })?;
+            let mut table: Table = toml::from_str(&text).map_err(|e| JkError::Config {
+                path: path.clone(),
+                message: e.to_string(),
+            })?;
+            // Checked layer by layer, so a bad value is reported where it was written
+            check_layer(&table).map_err(|message| JkError::Config {
+                path: path.clone(),
+                message,
+            })?;
+            resolve_paths(&mut table, path.parent().unwrap_or(Path::new(".")));
+            merge(&mut merged, table);
+        }
+
+        if let Some(aesdk_root) = aesdk_root {
+            merged.insert(
+                "aesdk_root".to_string(),
+                Value::String(aesdk_root.to_string_lossy().into_owned()),
+            );
+        }
+
+        for item in overrides {
+            let mut table = parse_override(item)?;
+            check_layer(&table).map_err(|message| JkError::ConfigOverrideValue {
+                item: item.clone(),
+                message,
+            })?;
+            resolve_paths(&mut table, dir);
+            merge(&mut merged, table);
+        }
+
+        Value::Table(merged)
+            .try_into()
+            .map_err(|e| JkError::Config {
+                path: files.last().cloned().unwrap_or_default(),
+                message: e.to_string(),
+            })
+    }
+}
+
+fn config_home() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => std::env::home_dir().map(|home| home.join(".config")),
+    }
+}
+
+/// Parses a `--config key=value` override.
+///
+/// The value is TOML (`--config 'install.destination="/tmp/plugins"'`); anything that does not
+/// parse as TOML is taken as a plain string (`--config aesdk_root=/opt/aesdk`).
+fn parse_override(item: &str) -> Result<Table, JkError> {
+    let (key, value) = item
+        .split_once('=')
+        .ok_or_else(|| JkError::ConfigOverride(item.to_string()))?;
+    let (key, value) = (key.trim(), value.trim());
+    if key.is_empty() {
+        return Err(JkError::ConfigOverride(item.to_string()));
+    }
+    toml::from_str(&format!("{key} = {value}"))
+        .or_else(|_| toml::from_str(&format!("{key} = {}", Value::String(value.to_string()))))
+        .map_err(|_| JkError::ConfigOverride(item.to_string()))
+}
+
+/// Checks that the keys set by one layer have the types of the configuration.
+fn check_layer(table: &Table) -> Result<(), String> {
+    Config::deserialize(Value::Table(table.clone()))
+        .map(drop)
+        .map_err(|e| e.to_string())
+}
+
+fn resolve_paths(table: &mut Table, base: &Path) {
+    for key in PATH_KEYS {
+        let mut parts = key.split('.').peekable();
+        let mut current = &mut *table;
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                if let Some(Value::String(path)) = current.get_mut(part) {
+                    *path = base.join(&*path).to_string_lossy().into_owned();
+                }
+            } else if let Some(Value::Table(next)) = current.get_mut(part) {
+                current = next;
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+fn merge(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(layer)) => merge(base, layer),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A user configuration directory and a workspace with a `.cargo-jk.toml`, returning the
+    /// directory a build starts in, below the workspace root.
+    fn layout(root: &Path, user: &str, workspace: &str) -> (PathBuf, PathBuf) {
+        let config_home = root.join("home/.config");
+        std::fs::create_dir_all(config_home.join("cargo-jk")).unwrap();
+        std::fs::write(config_home.join("cargo-jk/config.toml"), user).unwrap();
+        let dir = root.join("work/plugins/glow");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(root.join("work").join(WORKSPACE_CONFIG_FILE), workspace).unwrap();
+        (config_home, dir)
+    }
+
+    #[test]
+    fn layers_override_each_other_in_order() {
+        let root = tempfile::tempdir().unwrap();
+        let (config_home, dir) = layout(
+            root.path(),
+            "aesdk_root = \"/opt/aesdk\"\nprofile = \"user\"\n\
+             [sign.macos]\nhardened_runtime = false\nidentity = \"User\"\n",
+            "profile = \"workspace\"\n[sign.macos]\nidentity = \"Workspace\"\n",
+        );
+
+        let config = Config::load_layers(Some(&config_home), &dir, None, &[]).unwrap();
+        assert_eq!(config.aesdk_root, Some(PathBuf::from("/opt/aesdk")));
+        assert_eq!(config.profile.as_deref(), Some("workspace"));
+        // Tables are merged key by key
+        assert_eq!(config.sign.macos.identity.as_deref(), Some("Workspace"));
+        assert_eq!(config.sign.macos.hardened_runtime, Some(false));
+
+        let config = Config::load_layers(
+            Some(&config_home),
+            &dir,
+            Some("/sdk/env".into()),
+            &["profile=cli".to_string(), "aesdk_root=/sdk/cli".to_string()],
+        )
+        .unwrap();
+        assert_eq!(config.profile.as_deref(), Some("cli"));
+        assert_eq!(config.aesdk_root, Some(PathBuf::from("/sdk/cli")));
+
+        let config =
+            Config::load_layers(Some(&config_home), &dir, Some("/sdk/env".into()), &[]).unwrap();
+        assert_eq!(config.aesdk_root, Some(PathBuf::from("/sdk/env")));
+    }
+
+    #[test]
+    fn resolves_paths_against_the_layer_that_set_them() {
+        let root = tempfile::tempdir().unwrap();
+        let (config_home, dir) = layout(
+            root.path(),
+            "[install]\ndestination = \"plugins\"\n",
+            "[sign.macos]\nentitlements = \"entitlements.plist\"\np12_file = \"/keys/id.p12\"\n",
+        );
+        let config = Config::load_layers(
+            Some(&config_home),
+            &dir,
+            None,
+            &["notarize.key_file=keys/AuthKey_TEST.p8".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            config.install.destination,
+            Some(config_home.join("cargo-jk/plugins"))
+        );
+        assert_eq!(
+            config.sign.macos.entitlements,
+            Some(root.path().join("work/entitlements.plist"))
+        );
+        assert_eq!(
+            config.sign.macos.p12_file,
+            Some(PathBuf::from("/keys/id.p12"))
+        );
+        assert_eq!(
+            config.notarize.key_file,
+            Some(dir.join("keys/AuthKey_TEST.p8"))
+        );
+        // Keys that are not paths are left alone
+        let config = Config::load_layers(None, &dir, None, &["profile=dist".to_string()]).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("dist"));
+    }
+
+    #[test]
+    fn parses_override_values_as_toml_or_strings() {
+        let table = parse_override("sign.macos.hardened_runtime = false").unwrap();
+        assert_eq!(
+            table["sign"]["macos"]["hardened_runtime"],
+            Value::Boolean(false)
+        );
+        let table = parse_override("install.destination=/Library/Plug-ins").unwrap();
+        assert_eq!(
+            table["install"]["destination"],
+            Value::String("/Library/Plug-ins".to_string())
+        );
+        assert!(matches!(
+            parse_override("aesdk_root"),
+            Err(JkError::ConfigOverride(item)) if item == "aesdk_root"
+        ));
+        assert!(matches!(
+            parse_override("=value"),
+            Err(JkError::ConfigOverride(_))
+        ));
+    }
+
+    #[test]
+    fn reports_bad_values_where_they_were_written() {
+        let root = tempfile::tempdir().unwrap();
+        let (config_home, dir) = layout(root.path(), "", "profile = \"workspace\"\n");
+
+        let item = "sign.windows.verify=sometimes".to_string();
+        match Config::load_layers(Some(&config_home), &dir, None, std::slice::from_ref(&item)) {
+            Err(JkError::ConfigOverrideValue { item: reported, .. }) => assert_eq!(reported, item),
+            other => panic!("expected ConfigOverrideValue, got {other:?}"),
+        }
+
+        // The user configuration is wrong, not the workspace's that is read after it
+        std::fs::write(
+            config_home.join("cargo-jk/config.toml"),
+            "[notarize]\npoll_interval = \"often\"\n",
+        )
+        .unwrap();
+        match Config::load_layers(Some(&config_home), &dir, None, &[]) {
+            Err(JkError::Config { path, .. }) => {
+                assert_eq!(path, config_home.join("cargo-jk/config.toml"))
+            }
+            other => panic!("expected Config, got {other:?}"),
+        }
+    }
+}
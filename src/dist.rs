@@ -0,0 +1,619 @@
+//! Distributable archives of built plugins, one per plugin and platform.
+//!
+//! Plugins are picked up where `cargo jk build` stages them, `<target-dir>/jk/<profile>/<target>`,
+//! or from the paths given, so the builds of every platform can be gathered and packaged by a
+//! single job on any host. Each archive holds a `<PluginName>-<version>-<platform>-<architecture>`
+//! directory with the plugin, its resources and the package's documents, and `SHA256SUMS` lists
+//! the digests of the archives written.
+
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CargoOptions, PackageSelection, PluginManifest,
+    archive::Archive,
+    build::{bundle, fat},
+    error::JkError,
+    package, report,
+};
+
+/// Name of the checksum file written next to the archives.
+pub const CHECKSUMS: &str = "SHA256SUMS";
+
+/// Magic of a 64-bit Mach-O file, stored in the file's (little endian) byte order.
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+const IMAGE_FILE_MACHINE_ARM64: u16 = 0xaa64;
+
+/// Format of the archives `cargo jk package` writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    /// Gzipped tar
+    #[serde(rename = "tar.gz")]
+    #[value(name = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+}
+
+/// Options for [`package_plugins`].
+#[derive(Debug, Clone, Default)]
+pub struct PackageOptions {
+    /// Packages whose plugins are packaged
+    pub selection: PackageSelection,
+    /// The profile and target directory the plugins were built with
+    pub cargo: CargoOptions,
+    /// Built `.plugin` bundles and `.aex` files; if empty, every plugin of the selected packages
+    /// staged under `<target-dir>/jk/<profile>` is packaged
+    pub plugins: Vec<PathBuf>,
+    pub format: ArchiveFormat,
+    /// Directory to write the archives to; defaults to `<target-dir>/jk/package`
+    pub out_dir: Option<PathBuf>,
+}
+
+/// The archives written by [`package_plugins`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Packages {
+    pub archives: Vec<PackageOutput>,
+    /// The `SHA256SUMS` file listing the archives
+    pub checksums: PathBuf,
+}
+
+/// An archive of one plugin for one platform.
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageOutput {
+    /// Name of the cargo package the plugin was built from
+    pub package: String,
+    pub version: String,
+    pub plugin_name: String,
+    /// `macos` or `windows`
+    pub platform: String,
+    /// Architecture of the plugin binary; `universal` for universal macOS binaries
+    pub architecture: String,
+    /// The `.plugin` bundle or `.aex` file that was packaged
+    pub plugin: PathBuf,
+    /// The archive
+    pub path: PathBuf,
+    /// Hex SHA-256 digest of the archive
+    pub sha256: String,
+    /// Size of the archive in bytes
+    pub size: u64,
+}
+
+/// Packages the built plugins of the selected packages and writes `SHA256SUMS` next to the
+/// archives.
+pub fn package_plugins(options: &PackageOptions) -> Result<Packages, JkError> {
+    let selected = package::select_packages(&options.selection)?;
+    let target_dir = options
+        .cargo
+        .target_dir
+        .clone()
+        .unwrap_or(selected.target_directory);
+    let manifests = selected
+        .packages
+        .iter()
+        .map(PluginManifest::resolve)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let plugins = if options.plugins.is_empty() {
+        let staging_dir = target_dir.join("jk").join(options.cargo.profile_dir());
+        staged_plugins(&manifests, &staging_dir)?
+    } else {
+        options
+            .plugins
+            .iter()
+            .map(|path| {
+                let manifest = manifests
+                    .iter()
+                    .find(|manifest| plugin_name(path) == Some(&manifest.plugin_name))
+                    .ok_or_else(|| JkError::Package {
+                        path: path.clone(),
+                        message: format!(
+                            "it is not the .plugin bundle or .aex file of a selected package ({})",
+                            manifests
+                                .iter()
+                                .map(|manifest| manifest.plugin_name.as_str())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                    })?;
+                Ok((manifest, path.clone()))
+            })
+            .collect::<Result<Vec<_>, JkError>>()?
+    };
+
+    // Every archive is named before any is written, so that two plugins packaged under the same
+    // name fail the command without overwriting each other
+    let planned = plugins
+        .into_iter()
+        .map(|(manifest, plugin)| PluginArchive::inspect(manifest, plugin))
+        .collect::<Result<Vec<_>, _>>()?;
+    for (index, archive) in planned.iter().enumerate() {
+        if planned[..index]
+            .iter()
+            .any(|other| other.stem() == archive.stem())
+        {
+            return Err(JkError::Package {
+                path: archive.plugin.clone(),
+                message: format!(
+                    "another plugin is packaged as {}",
+                    archive.file_name(options.format)
+                ),
+            });
+        }
+    }
+
+    let out_dir = options
+        .out_dir
+        .clone()
+        .unwrap_or_else(|| target_dir.join("jk/package"));
+    std::fs::create_dir_all(&out_dir).map_err(JkError::bundle(&out_dir))?;
+    let archives = planned
+        .iter()
+        .map(|archive| archive.write(options.format, &out_dir))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let checksums = out_dir.join(CHECKSUMS);
+    let lines: String = archives
+        .iter()
+        .map(|archive| {
+            let name = archive.path.file_name().unwrap_or_default();
+            format!("{}  {}\n", archive.sha256, name.to_string_lossy())
+        })
+        .collect();
+    std::fs::write(&checksums, lines).map_err(JkError::bundle(&checksums))?;
+    Ok(Packages {
+        archives,
+        checksums,
+    })
+}
+
+/// The plugins of `manifests` in the target directories below `staging_dir`, in name order.
+/// Every package must have at least one.
+fn staged_plugins<'a>(
+    manifests: &'a [PluginManifest],
+    staging_dir: &Path,
+) -> Result<Vec<(&'a PluginManifest, PathBuf)>, JkError> {
+    let mut targets: Vec<PathBuf> = std::fs::read_dir(staging_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    targets.sort();
+
+    let mut plugins = Vec::new();
+    for manifest in manifests {
+        let found: Vec<PathBuf> = targets
+            .iter()
+            .flat_map(|dir| {
+                [
+                    dir.join(format!("{}.plugin", manifest.plugin_name)),
+                    dir.join(format!("{}.aex", manifest.plugin_name)),
+                ]
+            })
+            .filter(|path| path.exists())
+            .collect();
+        if found.is_empty() {
+            return Err(JkError::NoBuiltPlugin {
+                package: manifest.package.clone(),
+                dir: staging_dir.to_path_buf(),
+            });
+        }
+        plugins.extend(found.into_iter().map(|path| (manifest, path)));
+    }
+    Ok(plugins)
+}
+
+/// The plugin name of a `.plugin` bundle or `.aex` file.
+fn plugin_name(path: &Path) -> Option<&str> {
+    match path.extension()?.to_str()? {
+        "plugin" | "aex" => path.file_stem()?.to_str(),
+        _ => None,
+    }
+}
+
+/// A plugin about to be packaged, with what its archive is named after.
+struct PluginArchive<'a> {
+    manifest: &'a PluginManifest,
+    plugin: PathBuf,
+    /// `macos` or `windows`
+    platform: &'static str,
+    architecture: String,
+    /// Files put in the archive next to the plugin
+    resources: Vec<PathBuf>,
+}
+
+impl<'a> PluginArchive<'a> {
+    /// Reads the platform and architecture of `plugin` from its binary.
+    fn inspect(manifest: &'a PluginManifest, plugin: PathBuf) -> Result<Self, JkError> {
+        let error = |message: String| JkError::Package {
+            path: plugin.clone(),
+            message,
+        };
+        let dir = plugin.parent().unwrap_or(Path::new(""));
+        let (platform, architecture, resources) = match plugin.extension() {
+            Some(extension) if extension == "plugin" => {
+                let bundle = bundle::verify(&plugin)?;
+                let data = std::fs::read(&bundle.executable).map_err(|e| error(e.to_string()))?;
+                ("macos", mac_architecture(&data).map_err(error)?, Vec::new())
+            }
+            _ => {
+                let data = std::fs::read(&plugin).map_err(|e| error(e.to_string()))?;
+                // Resources were copied next to the .aex by the build
+                let resources: Vec<PathBuf> = manifest
+                    .resources
+                    .iter()
+                    .chain(&manifest.windows.resources)
+                    .filter_map(|resource| resource.file_name())
+                    .map(|name| dir.join(name))
+                    .collect();
+                ("windows", pe_architecture(&data).map_err(error)?, resources)
+            }
+        };
+        Ok(PluginArchive {
+            manifest,
+            plugin,
+            platform,
+            architecture,
+            resources,
+        })
+    }
+
+    /// `<PluginName>-<version>-<platform>-<architecture>`, the name of the archive and of the
+    /// directory in it.
+    fn stem(&self) -> String {
+        format!(
+            "{}-{}-{}-{}",
+            self.manifest.plugin_name, self.manifest.version, self.platform, self.architecture
+        )
+    }
+
+    fn file_name(&self, format: ArchiveFormat) -> String {
+        format!("{}.{}", self.stem(), format.extension())
+    }
+
+    /// Writes the archive to `out_dir`.
+    fn write(&self, format: ArchiveFormat, out_dir: &Path) -> Result<PackageOutput, JkError> {
+        let error = |message: String| JkError::Package {
+            path: self.plugin.clone(),
+            message,
+        };
+        let stem = self.stem();
+        let mut archive = Archive::new();
+        archive.add_directory(&stem);
+        for path in std::iter::once(self.plugin.as_path())
+            .chain(self.resources.iter().map(PathBuf::as_path))
+            .chain(self.manifest.documents.iter().map(PathBuf::as_path))
+        {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            archive
+                .add(path, &format!("{stem}/{name}"))
+                .map_err(error)?;
+        }
+        let data = match format {
+            ArchiveFormat::Zip => archive.zip(),
+            ArchiveFormat::TarGz => archive.tar_gz(),
+        }
+        .map_err(error)?;
+
+        let path = out_dir.join(self.file_name(format));
+        std::fs::write(&path, data).map_err(JkError::bundle(&path))?;
+        let (sha256, size) = report::digest_file(&path).map_err(JkError::bundle(&path))?;
+        Ok(PackageOutput {
+            package: self.manifest.package.clone(),
+            version: self.manifest.version.to_string(),
+            plugin_name: self.manifest.plugin_name.clone(),
+            platform: self.platform.to_string(),
+            architecture: self.architecture.clone(),
+            plugin: self.plugin.clone(),
+            path,
+            sha256,
+            size,
+        })
+    }
+}
+
+/// `universal` for a fat Mach-O file, or the architecture of a thin one.
+fn mac_architecture(data: &[u8]) -> Result<String, String> {
+    match fat::read(data) {
+        Some(slices) => match slices?.as_slice() {
+            [slice] => Ok(fat::architecture_name(slice.cpu_type)),
+            _ => Ok("universal".to_string()),
+        },
+        None => {
+            let word = |offset: usize| {
+                data.get(offset..offset + 4)
+                    .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            };
+            match (word(0), word(4)) {
+                (Some(MH_MAGIC_64), Some(cpu_type)) => Ok(fat::architecture_name(cpu_type)),
+                _ => Err("its executable is not a 64-bit Mach-O file".to_string()),
+            }
+        }
+    }
+}
+
+/// Architecture of a PE file, from the machine type of its COFF header.
+fn pe_architecture(data: &[u8]) -> Result<String, String> {
+    let pe = data
+        .get(0x3c..0x40)
+        .filter(|_| data.starts_with(b"MZ"))
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        .filter(|&pe| data.get(pe..pe + 4) == Some(b"PE\0\0"))
+        .ok_or("it is not a PE file")?;
+    let machine = data
+        .get(pe + 4..pe + 6)
+        .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or("it is not a PE file")?;
+    match machine {
+        IMAGE_FILE_MACHINE_AMD64 => Ok("x86_64".to_string()),
+        IMAGE_FILE_MACHINE_ARM64 => Ok("arm64".to_string()),
+        other => Err(format!("it is built for an unsupported machine {other:#x}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build::bundle::BundleContents,
+        pipl::{ByteOrder, Pipl, RESOURCE_ID, rsrc},
+    };
+
+    fn fixture(path: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(path)
+    }
+
+    /// A workspace at `root` with the plugin package `glow` (plugin `Glow` 1.2.0) and a README.
+    fn workspace(root: &Path) -> PackageOptions {
+        let dir = root.join("glow");
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "").unwrap();
+        std::fs::write(dir.join("README.md"), "# Glow\n").unwrap();
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"glow\"\nversion = \"1.2.0\"\nedition = \"2021\"\n\n\
+             [lib]\ncrate-type = [\"cdylib\"]\n\n\
+             [package.metadata.jk_plugin]\nplugin_name = \"Glow\"\nidentifier = \"com.example.glow\"\n",
+        )
+        .unwrap();
+        PackageOptions {
+            selection: PackageSelection {
+                manifest_path: Some(dir.join("Cargo.toml")),
+                ..PackageSelection::default()
+            },
+            cargo: CargoOptions {
+                release: true,
+                target_dir: Some(root.join("target")),
+                ..CargoOptions::default()
+            },
+            ..PackageOptions::default()
+        }
+    }
+
+    /// Stages `Glow.aex` and a universal `Glow.plugin` like `cargo jk build --release` does.
+    fn stage(root: &Path, options: &PackageOptions) -> (PathBuf, PathBuf) {
+        let staging = root.join("target/jk/release");
+        let windows = staging.join("x86_64-pc-windows-msvc");
+        std::fs::create_dir_all(&windows).unwrap();
+        let aex = windows.join("Glow.aex");
+        std::fs::copy(fixture("pe/machot.dll"), &aex).unwrap();
+
+        let mac = staging.join("universal-apple-darwin");
+        std::fs::create_dir_all(&mac).unwrap();
+        let (x86_64, arm64) = (
+            fixture("macho/libmachot.dylib"),
+            fixture("macho/libmachot.arm64.dylib"),
+        );
+        let universal = root.join("libglow.dylib");
+        fat::create(
+            &[
+                fat::Slice {
+                    path: &x86_64,
+                    target: "x86_64-apple-darwin",
+                },
+                fat::Slice {
+                    path: &arm64,
+                    target: "aarch64-apple-darwin",
+                },
+            ],
+            &universal,
+        )
+        .unwrap();
+        let rsrc_path = root.join("Glow.rsrc");
+        let pipl = Pipl {
+            properties: Vec::new(),
+        }
+        .encode(ByteOrder::Big);
+        std::fs::write(
+            &rsrc_path,
+            rsrc::write_resource_fork(*b"PiPL", RESOURCE_ID, &pipl),
+        )
+        .unwrap();
+        let manifest_path = options.selection.manifest_path.as_ref().unwrap();
+        let package = cargo_metadata::MetadataCommand::new()
+            .manifest_path(manifest_path)
+            .no_deps()
+            .exec()
+            .unwrap()
+            .root_package()
+            .unwrap()
+            .clone();
+        let manifest = PluginManifest::resolve(&package).unwrap();
+        let contents = BundleContents::new(&manifest, universal, rsrc_path);
+        let bundle = bundle::assemble(&mac, &manifest, &contents).unwrap();
+        (bundle.path, aex)
+    }
+
+    #[test]
+    fn packages_the_staged_plugins_with_their_checksums() {
+        let root = tempfile::tempdir().unwrap();
+        let options = workspace(root.path());
+        let (plugin, aex) = stage(root.path(), &options);
+
+        let packages = package_plugins(&options).unwrap();
+        let out_dir = root.path().join("target/jk/package");
+        assert_eq!(packages.checksums, out_dir.join("SHA256SUMS"));
+        let archives: Vec<_> = packages
+            .archives
+            .iter()
+            .map(|archive| {
+                (
+                    archive.plugin.clone(),
+                    archive.platform.as_str(),
+                    archive.architecture.as_str(),
+                    archive.path.strip_prefix(&out_dir).unwrap().to_path_buf(),
+                )
+            })
+            .collect();
+        // In the order of the target directories
+        assert_eq!(
+            archives,
+            [
+                (
+                    plugin,
+                    "macos",
+                    "universal",
+                    "Glow-1.2.0-macos-universal.zip".into()
+                ),
+                (
+                    aex,
+                    "windows",
+                    "x86_64",
+                    "Glow-1.2.0-windows-x86_64.zip".into()
+                ),
+            ]
+        );
+
+        let mut expected = String::new();
+        for archive in &packages.archives {
+            let (sha256, size) = report::digest_file(&archive.path).unwrap();
+            assert_eq!((&archive.sha256, archive.size), (&sha256, size));
+            let name = archive.path.file_name().unwrap().to_str().unwrap();
+            expected += &format!("{sha256}  {name}\n");
+        }
+        assert_eq!(
+            std::fs::read_to_string(&packages.checksums).unwrap(),
+            expected
+        );
+
+        let zip = std::fs::File::open(&packages.archives[1].path).unwrap();
+        let mut names: Vec<String> = zip::ZipArchive::new(zip)
+            .unwrap()
+            .file_names()
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "Glow-1.2.0-windows-x86_64/",
+                "Glow-1.2.0-windows-x86_64/Glow.aex",
+                "Glow-1.2.0-windows-x86_64/README.md",
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_nothing_when_two_plugins_share_an_archive_name() {
+        let root = tempfile::tempdir().unwrap();
+        let mut options = workspace(root.path());
+        let (_, aex) = stage(root.path(), &options);
+        let copy = root.path().join("copy/Glow.aex");
+        std::fs::create_dir_all(copy.parent().unwrap()).unwrap();
+        std::fs::copy(&aex, &copy).unwrap();
+        options.plugins = vec![aex, copy.clone()];
+
+        match package_plugins(&options) {
+            Err(JkError::Package { path, message }) => {
+                assert_eq!(path, copy);
+                assert_eq!(
+                    message,
+                    "another plugin is packaged as Glow-1.2.0-windows-x86_64.zip"
+                );
+            }
+            other => panic!("expected Package, got {other:?}"),
+        }
+        assert!(!root.path().join("target/jk/package").exists());
+    }
+
+    #[test]
+    fn requires_a_staged_plugin_for_every_package() {
+        let root = tempfile::tempdir().unwrap();
+        let options = workspace(root.path());
+        std::fs::create_dir_all(root.path().join("target/jk/debug/x86_64-pc-windows-msvc"))
+            .unwrap();
+        std::fs::copy(
+            fixture("pe/machot.dll"),
+            root.path()
+                .join("target/jk/debug/x86_64-pc-windows-msvc/Glow.aex"),
+        )
+        .unwrap();
+        // Built with another profile than the one packaged
+        match package_plugins(&options) {
+            Err(JkError::NoBuiltPlugin { package, dir }) => {
+                assert_eq!(package, "glow");
+                assert_eq!(dir, root.path().join("target/jk/release"));
+            }
+            other => panic!("expected NoBuiltPlugin, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn reads_the_architecture_of_mach_o_files() {
+        let read = |name: &str| std::fs::read(fixture(&format!("macho/{name}"))).unwrap();
+        assert_eq!(
+            mac_architecture(&read("libmachot.dylib")).unwrap(),
+            "x86_64"
+        );
+        assert_eq!(
+            mac_architecture(&read("libmachot.arm64.dylib")).unwrap(),
+            "arm64"
+        );
+        let not_mach_o = Err("its executable is not a 64-bit Mach-O file".to_string());
+        assert_eq!(mac_architecture(&read("i386.o")), not_mach_o);
+        assert_eq!(
+            mac_architecture(&std::fs::read(fixture("pe/machot.dll")).unwrap()),
+            not_mach_o
+        );
+        assert_eq!(mac_architecture(b"\xcf\xfa\xed\xfe"), not_mach_o);
+    }
+
+    #[test]
+    fn reads_the_architecture_of_pe_files() {
+        let dll = std::fs::read(fixture("pe/machot.dll")).unwrap();
+        assert_eq!(pe_architecture(&dll).unwrap(), "x86_64");
+
+        let pe = u32::from_le_bytes(dll[0x3c..0x40].try_into().unwrap()) as usize;
+        let machine = |value: u16| {
+            let mut patched = dll.clone();
+            patched[pe + 4..pe + 6].copy_from_slice(&value.to_le_bytes());
+            pe_architecture(&patched)
+        };
+        assert_eq!(machine(IMAGE_FILE_MACHINE_ARM64).unwrap(), "arm64");
+        assert_eq!(
+            machine(0x14c),
+            Err("it is built for an unsupported machine 0x14c".to_string())
+        );
+        let dylib = std::fs::read(fixture("macho/libmachot.dylib")).unwrap();
+        assert_eq!(
+            pe_architecture(&dylib),
+            Err("it is not a PE file".to_string())
+        );
+    }
+}
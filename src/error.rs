@@ -0,0 +1,211 @@
+use std::{io, path::PathBuf, process::ExitStatus};
+
+/// Errors that can stop the build/install pipeline.
+#[derive(Debug, thiserror::Error)]
+pub enum JkError {
+    #[error("failed to read cargo metadata: {0}")]
+    CargoMetadata(#[from] cargo_metadata::Error),
+
+    #[error("failed to get the current directory: {0}")]
+    CurrentDir(io::Error),
+
+    #[error("invalid manifest path {}: {source}", .path.display())]
+    ManifestPath { path: PathBuf, source: io::Error },
+
+    #[error(
+        "could not determine which package to build; use --package or --workspace (plugin packages: {})",
+        .candidates.join(", ")
+    )]
+    NoPackageSelected { candidates: Vec<String> },
+
+    #[error("package `{0}` not found in the workspace")]
+    UnknownPackage(String),
+
+    #[error("no workspace member has a [package.metadata.jk_plugin] section")]
+    NoPluginPackages,
+
+    #[error("no [package.metadata.jk_plugin] section in {}", .manifest_path.display())]
+    MissingMetadata {
+        package: String,
+        manifest_path: PathBuf,
+    },
+
+    #[error(
+        "the plugin metadata has {errors} error{}; see the messages above",
+        if *.errors == 1 { "" } else { "s" }
+    )]
+    MetadataCheck { errors: usize },
+
+    #[error("failed to parse jk_plugin metadata of `{package}`: {source}")]
+    InvalidMetadata {
+        package: String,
+        source: serde_json::Error,
+    },
+
+    #[error(
+        "AESDK_ROOT is not defined; set the environment variable, or `aesdk_root` in the cargo-jk config or [package.metadata.jk_plugin]"
+    )]
+    MissingAesdkRoot,
+
+    #[error("invalid [package.metadata.jk_plugin] in `{package}`: {message}")]
+    InvalidManifest { package: String, message: String },
+
+    #[error("invalid [package.metadata.jk_plugin.pipl] in `{package}`: {message}")]
+    InvalidPipl { package: String, message: String },
+
+    #[error("failed to write PiPL resource {}: {source}", .path.display())]
+    Pipl { path: PathBuf, source: io::Error },
+
+    #[error("failed to write version resource {}: {source}", .path.display())]
+    VersionInfo { path: PathBuf, source: io::Error },
+
+    #[error(
+        "`{package}` has no PiPL resource; pass --rsrc, or add [package.metadata.jk_plugin.pipl] to generate one"
+    )]
+    NoPipl { package: String },
+
+    #[error("failed to read PiPL from {}: {message}", .path.display())]
+    ReadPipl { path: PathBuf, message: String },
+
+    #[error("invalid Info.plist template {}: {message}", .path.display())]
+    InfoPlistTemplate { path: PathBuf, message: String },
+
+    #[error("AESDK_ROOT points to {}, {message}", .root.display())]
+    InvalidAesdk { root: PathBuf, message: String },
+
+    #[error("invalid configuration in {}: {message}", .path.display())]
+    Config { path: PathBuf, message: String },
+
+    #[error("invalid --config override `{0}`; expected KEY=VALUE")]
+    ConfigOverride(String),
+
+    #[error("invalid --config override `{item}`: {message}")]
+    ConfigOverrideValue { item: String, message: String },
+
+    #[error("could not determine the host target triple: {0}")]
+    HostTriple(String),
+
+    #[error("failed to execute `{command}`: {source}")]
+    Spawn { command: String, source: io::Error },
+
+    #[error("failed to read cargo output: {0}")]
+    CargoOutput(io::Error),
+
+    #[error("cargo build failed with status: {0}")]
+    CargoFailed(ExitStatus),
+
+    #[error("cargo build for {target} failed with status: {status}")]
+    TargetBuildFailed { target: String, status: ExitStatus },
+
+    #[error("the thread running cargo build for {target} panicked")]
+    BuildThreadPanicked { target: String },
+
+    #[error(
+        "package `{package}` has no cdylib target; add `crate-type = [\"cdylib\"]` to the [lib] section of its Cargo.toml"
+    )]
+    NoCdylib { package: String },
+
+    #[error(
+        "unsupported target `{0}`; After Effects plugins are built for macOS (*-apple-darwin) or Windows (*-pc-windows-*)"
+    )]
+    UnsupportedTarget(String),
+
+    #[error("{operation} is not supported on {}", std::env::consts::OS)]
+    UnsupportedHost { operation: String },
+
+    #[error("cargo did not report a cdylib artifact for package `{package}`")]
+    NoArtifact { package: String },
+
+    #[error("unexpected artifact path {}", .0.display())]
+    InvalidArtifactPath(PathBuf),
+
+    #[error("`{tool}` failed with status: {status}")]
+    ToolFailed { tool: String, status: ExitStatus },
+
+    #[error("failed to create plugin bundle at {}: {source}", .path.display())]
+    Bundle { path: PathBuf, source: io::Error },
+
+    #[error("{} is not a valid plugin bundle: {message}", .path.display())]
+    InvalidBundle { path: PathBuf, message: String },
+
+    #[error("{} cannot be merged into a universal binary: {message}", .path.display())]
+    InvalidMachO { path: PathBuf, message: String },
+
+    #[error("failed to sign {}: {reason}", .path.display())]
+    Signing { path: PathBuf, reason: String },
+
+    #[error(
+        "`notarize.{0}` is not set; add it to the [notarize] section of the cargo-jk config or pass --config notarize.{0}=..."
+    )]
+    MissingNotarizeConfig(&'static str),
+
+    #[error("invalid App Store Connect API key {}: {message}", .path.display())]
+    NotaryKey { path: PathBuf, message: String },
+
+    #[error("notary service request failed while {operation}: {message}")]
+    NotaryRequest {
+        operation: &'static str,
+        message: String,
+    },
+
+    #[error("{} was not notarized: submission {id} is {status}; see the log above", .path.display())]
+    NotarizationRejected {
+        path: PathBuf,
+        id: String,
+        status: String,
+    },
+
+    #[error("submission {id} was still in progress after {seconds} seconds")]
+    NotarizationTimeout { id: String, seconds: u64 },
+
+    #[error("failed to staple the ticket to {}: {message}", .path.display())]
+    Staple { path: PathBuf, message: String },
+
+    #[error("failed to collect debug symbols {}: {message}", .path.display())]
+    DebugSymbols { path: PathBuf, message: String },
+
+    #[error(
+        "no built plugin of `{package}` in {}; run `cargo jk build` with the same profile first, or pass the plugins to package",
+        .dir.display()
+    )]
+    NoBuiltPlugin { package: String, dir: PathBuf },
+
+    #[error("cannot package {}: {message}", .path.display())]
+    Package { path: PathBuf, message: String },
+
+    #[error("destination {} already exists", .0.display())]
+    DestinationExists(PathBuf),
+
+    #[error("invalid package name `{name}`: {reason}")]
+    InvalidPackageName { name: String, reason: String },
+
+    #[error("invalid plugin name `{name}`: {reason}")]
+    InvalidPluginName { name: String, reason: String },
+
+    #[error("identifier `{0}` is not a reverse-DNS name such as `com.example.plugin`")]
+    InvalidIdentifier(String),
+
+    #[error("cannot add plugin metadata to {}: {message}", .path.display())]
+    InitManifest { path: PathBuf, message: String },
+
+    #[error("failed to write {}: {source}", .path.display())]
+    Scaffold { path: PathBuf, source: io::Error },
+
+    #[error("failed to elevate privileges: {0}")]
+    Elevation(String),
+
+    #[error("failed to install {}: {source}", .path.display())]
+    Install { path: PathBuf, source: io::Error },
+}
+
+impl JkError {
+    pub(crate) fn bundle(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> JkError {
+        let path = path.into();
+        move |source| JkError::Bundle { path, source }
+    }
+
+    pub(crate) fn spawn(command: &std::process::Command) -> impl FnOnce(io::Error) -> JkError {
+        let command = format!("{command:?}");
+        move |source| JkError::Spawn { command, source }
+    }
+}
@@ -0,0 +1,162 @@
+//! Progress events of the build and install pipeline.
+//!
+//! `cargo jk build --format ndjson` prints every [`Event`] as one JSON object per line, e.g.
+//!
+//! ```json
+//! {"timestamp":1760000000000,"event":"artifact","package":"glow","path":"/work/target/release/libglow.dylib"}
+//! ```
+
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use cargo_metadata::diagnostic::DiagnosticLevel;
+use serde::Serialize;
+
+use crate::{check::Location, sdk::SdkVersion};
+
+/// A pipeline event with the time it happened.
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum EventKind {
+    /// The package's `[package.metadata.jk_plugin]` section was read.
+    MetadataResolved {
+        package: String,
+        plugin_name: String,
+        identifier: String,
+        manifest_path: PathBuf,
+    },
+    /// The After Effects SDK the package builds against was found.
+    SdkResolved {
+        package: String,
+        root: PathBuf,
+        version: Option<SdkVersion>,
+    },
+    /// Something looks wrong but does not stop the build, such as an unknown metadata key.
+    Warning {
+        package: String,
+        message: String,
+        /// The line of `Cargo.toml` the warning is about
+        #[serde(skip_serializing_if = "Option::is_none")]
+        location: Option<Location>,
+    },
+    /// The package's metadata is invalid; the build stops after every package is checked.
+    MetadataError {
+        package: String,
+        message: String,
+        location: Option<Location>,
+    },
+    /// A cargo build was started, for the host or for the given target.
+    CargoStarted {
+        package: String,
+        target: Option<String>,
+        command: String,
+    },
+    /// A tool other than cargo was run, such as `rustup` or `dsymutil`.
+    ToolStarted { package: String, command: String },
+    /// rustc reported a warning or an error.
+    CompilerDiagnostic {
+        package: String,
+        level: DiagnosticLevel,
+        message: String,
+        rendered: Option<String>,
+    },
+    /// cargo produced the plugin's dynamic library.
+    Artifact { package: String, path: PathBuf },
+    /// The plugin (`.plugin` bundle or `.aex` file) was created.
+    BundleCreated { package: String, path: PathBuf },
+    /// Per-architecture binaries were merged into a universal binary.
+    UniversalMerged {
+        package: String,
+        path: PathBuf,
+        architectures: Vec<String>,
+    },
+    /// The plugin was code signed.
+    Signed {
+        package: String,
+        path: PathBuf,
+        identity: String,
+    },
+    /// The debug symbols of the plugin (`.dSYM` bundle or `.pdb` file) were written.
+    SymbolsCollected { package: String, path: PathBuf },
+    /// Debug symbols were packed into `symbols.zip`.
+    SymbolsPacked { path: PathBuf },
+    /// The bundle was uploaded to the notary service.
+    NotarizationSubmitted {
+        path: PathBuf,
+        id: String,
+        /// Size of the uploaded archive in bytes
+        size: u64,
+    },
+    /// The notary service has not decided on the submission yet.
+    NotarizationPending { id: String, status: String },
+    /// The notary service decided on the submission.
+    NotarizationFinished {
+        id: String,
+        status: String,
+        /// The notarization log, whose issues explain the verdict
+        log: Option<serde_json::Value>,
+        /// Why the log could not be fetched
+        #[serde(skip_serializing_if = "Option::is_none")]
+        log_error: Option<String>,
+    },
+    /// The notarization ticket was stapled to the bundle.
+    Stapled { path: PathBuf },
+    /// The plugin was copied into the plugin directory.
+    Installed {
+        package: String,
+        path: PathBuf,
+        destination: PathBuf,
+    },
+    /// The pipeline ended; `error` is set if it failed.
+    Finished {
+        success: bool,
+        plugins: Vec<PathBuf>,
+        error: Option<String>,
+    },
+}
+
+type HandlerFn = dyn Fn(&Event) + Send + Sync;
+
+/// Receives pipeline events. The default handler drops them.
+#[derive(Clone, Default)]
+pub struct EventHandler(Option<Arc<HandlerFn>>);
+
+impl EventHandler {
+    pub fn new(handler: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        EventHandler(Some(Arc::new(handler)))
+    }
+
+    /// Whether events are being listened to at all.
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn emit(&self, kind: EventKind) {
+        if let Some(handler) = &self.0 {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64);
+            handler(&Event { timestamp, kind });
+        }
+    }
+}
+
+impl fmt::Debug for EventHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("EventHandler")
+            .field(&self.is_enabled())
+            .finish()
+    }
+}
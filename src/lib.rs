@@ -0,0 +1,609 @@
+//! Build and install pipeline for JK plugins (Adobe After Effects plugins written in Rust).
+//!
+//! The `cargo-jk` binary is a thin command-line wrapper around [`build`] and [`install`];
+//! other tools can drive the same pipeline directly and handle [`JkError`]s themselves.
+
+mod archive;
+pub mod build;
+pub mod cargo;
+pub mod check;
+pub mod command;
+pub mod config;
+pub mod dist;
+pub mod error;
+pub mod event;
+pub mod manifest;
+pub mod mv;
+pub mod notarize;
+pub mod package;
+pub mod pipl;
+pub mod report;
+pub mod scaffold;
+pub mod sdk;
+pub mod sign;
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use cargo_metadata::{Artifact, Message};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::build::bundle::{BundleContents, MacBundle};
+use crate::build::symbols::SymbolsMode;
+use crate::pipl::Platform;
+
+pub use crate::build::SigningStatus;
+pub use crate::build::symbols::{DebugId, Symbols};
+pub use crate::cargo::CargoOptions;
+pub use crate::check::{Problem, Severity};
+pub use crate::config::Config;
+pub use crate::dist::{PackageOptions, PackageOutput, Packages, package_plugins};
+pub use crate::error::JkError;
+pub use crate::event::{Event, EventHandler, EventKind};
+pub use crate::manifest::PluginManifest;
+pub use crate::notarize::{NotarizeOptions, NotarizeOutput, notarize};
+pub use crate::package::{JkPluginMetadata, MacArch, PackageSelection};
+pub use crate::pipl::PiplResources;
+pub use crate::report::{BuildReport, InstallReport};
+pub use crate::sdk::AeSdk;
+
+/// Options for [`build`].
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Options forwarded to cargo
+    pub cargo: CargoOptions,
+    /// Architectures of macOS plugins built without an explicit target; overrides `macos.arch`
+    pub arch: Option<MacArch>,
+    /// Collect the debug symbols of each plugin, and whether to pack them into `symbols.zip`
+    pub symbols: Option<SymbolsMode>,
+    /// Packages to build
+    pub selection: PackageSelection,
+    /// Machine and workspace settings
+    pub config: Config,
+    /// Receives progress events
+    pub events: EventHandler,
+}
+
+/// A plugin built by [`build`], as reported in the JSON result document.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BuildOutput {
+    /// Name of the cargo package the plugin was built from
+    pub package: String,
+    /// Version of the cargo package
+    pub version: String,
+    /// Plugin name from `[package.metadata.jk_plugin]`
+    pub plugin_name: String,
+    /// Bundle identifier from `[package.metadata.jk_plugin]`
+    pub identifier: String,
+    /// Cargo profile the plugin was built with
+    pub profile: String,
+    /// Path to the finished plugin (`.aex` file or `.plugin` bundle)
+    pub path: PathBuf,
+    /// Path to the dynamic library cargo produced
+    pub cdylib: PathBuf,
+    /// Target triples the plugin binary contains
+    pub targets: Vec<String>,
+    /// Architectures the plugin binary contains (`x86_64`, `arm64`)
+    pub architectures: Vec<String>,
+    /// How the plugin was code signed
+    pub signing: SigningStatus,
+    /// Hex SHA-256 digest of the plugin binary (the `.aex` file or the bundle executable)
+    pub sha256: String,
+    /// Size of the plugin binary in bytes
+    pub size: u64,
+    /// The After Effects SDK the plugin was built against
+    pub sdk: AeSdk,
+    /// Debug symbols collected with `--symbols`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbols: Option<Symbols>,
+}
+
+/// Options for [`install`].
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    pub build: BuildOptions,
+}
+
+/// Options for [`bundle`].
+#[derive(Debug, Clone, Default)]
+pub struct BundleOptions {
+    /// The package whose manifest describes the bundle
+    pub selection: PackageSelection,
+    /// Machine and workspace settings
+    pub config: Config,
+    /// Mach-O binary built for the plugin, thin or universal
+    pub executable: PathBuf,
+    /// PiPL resource fork; generated from the manifest if not given
+    pub rsrc: Option<PathBuf>,
+    /// Directory to write the bundle to; defaults to `<target-dir>/jk/bundle`
+    pub out_dir: Option<PathBuf>,
+    /// Receives the problems found in the metadata
+    pub events: EventHandler,
+}
+
+/// A plugin installed by [`install`], as reported in the JSON result document.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct InstallOutput {
+    #[serde(flatten)]
+    pub build: BuildOutput,
+    /// Where the plugin was copied to
+    pub installed_path: PathBuf,
+}
+
+/// Emits the final [`EventKind::Finished`] event for a pipeline run.
+fn report_finished(events: &EventHandler, result: Result<Vec<PathBuf>, &JkError>) {
+    events.emit(match result {
+        Ok(plugins) => EventKind::Finished {
+            success: true,
+            plugins,
+            error: None,
+        },
+        Err(e) => EventKind::Finished {
+            success: false,
+            plugins: Vec::new(),
+            error: Some(e.to_string()),
+        },
+    });
+}
+
+/// Builds a plugin for every selected package, each bundled separately.
+pub fn build(options: &BuildOptions) -> Result<Vec<BuildOutput>, JkError> {
+    let result = build_plugins(options);
+    report_finished(
+        &options.events,
+        result
+            .as_ref()
+            .map(|builds| builds.iter().map(|b| b.path.clone()).collect()),
+    );
+    result
+}
+
+fn build_plugins(options: &BuildOptions) -> Result<Vec<BuildOutput>, JkError> {
+    let selected = package::select_packages(&options.selection)?;
+    let target_dir = options
+        .cargo
+        .target_dir
+        .clone()
+        .unwrap_or(selected.target_directory);
+
+    let problems = check::check_packages(&selected.packages, &selected.members);
+    report_problems(&options.events, &problems)?;
+    let target = options.cargo.target_triple()?;
+    let platform =
+        Platform::from_target(&target).ok_or_else(|| JkError::UnsupportedTarget(target.clone()))?;
+
+    // Check every selected package up front so a bad manifest fails before anything is built
+    let plugins = selected
+        .packages
+        .into_iter()
+        .map(|package| {
+            let manifest = PluginManifest::resolve(&package)?;
+            package::cdylib_target(&package)?;
+            let sdk = AeSdk::resolve(options.config.aesdk_root.as_deref(), &manifest)?;
+            options.events.emit(EventKind::MetadataResolved {
+                package: package.name.to_string(),
+                plugin_name: manifest.plugin_name.clone(),
+                identifier: manifest.identifier.clone(),
+                manifest_path: manifest.manifest_path.clone(),
+            });
+            options.events.emit(EventKind::SdkResolved {
+                package: package.name.to_string(),
+                root: sdk.root.clone(),
+                version: sdk.version,
+            });
+            Ok((package, manifest, sdk))
+        })
+        .collect::<Result<Vec<_>, JkError>>()?;
+
+    let mut outputs = plugins
+        .into_iter()
+        .map(|(package, manifest, sdk)| {
+            let pipl = match &manifest.pipl {
+                Some(pipl) => Some(pipl::generate(
+                    &target_dir.join("jk/pipl").join(package.name.as_str()),
+                    &manifest,
+                    pipl,
+                    &sdk,
+                )?),
+                None => None,
+            };
+            build_package(
+                options,
+                &package,
+                &manifest,
+                sdk,
+                pipl.as_ref(),
+                (&target, platform),
+                &target_dir,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if options.symbols == Some(SymbolsMode::Zip) {
+        build::symbols::pack(
+            outputs
+                .iter_mut()
+                .filter_map(|output| output.symbols.as_mut()),
+            &options.events,
+        )?;
+    }
+    Ok(outputs)
+}
+
+/// Assembles the macOS bundle of the selected package from an already built binary, on any host,
+/// and verifies its layout. The bundle is not signed.
+pub fn bundle(options: &BundleOptions) -> Result<MacBundle, JkError> {
+    let selected = package::select_packages(&options.selection)?;
+    let [package] = selected.packages.as_slice() else {
+        return Err(JkError::NoPackageSelected {
+            candidates: selected
+                .packages
+                .iter()
+                .map(|package| package.name.to_string())
+                .collect(),
+        });
+    };
+    let problems = check::check_packages(&selected.packages, &selected.members);
+    report_problems(&options.events, &problems)?;
+    let manifest = PluginManifest::resolve(package)?;
+
+    let target_dir = selected.target_directory;
+    let rsrc = match (&options.rsrc, &manifest.pipl) {
+        (Some(rsrc), _) => rsrc.clone(),
+        (None, Some(pipl)) => {
+            let sdk = AeSdk::resolve(options.config.aesdk_root.as_deref(), &manifest)?;
+            pipl::generate(
+                &target_dir.join("jk/pipl").join(package.name.as_str()),
+                &manifest,
+                pipl,
+                &sdk,
+            )?
+            .rsrc
+        }
+        (None, None) => {
+            return Err(JkError::NoPipl {
+                package: manifest.package,
+            });
+        }
+    };
+
+    let out_dir = options
+        .out_dir
+        .clone()
+        .unwrap_or_else(|| target_dir.join("jk/bundle"));
+    let contents = BundleContents::new(&manifest, options.executable.clone(), rsrc);
+    let bundle = build::bundle::assemble(&out_dir, &manifest, &contents)?;
+    build::bundle::verify(&bundle.path)
+}
+
+/// Checks the plugin metadata of the selected packages without building them.
+pub fn check_metadata(selection: &PackageSelection) -> Result<Vec<Problem>, JkError> {
+    let selected = package::select_packages(selection)?;
+    Ok(check::check_packages(&selected.packages, &selected.members))
+}
+
+/// Reports the problems found by the metadata check, failing if any of them is an error.
+fn report_problems(events: &EventHandler, problems: &[Problem]) -> Result<(), JkError> {
+    for problem in problems {
+        let Problem {
+            package,
+            message,
+            location,
+            ..
+        } = problem.clone();
+        events.emit(match problem.severity {
+            Severity::Error => EventKind::MetadataError {
+                package,
+                message,
+                location,
+            },
+            Severity::Warning => EventKind::Warning {
+                package,
+                message,
+                location,
+            },
+        });
+    }
+    match problems
+        .iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .count()
+    {
+        0 => Ok(()),
+        errors => Err(JkError::MetadataCheck { errors }),
+    }
+}
+
+/// Picks the dynamic library out of a cdylib artifact's files, skipping `.rlib`s, import
+/// libraries and debug info.
+fn cdylib_filename(artifact: &Artifact, platform: Platform) -> Option<PathBuf> {
+    artifact
+        .filenames
+        .iter()
+        .find(|f| f.extension() == Some(platform.dll_extension()))
+        .map(|f| f.clone().into())
+}
+
+fn build_package(
+    options: &BuildOptions,
+    package: &cargo_metadata::Package,
+    manifest: &PluginManifest,
+    sdk: AeSdk,
+    pipl: Option<&PiplResources>,
+    (target, platform): (&str, Platform),
+    target_dir: &Path,
+) -> Result<BuildOutput, JkError> {
+    // Without --target, the architectures of a macOS plugin are chosen by --arch or the metadata
+    let arch = match platform {
+        Platform::MacOs if options.cargo.target.is_none() => options
+            .arch
+            .or(manifest.macos.arch)
+            .or_else(|| options.cargo.is_release().then_some(MacArch::Universal)),
+        _ => None,
+    };
+    if options.arch.is_some() && arch.is_none() {
+        options.events.emit(EventKind::Warning {
+            package: package.name.to_string(),
+            message: format!("--arch only applies to macOS plugins; building for {target}"),
+            location: None,
+        });
+    }
+    // Windows resources are linked by cargo-jk itself, so plugins need no build script for them
+    let resources = match platform {
+        Platform::Windows => {
+            let version_info = pipl::res::generate_version_info(
+                &target_dir
+                    .join("jk/versioninfo")
+                    .join(package.name.as_str()),
+                manifest,
+            )?;
+            pipl.map(|pipl| pipl.res.clone())
+                .into_iter()
+                .chain([version_info.res])
+                .collect()
+        }
+        Platform::MacOs => Vec::new(),
+    };
+    let cdylibs = match arch.map(MacArch::targets) {
+        Some(&[arch_target]) if arch_target != target => {
+            cargo::ensure_targets(&[arch_target], &options.events, &package.name)?;
+            vec![(
+                arch_target.to_string(),
+                cargo_build(options, package, &resources, Some(arch_target), platform)?,
+            )]
+        }
+        Some(targets @ &[_, _, ..]) => {
+            cargo::ensure_targets(targets, &options.events, &package.name)?;
+            let cdylibs =
+                cargo_build_concurrently(options, package, &resources, targets, platform)?;
+            targets
+                .iter()
+                .map(|target| target.to_string())
+                .zip(cdylibs)
+                .collect()
+        }
+        // A plain host build keeps its artifacts where `cargo build` puts them
+        _ => vec![(
+            target.to_string(),
+            cargo_build(options, package, &resources, None, platform)?,
+        )],
+    };
+
+    let bundle = build::post_build_process(options, target_dir, &cdylibs, manifest, pipl)?;
+    let (sha256, size) =
+        report::digest_file(&bundle.executable).map_err(JkError::bundle(&bundle.executable))?;
+    Ok(BuildOutput {
+        package: package.name.to_string(),
+        version: manifest.version.to_string(),
+        plugin_name: manifest.plugin_name.clone(),
+        identifier: manifest.identifier.clone(),
+        profile: options.cargo.profile().to_string(),
+        path: bundle.path,
+        cdylib: bundle.cdylib,
+        architectures: bundle
+            .targets
+            .iter()
+            .map(|t| build::architecture(t))
+            .collect(),
+        targets: bundle.targets,
+        signing: bundle.signing,
+        sha256,
+        size,
+        sdk,
+        symbols: bundle.symbols,
+    })
+}
+
+/// Runs cargo for `package`, streaming its output, and returns the cdylib it built. `resources`
+/// are linked into it.
+fn cargo_build(
+    options: &BuildOptions,
+    package: &cargo_metadata::Package,
+    resources: &[PathBuf],
+    target: Option<&str>,
+    platform: Platform,
+) -> Result<PathBuf, JkError> {
+    let events = &options.events;
+    // Compiler diagnostics come back as JSON when someone listens for them
+    let mut command = options.cargo.command(
+        &package.name,
+        options.selection.manifest_path.as_deref(),
+        target.or(options.cargo.target.as_deref()),
+        resources,
+        events.is_enabled(),
+    );
+    command.stdout(Stdio::piped());
+    events.emit(EventKind::CargoStarted {
+        package: package.name.to_string(),
+        target: target.map(str::to_string).or(options.cargo.target.clone()),
+        command: format!("{command:?}"),
+    });
+    let mut child = command.spawn().map_err(JkError::spawn(&command))?;
+
+    let reader = io::BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let filename = read_messages(reader, package, platform, events)?;
+
+    let status = child.wait().map_err(JkError::CargoOutput)?;
+    if !status.success() {
+        return Err(JkError::CargoFailed(status));
+    }
+    filename.ok_or_else(|| JkError::NoArtifact {
+        package: package.name.to_string(),
+    })
+}
+
+/// Runs one cargo per target at the same time and returns their cdylibs, in the order of
+/// `targets`.
+///
+/// The builds share the target directory, where cargo keeps each triple's artifacts apart and
+/// builds the host dependencies (build scripts, proc macros) once; cargo's lock on the directory
+/// orders the steps that touch them. Each build's output is captured separately; if a build
+/// fails, only that target's diagnostics are printed.
+fn cargo_build_concurrently(
+    options: &BuildOptions,
+    package: &cargo_metadata::Package,
+    resources: &[PathBuf],
+    targets: &[&str],
+    platform: Platform,
+) -> Result<Vec<PathBuf>, JkError> {
+    let events = &options.events;
+    let outputs = std::thread::scope(|scope| {
+        let builds: Vec<_> = targets
+            .iter()
+            .map(|&target| {
+                let mut command = options.cargo.command(
+                    &package.name,
+                    options.selection.manifest_path.as_deref(),
+                    Some(target),
+                    resources,
+                    true,
+                );
+                events.emit(EventKind::CargoStarted {
+                    package: package.name.to_string(),
+                    target: Some(target.to_string()),
+                    command: format!("{command:?}"),
+                });
+                scope.spawn(move || command.output().map_err(JkError::spawn(&command)))
+            })
+            .collect();
+        builds
+            .into_iter()
+            .zip(targets)
+            .map(|(build, target)| {
+                build.join().unwrap_or_else(|_| {
+                    Err(JkError::BuildThreadPanicked {
+                        target: target.to_string(),
+                    })
+                })
+            })
+            .collect::<Vec<_>>()
+    });
+    let outputs = outputs.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+    if let Some((target, output)) = targets
+        .iter()
+        .zip(&outputs)
+        .find(|(_, output)| !output.status.success())
+    {
+        read_messages(&output.stdout[..], package, platform, events)?;
+        io::stderr()
+            .write_all(&output.stderr)
+            .map_err(JkError::CargoOutput)?;
+        return Err(JkError::TargetBuildFailed {
+            target: target.to_string(),
+            status: output.status,
+        });
+    }
+    outputs
+        .iter()
+        .map(|output| {
+            read_messages(&output.stdout[..], package, platform, events)?.ok_or_else(|| {
+                JkError::NoArtifact {
+                    package: package.name.to_string(),
+                }
+            })
+        })
+        .collect()
+}
+
+/// Reads cargo's JSON messages, reporting compiler diagnostics, and returns the plugin crate's
+/// cdylib if it was built.
+fn read_messages(
+    reader: impl io::BufRead,
+    package: &cargo_metadata::Package,
+    platform: Platform,
+    events: &EventHandler,
+) -> Result<Option<PathBuf>, JkError> {
+    let mut filename = None;
+    for message in Message::parse_stream(reader) {
+        match message.map_err(JkError::CargoOutput)? {
+            // Dependencies are reported too; only the plugin crate's own cdylib is of interest
+            Message::CompilerArtifact(artifact)
+                if artifact.package_id == package.id && artifact.target.is_cdylib() =>
+            {
+                filename = cdylib_filename(&artifact, platform);
+                if let Some(path) = &filename {
+                    events.emit(EventKind::Artifact {
+                        package: package.name.to_string(),
+                        path: path.clone(),
+                    });
+                }
+            }
+            Message::CompilerMessage(message) => {
+                let diagnostic = message.message;
+                events.emit(EventKind::CompilerDiagnostic {
+                    package: message.target.name.clone(),
+                    level: diagnostic.level,
+                    message: diagnostic.message,
+                    rendered: diagnostic.rendered,
+                });
+            }
+            _ => (), // Unknown message
+        }
+    }
+    Ok(filename)
+}
+
+/// Builds the selected plugins and copies them into the Adobe plugin directory, elevating if
+/// needed.
+pub fn install(options: &InstallOptions) -> Result<Vec<InstallOutput>, JkError> {
+    let result = install_plugins(options);
+    report_finished(
+        &options.build.events,
+        result
+            .as_ref()
+            .map(|installs| installs.iter().map(|i| i.installed_path.clone()).collect()),
+    );
+    result
+}
+
+fn install_plugins(options: &InstallOptions) -> Result<Vec<InstallOutput>, JkError> {
+    if !mv::is_supported_host() {
+        return Err(JkError::UnsupportedHost {
+            operation: "installing plugins".to_string(),
+        });
+    }
+    let builds = build_plugins(&options.build)?;
+
+    builds
+        .into_iter()
+        .map(|build| {
+            let installed_path = mv::install_plugin(
+                &build.path,
+                options.build.config.install.destination.as_deref(),
+                &options.build.events,
+                &build.package,
+            )?;
+            options.build.events.emit(EventKind::Installed {
+                package: build.package.clone(),
+                path: build.path.clone(),
+                destination: installed_path.clone(),
+            });
+            Ok(InstallOutput {
+                build,
+                installed_path,
+            })
+        })
+        .collect()
+}
@@ -1,133 +1,207 @@
-mod build;
-mod command;
-mod mv;
-
-use crate::command::{Cargo, JKCommand};
-use cargo_metadata::Message;
-use cargo_metadata::MetadataCommand;
+use cargo_jk::command::{
+    Cargo, CargoArgs, Format, Input, JKCommand, PackageArgs, PiplCommand, SchemaDocument,
+};
+use cargo_jk::{
+    BuildOptions, BuildReport, BundleOptions, CargoOptions, Config, Event, EventHandler, EventKind,
+    InstallOptions, InstallReport, JkError, NotarizeOptions, PackageOptions, PackageSelection,
+    Problem, Severity, mv, pipl,
+    scaffold::{self, ScaffoldOptions},
+};
 use clap::Parser;
-use serde::{Deserialize, Serialize};
-use serde_json;
-use std::env;
+use serde::Serialize;
 use std::io;
 use std::io::Write;
-use std::path::PathBuf;
-use std::process::Command;
-use std::process::Stdio;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Deserialize)]
-struct JkPluginMetadata {
-    plugin_name: String,
-    identifier: String,
+fn main() {
+    let Cargo::Input(input) = Cargo::parse();
+    if let Err(e) = run(input) {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct PluginOutput {
-    path: String,
+/// Loads the configuration for the workspace at `manifest_path`, or the current directory.
+fn load_config(manifest_path: Option<&Path>, overrides: &[String]) -> Result<Config, JkError> {
+    let cwd = std::env::current_dir().map_err(JkError::CurrentDir)?;
+    let dir = match manifest_path.and_then(Path::parent) {
+        Some(dir) => cwd.join(dir),
+        None => cwd,
+    };
+    Config::load(&dir, overrides)
+}
+
+/// Prints the progress of the pipeline to stderr.
+fn print_event(event: &Event) {
+    let problem = |severity, package: &String, message: &String, location: &Option<_>| Problem {
+        severity,
+        package: package.clone(),
+        message: message.clone(),
+        location: location.clone(),
+    };
+    match &event.kind {
+        EventKind::MetadataResolved { plugin_name, .. } => {
+            eprintln!("Plugin Name: {plugin_name}")
+        }
+        EventKind::SdkResolved {
+            root,
+            version: Some(version),
+            ..
+        } => eprintln!("After Effects SDK: {} (version {version})", root.display()),
+        EventKind::SdkResolved { root, .. } => {
+            eprintln!("After Effects SDK: {} (unknown version)", root.display())
+        }
+        EventKind::Warning {
+            package,
+            message,
+            location,
+        } => eprintln!("{}", problem(Severity::Warning, package, message, location)),
+        EventKind::MetadataError {
+            package,
+            message,
+            location,
+        } => eprintln!("{}", problem(Severity::Error, package, message, location)),
+        EventKind::CargoStarted { command, .. } | EventKind::ToolStarted { command, .. } => {
+            eprintln!("Executing: {command}")
+        }
+        EventKind::CompilerDiagnostic {
+            rendered: Some(rendered),
+            ..
+        } => eprint!("{rendered}"),
+        EventKind::BundleCreated { path, .. } => eprintln!("Built plugin: {}", path.display()),
+        EventKind::SymbolsCollected { path, .. } => {
+            eprintln!("Wrote debug symbols: {}", path.display())
+        }
+        EventKind::SymbolsPacked { path } => {
+            eprintln!("Packed debug symbols: {}", path.display())
+        }
+        EventKind::NotarizationSubmitted { path, id, size } => {
+            eprintln!("Submitted {} as {id} ({size} bytes)", path.display())
+        }
+        EventKind::NotarizationPending { status, .. } => {
+            eprintln!("Waiting for the notary service ({status})...")
+        }
+        EventKind::NotarizationFinished {
+            id,
+            status,
+            log,
+            log_error,
+        } => {
+            eprintln!("Notarization {id}: {status}");
+            if let Some(log) = log {
+                print_notarization_log(log);
+            }
+            if let Some(e) = log_error {
+                eprintln!("warning: cannot fetch the notarization log: {e}");
+            }
+        }
+        EventKind::Stapled { path } => {
+            eprintln!("Stapled the ticket to {}", path.display())
+        }
+        _ => {}
+    }
 }
 
-fn package_for_cwd() -> cargo_metadata::Package {
-    let cwd = std::env::current_dir().unwrap();
-    let meta = MetadataCommand::new().no_deps().exec().unwrap();
+/// Prints the issues of a notarization log, or the whole log if it has an unexpected shape.
+fn print_notarization_log(log: &serde_json::Value) {
+    let Some(issues) = log["issues"].as_array() else {
+        eprintln!(
+            "{}",
+            serde_json::to_string_pretty(log).unwrap_or_else(|_| log.to_string())
+        );
+        return;
+    };
+    if let Some(summary) = log["statusSummary"].as_str() {
+        eprintln!("{summary}");
+    }
+    for issue in issues {
+        let field = |key: &str| issue[key].as_str().unwrap_or_default();
+        let architecture = match field("architecture") {
+            "" => String::new(),
+            architecture => format!(" ({architecture})"),
+        };
+        eprintln!(
+            "{}: {}{architecture}: {}",
+            field("severity"),
+            field("path"),
+            field("message")
+        );
+        if let Some(url) = issue["docUrl"].as_str() {
+            eprintln!("  see {url}");
+        }
+    }
+}
 
-    let mut pkgs: Vec<_> = meta
-        .packages
-        .into_iter()
-        .filter(|p| {
-            let manifest_dir = p.manifest_path.parent().unwrap();
-            cwd.starts_with(manifest_dir)
-        })
-        .collect();
+/// Combines the command line with the configuration, returning the options and output format.
+fn build_options(
+    cargo: CargoArgs,
+    packages: PackageArgs,
+    format: Option<Format>,
+    overrides: &[String],
+) -> Result<(BuildOptions, Format), JkError> {
+    let config = load_config(packages.manifest_path.as_deref(), overrides)?;
+    let arch = cargo.arch;
+    let symbols = cargo.symbols;
+    let mut cargo: CargoOptions = cargo.into();
+    if !cargo.release && cargo.profile.is_none() {
+        cargo.profile = config.profile.clone();
+    }
+    let format = format.or(config.format).unwrap_or(Format::None);
+    let events = match format {
+        Format::Ndjson => EventHandler::new(|event| {
+            print_event(event);
+            let line = serde_json::to_string(event).expect("Failed to serialize event to JSON");
+            let mut stdout = io::stdout().lock();
+            let _ = writeln!(stdout, "{line}");
+            let _ = stdout.flush();
+        }),
+        _ => EventHandler::new(print_event),
+    };
+    let options = BuildOptions {
+        cargo,
+        arch,
+        symbols,
+        selection: packages.into(),
+        config,
+        events,
+    };
+    Ok((options, format))
+}
 
-    pkgs.sort_by_key(|p| p.manifest_path.components().count());
-    pkgs.pop().unwrap()
+fn print_json<T: Serialize>(value: &T) {
+    let output = serde_json::to_string_pretty(value).expect("Failed to serialize output to JSON");
+    println!("{}", output);
 }
 
-fn main() {
-    let Cargo::Input(input) = Cargo::parse();
-    // let ostype = env::consts::OS;
-    // println!("Operating System: {}", ostype);
+fn run(input: Input) -> Result<(), JkError> {
     match input.cmd {
         JKCommand::Build(build) => {
-            let _aesdk_root = env::var("AESDK_ROOT")
-                .expect("AESDK_ROOT is not defined as an environment variable");
-            let package = package_for_cwd();
-
-            let jk_pluging_metadata_value = package
-                .metadata
-                .get("jk_plugin")
-                .expect("no [package.metadata.jk_plugin] section in Cargo.toml")
-                .clone();
-            let jk_plugin_metadata: JkPluginMetadata =
-                serde_json::from_value(jk_pluging_metadata_value)
-                    .map_err(|e| {
-                        io::Error::other(format!("Failed to parse jk_plugin metadata: {}", e))
-                    })
-                    .unwrap();
-
-            eprintln!("Plugin Name: {}", jk_plugin_metadata.plugin_name);
-            let mut command = Command::new("cargo");
-            command.arg("build");
-            if build.release {
-                command.arg("--release");
-            }
-            command.arg("--message-format");
-            command.arg("json-render-diagnostics");
-            command.stdout(Stdio::piped());
-            eprintln!("Executing: {:?}", command);
-            match command.spawn() {
-                Ok(mut child) => {
-                    let reader = io::BufReader::new(child.stdout.take().unwrap());
-                    let mut filename: Option<PathBuf> = None;
-                    for message in cargo_metadata::Message::parse_stream(reader) {
-                        match message.unwrap() {
-                            Message::CompilerArtifact(artifact) => {
-                                if let Some(first) = artifact.filenames.get(0) {
-                                    filename = Some(first.clone().into());
-                                }
-                            }
-                            _ => (), // Unknown message
-                        }
-                    }
-                    let filename = filename.expect("No artifact filename found after build");
-
-                    let status = child.wait().expect("Failed to wait on child process");
-                    if status.success() {
-                        let plugin_path: PathBuf = build::post_build_process(
-                            &build,
-                            &filename,
-                            &package.name,
-                            &jk_plugin_metadata,
-                        );
-                        eprintln!("Build succeeded.");
-                        // check format argument
-                        match build.format {
-                            command::Format::Json => {
-                                let plugin_output = PluginOutput {
-                                    path: plugin_path.to_string_lossy().to_string(),
-                                };
-                                let output = serde_json::to_string(&plugin_output)
-                                    .expect("Failed to serialize output to JSON");
-                                println!("{}", output);
-                            }
-                            command::Format::None => {
-                                // nothing to do
-                            }
-                        }
-                    } else {
-                        eprintln!("Build failed with status: {}", status);
-                        std::process::exit(1);
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Failed to execute command: {}", e);
-                    std::process::exit(1);
-                }
+            let (options, format) =
+                build_options(build.cargo, build.packages, build.format, &input.config)?;
+            let outputs = cargo_jk::build(&options)?;
+            for output in &outputs {
+                eprintln!(
+                    "Build succeeded: {} ({})",
+                    output.plugin_name, output.package
+                );
+            }
+            if format == Format::Json {
+                print_json(&BuildReport::new(outputs));
             }
         }
         JKCommand::MV(mv) => {
+            if !mv::is_supported_host() {
+                return Err(JkError::UnsupportedHost {
+                    operation: "installing plugins".to_string(),
+                });
+            }
             if mv::is_elevated() {
-                match mv::mv_command(&mv) {
+                let destination: Option<PathBuf> = match mv.destination {
+                    Some(destination) => Some(destination),
+                    None => load_config(None, &input.config)?.install.destination,
+                };
+                let result = mv::mv_command(Path::new(&mv.src), destination.as_deref());
+                match &result {
                     Ok(_) => eprintln!("File moved successfully."),
                     Err(e) => eprintln!("Failed to move file: {e}"),
                 }
@@ -135,97 +209,153 @@ fn main() {
                 println!("Press Enter to exit...");
                 let _ = io::stdout().flush();
                 let _ = io::stdin().read_line(&mut String::new());
+                result?;
             } else {
                 eprintln!("Not running with elevated privileges. Attempting to elevate...");
-                mv::elevate_self();
+                mv::elevate_self()?;
             }
         }
         JKCommand::Install(install) => {
-            install_command(install.release);
+            let (build, format) = build_options(
+                install.cargo,
+                install.packages,
+                install.format,
+                &input.config,
+            )?;
+            let outputs = cargo_jk::install(&InstallOptions { build })?;
+            for output in &outputs {
+                eprintln!(
+                    "Installed: {} ({})",
+                    output.build.plugin_name,
+                    output.installed_path.display()
+                );
+            }
+            eprintln!("Install completed successfully!");
+            if format == Format::Json {
+                print_json(&InstallReport::new(outputs));
+            }
         }
-    }
-}
-
-fn install_command(release: bool) {
-    eprintln!("Starting install process...");
-
-    // Detect if we're running in development mode (cargo run -- jk) or production mode (cargo jk)
-    let current_exe = env::current_exe().expect("Failed to get current executable path");
-    let is_dev_mode = current_exe.to_string_lossy().contains("target");
-
-    let (cmd_prefix, cmd_args): (&str, Vec<&str>) = if is_dev_mode {
-        ("cargo", vec!["run", "--", "jk"])
-    } else {
-        ("cargo", vec!["jk"])
-    };
-
-    // Step 1: Execute build command
-    let mut build_command = Command::new(cmd_prefix);
-    for arg in &cmd_args {
-        build_command.arg(arg);
-    }
-    build_command.arg("build");
-    if release {
-        build_command.arg("--release");
-    }
-    let build_output = build_command.arg("--format").arg("json").output();
-
-    match build_output {
-        Ok(output) => {
-            if !output.status.success() {
-                eprintln!("Build failed: {}", String::from_utf8_lossy(&output.stderr));
-                std::process::exit(1);
+        JKCommand::Schema(schema) => match schema.document {
+            SchemaDocument::Build => print_json(&BuildReport::schema()),
+            SchemaDocument::Install => print_json(&InstallReport::schema()),
+        },
+        JKCommand::Bundle(bundle) => {
+            let config = load_config(bundle.manifest_path.as_deref(), &input.config)?;
+            let options = BundleOptions {
+                selection: PackageSelection {
+                    packages: bundle.package.into_iter().collect(),
+                    workspace: false,
+                    manifest_path: bundle.manifest_path,
+                },
+                config,
+                executable: bundle.executable,
+                rsrc: bundle.rsrc,
+                out_dir: bundle.out_dir,
+                events: EventHandler::new(print_event),
+            };
+            let bundle = cargo_jk::bundle(&options)?;
+            eprintln!("Bundle assembled: {}", bundle.path.display());
+        }
+        JKCommand::Notarize(notarize) => {
+            let config = load_config(None, &input.config)?;
+            let output = cargo_jk::notarize(&NotarizeOptions {
+                plugin: notarize.plugin,
+                config: config.notarize,
+                staple: !notarize.no_staple,
+                events: EventHandler::new(print_event),
+            })?;
+            if notarize.format == Some(Format::Json) {
+                print_json(&output);
             }
-
-            // Step 2: Parse the JSON output to get the plugin path
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            eprintln!("Build output: {}", stdout);
-
-            match serde_json::from_str::<PluginOutput>(&stdout) {
-                Ok(plugin_output) => {
-                    let plugin_path = &plugin_output.path;
-                    eprintln!("Built plugin: {}", plugin_path);
-
-                    // Step 3: Execute mv command
-                    let mv_cmd =
-                        format!("{} {} mv {}", cmd_prefix, cmd_args.join(" "), plugin_path);
-                    eprintln!("Running: {}", mv_cmd);
-
-                    let mut mv_command = Command::new(cmd_prefix);
-                    for arg in &cmd_args {
-                        mv_command.arg(arg);
-                    }
-                    let mv_output = mv_command.arg("mv").arg(plugin_path).output();
-
-                    match mv_output {
-                        Ok(mv_result) => {
-                            if mv_result.status.success() {
-                                eprintln!("Install completed successfully!");
-                                eprintln!("{}", String::from_utf8_lossy(&mv_result.stdout));
-                            } else {
-                                eprintln!(
-                                    "Move failed: {}",
-                                    String::from_utf8_lossy(&mv_result.stderr)
-                                );
-                                std::process::exit(1);
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to execute move command: {}", e);
-                            std::process::exit(1);
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Failed to parse build output JSON: {}", e);
-                    eprintln!("Raw output: {}", stdout);
-                    std::process::exit(1);
+        }
+        JKCommand::Package(package) => {
+            let config = load_config(package.packages.manifest_path.as_deref(), &input.config)?;
+            let mut cargo = CargoOptions {
+                release: package.release,
+                profile: package.profile,
+                target_dir: package.target_dir,
+                ..CargoOptions::default()
+            };
+            if !cargo.release && cargo.profile.is_none() {
+                cargo.profile = config.profile;
+            }
+            let packages = cargo_jk::package_plugins(&PackageOptions {
+                selection: package.packages.into(),
+                cargo,
+                plugins: package.plugins,
+                format: package.archive,
+                out_dir: package.out_dir,
+            })?;
+            for archive in &packages.archives {
+                eprintln!("Packaged: {}", archive.path.display());
+            }
+            eprintln!("Checksums: {}", packages.checksums.display());
+            if package.format == Some(Format::Json) {
+                print_json(&packages);
+            }
+        }
+        JKCommand::New(new) => {
+            let options = ScaffoldOptions {
+                path: new.path,
+                name: new.name,
+                plugin_name: new.plugin.plugin_name,
+                identifier: new.plugin.identifier,
+                template: new.plugin.template,
+            };
+            scaffold::new_package(&options)?;
+            eprintln!("Created plugin crate at {}", options.path.display());
+        }
+        JKCommand::Init(init) => {
+            let options = ScaffoldOptions {
+                path: init.path,
+                name: None,
+                plugin_name: init.plugin.plugin_name,
+                identifier: init.plugin.identifier,
+                template: init.plugin.template,
+            };
+            let written = scaffold::init_package(&options)?;
+            for path in &written {
+                eprintln!("Wrote {}", path.display());
+            }
+            let lib_rs = options.path.join("src/lib.rs");
+            let entry_point = options.template.entry_point();
+            let existing = std::fs::read_to_string(&lib_rs).unwrap_or_default();
+            if !written.contains(&lib_rs) && !existing.contains(entry_point) {
+                eprintln!(
+                    "note: {} already exists; the plugin must export `{entry_point}` from it",
+                    lib_rs.display()
+                );
+            }
+        }
+        JKCommand::CheckMetadata(check) => {
+            let problems = cargo_jk::check_metadata(&check.packages.into())?;
+            if check.format == Some(Format::Json) {
+                print_json(&problems);
+            } else {
+                for problem in &problems {
+                    eprintln!("{problem}");
                 }
             }
+            let errors = problems
+                .iter()
+                .filter(|problem| problem.severity == Severity::Error)
+                .count();
+            if errors > 0 {
+                return Err(JkError::MetadataCheck { errors });
+            }
+            eprintln!("Plugin metadata OK");
         }
-        Err(e) => {
-            eprintln!("Failed to execute build command: {}", e);
-            std::process::exit(1);
+        JKCommand::Pipl(PiplCommand::Dump(dump)) => {
+            let embedded = pipl::read_plugin(&dump.path)?;
+            if dump.format == Some(Format::Json) {
+                print_json(&embedded);
+            } else {
+                println!("{} ({})", embedded.path.display(), embedded.platform);
+                for property in &embedded.pipl.properties {
+                    println!("  {property}");
+                }
+            }
         }
     }
+    Ok(())
 }
@@ -0,0 +1,352 @@
+//! The plugin manifest: `[package.metadata.jk_plugin]` resolved against the rest of the package.
+//!
+//! Every build step (bundle, Info.plist, PiPL, packaging) reads the plugin's names, version and
+//! resources from the [`PluginManifest`], so fields that are not set fall back to the package's
+//! `version`, `description` and `authors` in one place.
+
+use std::path::{Path, PathBuf};
+
+use cargo_metadata::{Package, semver::Version};
+use serde_json::Value;
+
+use crate::{
+    JkPluginMetadata,
+    error::JkError,
+    package::{MacOsMetadata, WindowsMetadata},
+    pipl::{PiplMetadata, PluginKind},
+};
+
+/// A plugin's `[package.metadata.jk_plugin]` section with every default filled in.
+#[derive(Debug, Clone)]
+pub struct PluginManifest {
+    /// Name of the cargo package
+    pub package: String,
+    /// The package's `Cargo.toml`
+    pub manifest_path: PathBuf,
+    pub plugin_name: String,
+    pub identifier: String,
+    pub version: Version,
+    pub display_name: String,
+    pub category: Option<String>,
+    pub match_name: String,
+    pub kind: PluginKind,
+    pub min_host_version: Option<String>,
+    pub copyright: Option<String>,
+    pub description: Option<String>,
+    /// Package authors, without their e-mail addresses
+    pub authors: Vec<String>,
+    /// Extra files shipped on every platform, as absolute paths
+    pub resources: Vec<PathBuf>,
+    /// Documents put in package archives next to the plugin, as absolute paths
+    pub documents: Vec<PathBuf>,
+    pub aesdk_root: Option<PathBuf>,
+    pub pipl: Option<PiplMetadata>,
+    /// macOS settings, with `resources` and `info_plist_template` made absolute
+    pub macos: MacOsMetadata,
+    /// Windows settings, with `resources` made absolute and `company_name` defaulted
+    pub windows: WindowsMetadata,
+}
+
+impl PluginManifest {
+    /// Resolves the manifest of a package. Unknown keys are ignored here; the
+    /// [metadata check](crate::check) warns about them.
+    pub fn resolve(package: &Package) -> Result<PluginManifest, JkError> {
+        let metadata = JkPluginMetadata::from_package(package)?;
+        let version = match &metadata.version {
+            Some(version) => Version::parse(version).map_err(|e| JkError::InvalidManifest {
+                package: package.name.to_string(),
+                message: format!("invalid version `{version}`: {e}"),
+            })?,
+            None => package.version.clone(),
+        };
+        let authors: Vec<String> = package
+            .authors
+            .iter()
+            .map(|author| match author.split_once('<') {
+                Some((name, _)) => name.trim().to_string(),
+                None => author.trim().to_string(),
+            })
+            .filter(|name| !name.is_empty())
+            .collect();
+        let copyright = metadata.copyright.or_else(|| {
+            (!authors.is_empty()).then(|| format!("Copyright © {}", authors.join(", ")))
+        });
+
+        let company_name = metadata
+            .windows
+            .company_name
+            .or_else(|| (!authors.is_empty()).then(|| authors.join(", ")));
+
+        let dir = package
+            .manifest_path
+            .parent()
+            .map_or_else(PathBuf::new, |dir| dir.as_std_path().to_path_buf());
+        let absolute = |paths: Vec<PathBuf>| -> Vec<PathBuf> {
+            paths.into_iter().map(|path| dir.join(path)).collect()
+        };
+
+        let manifest = PluginManifest {
+            package: package.name.to_string(),
+            manifest_path: package.manifest_path.clone().into(),
+            display_name: metadata
+                .display_name
+                .unwrap_or_else(|| metadata.plugin_name.clone()),
+            match_name: metadata
+                .match_name
+                .unwrap_or_else(|| metadata.plugin_name.clone()),
+            plugin_name: metadata.plugin_name,
+            identifier: metadata.identifier,
+            version,
+            category: metadata.category,
+            kind: metadata.kind.unwrap_or_default(),
+            min_host_version: metadata.min_host_version,
+            copyright,
+            description: metadata.description.or_else(|| package.description.clone()),
+            authors,
+            resources: absolute(metadata.resources),
+            documents: match metadata.documents {
+                Some(documents) => absolute(documents),
+                None => find_documents(package, &dir),
+            },
+            aesdk_root: metadata.aesdk_root.map(|root| dir.join(root)),
+            pipl: metadata.pipl,
+            macos: MacOsMetadata {
+                resources: absolute(metadata.macos.resources),
+                info_plist_template: metadata
+                    .macos
+                    .info_plist_template
+                    .map(|path| dir.join(path)),
+                ..metadata.macos
+            },
+            windows: WindowsMetadata {
+                resources: absolute(metadata.windows.resources),
+                company_name,
+            },
+        };
+        Ok(manifest)
+    }
+
+    /// Directory of the package's `Cargo.toml`.
+    pub fn manifest_dir(&self) -> &Path {
+        self.manifest_path.parent().unwrap_or(Path::new("."))
+    }
+}
+
+/// Beginnings of the file names taken for documents, in upper case.
+const DOCUMENT_PREFIXES: &[&str] = &[
+    "README",
+    "LICENSE",
+    "LICENCE",
+    "COPYING",
+    "NOTICE",
+    "THIRD-PARTY",
+    "THIRD_PARTY",
+    "THIRDPARTY",
+];
+
+/// The package's `readme` and `license-file`, then the files in `dir` named like a README,
+/// license or notice, in name order. Only the first file of each name is kept.
+fn find_documents(package: &Package, dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let name = name.to_uppercase();
+            path.is_file()
+                && DOCUMENT_PREFIXES
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix))
+        })
+        .collect();
+    files.sort();
+
+    let mut documents: Vec<PathBuf> = Vec::new();
+    for path in [package.readme(), package.license_file()]
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .chain(files)
+    {
+        if !documents
+            .iter()
+            .any(|document| document.file_name() == path.file_name())
+        {
+            documents.push(path);
+        }
+    }
+    documents
+}
+
+/// A key of the metadata section and, for tables, the keys inside it.
+pub(crate) struct Key {
+    pub name: &'static str,
+    pub table: &'static [Key],
+}
+
+const fn key(name: &'static str) -> Key {
+    Key { name, table: &[] }
+}
+
+/// Every key of `[package.metadata.jk_plugin]`.
+pub(crate) const METADATA_KEYS: &[Key] = &[
+    key("plugin_name"),
+    key("identifier"),
+    key("version"),
+    key("display_name"),
+    key("category"),
+    key("match_name"),
+    key("kind"),
+    key("min_host_version"),
+    key("copyright"),
+    key("description"),
+    key("resources"),
+    key("documents"),
+    key("aesdk_root"),
+    Key {
+        name: "pipl",
+        table: &[
+            key("entry_point"),
+            Key {
+                name: "entry_points",
+                table: &[
+                    key("macos_x86_64"),
+                    key("macos_arm64"),
+                    key("windows_x86_64"),
+                ],
+            },
+            Key {
+                name: "spec_version",
+                table: &[key("major"), key("minor")],
+            },
+            key("info_flags"),
+            key("out_flags"),
+            key("out_flags2"),
+            key("reserved_info"),
+        ],
+    },
+    Key {
+        name: "macos",
+        table: &[
+            key("arch"),
+            key("minimum_system_version"),
+            key("resources"),
+            key("info_plist_template"),
+            // Any key is allowed in here
+            key("info_plist"),
+        ],
+    },
+    Key {
+        name: "windows",
+        table: &[key("resources"), key("company_name")],
+    },
+];
+
+/// A key cargo-jk does not know, probably a typo.
+#[derive(Debug, Clone)]
+pub(crate) struct UnknownKey {
+    /// Path below `jk_plugin`, e.g. `["pipl", "out_flag2"]`
+    pub path: Vec<String>,
+    /// The known key it is closest to
+    pub suggestion: Option<&'static str>,
+}
+
+/// Collects every key of `value` that is not in `keys`.
+pub(crate) fn unknown_keys(value: &Value, keys: &[Key], path: &[String]) -> Vec<UnknownKey> {
+    let Value::Object(table) = value else {
+        return Vec::new();
+    };
+    let mut unknown = Vec::new();
+    for (name, value) in table {
+        let mut key_path = path.to_vec();
+        key_path.push(name.clone());
+        match keys.iter().find(|key| key.name == name) {
+            Some(key) if !key.table.is_empty() => {
+                unknown.extend(unknown_keys(value, key.table, &key_path));
+            }
+            Some(_) => {}
+            None => unknown.push(UnknownKey {
+                path: key_path,
+                suggestion: did_you_mean(name, keys),
+            }),
+        }
+    }
+    unknown
+}
+
+/// The known key closest to `name`, if any is close enough to be a typo.
+fn did_you_mean(name: &str, keys: &[Key]) -> Option<&'static str> {
+    keys.iter()
+        .map(|key| (strsim::levenshtein(name, key.name), key.name))
+        .filter(|&(distance, _)| distance <= 3.max(name.len() / 3))
+        // Among equally distant keys, prefer the one sharing more of the spelling
+        .min_by(|(a_distance, a), (b_distance, b)| {
+            a_distance
+                .cmp(b_distance)
+                .then(strsim::jaro_winkler(name, b).total_cmp(&strsim::jaro_winkler(name, a)))
+        })
+        .map(|(_, key)| key)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn unknown(metadata: Value) -> Vec<(String, Option<&'static str>)> {
+        unknown_keys(&metadata, METADATA_KEYS, &[])
+            .into_iter()
+            .map(|key| (key.path.join("."), key.suggestion))
+            .collect()
+    }
+
+    #[test]
+    fn suggests_the_key_a_typo_was_meant_to_be() {
+        assert_eq!(
+            unknown(json!({
+                "plugin_name": "Glow",
+                "identifer": "com.example.glow",
+                "pipl": { "out_flag2": 0, "spec_version": { "mjaor": 2 } },
+                "macos": { "minimum_sytem_version": "11.0" },
+            })),
+            [
+                ("identifer".to_string(), Some("identifier")),
+                (
+                    "macos.minimum_sytem_version".to_string(),
+                    Some("minimum_system_version")
+                ),
+                ("pipl.out_flag2".to_string(), Some("out_flags2")),
+                ("pipl.spec_version.mjaor".to_string(), Some("major")),
+            ]
+        );
+    }
+
+    #[test]
+    fn suggests_nothing_for_keys_far_from_every_known_one() {
+        assert_eq!(
+            unknown(json!({ "plugin_name": "Glow", "thumbnail_color": "#ff0000" })),
+            [("thumbnail_color".to_string(), None)]
+        );
+        // A key of another table is not a suggestion at this level
+        assert_eq!(
+            unknown(json!({ "pipl": { "arch": "universal" } })),
+            [("pipl.arch".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn accepts_known_keys_and_any_info_plist_key() {
+        assert_eq!(
+            unknown(json!({
+                "plugin_name": "Glow",
+                "identifier": "com.example.glow",
+                "pipl": { "entry_points": { "windows_x86_64": "EffectMain" } },
+                "macos": { "info_plist": { "NSHumanReadableCopyright": "(c) Example" } },
+                "windows": { "company_name": "Example" },
+            })),
+            []
+        );
+    }
+}
@@ -2,45 +2,58 @@ use std::{io, path::Path, process::Command};
 
 use nix::unistd::Uid;
 
-use crate::command::MV;
+use crate::error::JkError;
+
+/// Adobe's shared plugin directory, used unless `install.destination` is configured
+pub const DEFAULT_DESTINATION: &str =
+    "/Library/Application Support/Adobe/Common/Plug-ins/7.0/MediaCore/";
 
 pub fn is_elevated() -> bool {
     Uid::current().is_root()
 }
 
-pub fn elevate_self() {
+pub fn elevate_self() -> Result<(), JkError> {
     // Use `sudo` to re-run the command with elevated privileges
+    let current_exe = std::env::current_exe().map_err(|e| JkError::Elevation(e.to_string()))?;
     let args: Vec<String> = std::env::args().skip(1).collect();
     let status = Command::new("sudo")
-        .arg(std::env::current_exe().unwrap())
+        .arg(current_exe)
         .args(args)
         .status()
-        .expect("Failed to execute sudo command");
+        .map_err(|e| JkError::Elevation(format!("failed to execute sudo command: {e}")))?;
 
     if !status.success() {
-        eprintln!("Failed to elevate privileges");
-        std::process::exit(1);
+        return Err(JkError::Elevation(format!("sudo exited with {status}")));
     }
+    Ok(())
 }
 
 // macOS
 // dst は "/Library/Application Support/Adobe/Common/Plug-ins/7.0/MediaCore/"
 // src はコマンドライン引数で指定されたパスで、windows版と違いプラグインはディレクトリなので、ディレクトリをコピーする
-pub fn mv_command(mv: &MV) -> io::Result<()> {
-    let target_dir = Path::new("/Library/Application Support/Adobe/Common/Plug-ins/7.0/MediaCore/");
+pub fn mv_command(src: &Path, destination: Option<&Path>) -> Result<(), JkError> {
+    let target_dir = destination.unwrap_or(Path::new(DEFAULT_DESTINATION));
+
+    let install_err = |source| JkError::Install {
+        path: src.to_path_buf(),
+        source,
+    };
 
-    let src = Path::new(&mv.src);
     // get directory name from the source path
-    let dirname = src.file_name().ok_or(io::Error::new(
-        io::ErrorKind::NotFound,
-        "Source directory does not have a valid name",
-    ))?;
+    let dirname = src.file_name().ok_or_else(|| {
+        install_err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Source directory does not have a valid name",
+        ))
+    })?;
     let target = target_dir.join(dirname);
 
-    std::fs::remove_dir_all(target.as_path())?;
+    if target.exists() {
+        std::fs::remove_dir_all(target.as_path()).map_err(install_err)?;
+    }
 
     dircpy::copy_dir(src, &target)
-        .map_err(|e| io::Error::other(format!("Failed to move directory: {e}")))?;
+        .map_err(|e| install_err(io::Error::other(format!("Failed to move directory: {e}"))))?;
 
     Ok(())
 }
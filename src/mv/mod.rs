@@ -18,6 +18,13 @@
 //! - **Operation**: Copies directories using `dircpy::copy_dir()`
 //! - **Elevation**: Uses `sudo` command
 //!
+//! ### Other hosts
+//! - Plugins can be built (cross-compiled) here, but every operation fails with
+//!   `JkError::UnsupportedHost`
+//!
+//! Both target directories can be overridden with `install.destination` in the cargo-jk
+//! configuration or `cargo jk mv --destination`.
+//!
 //! This fundamental difference exists because:
 //! - Windows Adobe plugins are typically single `.aex` files
 //! - macOS Adobe plugins are typically `.plugin` directory bundles
@@ -36,6 +43,68 @@ mod os_impl;
 mod os_impl;
 
 #[cfg(not(any(target_os = "windows", target_os = "macos")))]
-compile_error!("mv_platform: unsupported operating system");
+#[path = "unsupported.rs"]
+mod os_impl;
 
 pub use os_impl::*;
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::{EventHandler, EventKind, error::JkError};
+
+/// Whether plugins can be installed on this host. Elsewhere, such as on Linux, plugins can be
+/// built but the install step fails.
+pub const fn is_supported_host() -> bool {
+    cfg!(any(target_os = "windows", target_os = "macos"))
+}
+
+/// Copies a built plugin into the Adobe plugin directory and returns the installed path.
+///
+/// Copying needs elevated privileges, so unless the current process already has them,
+/// this runs `cargo jk mv` in a child process which elevates itself.
+pub fn install_plugin(
+    plugin_path: &Path,
+    destination: Option<&Path>,
+    events: &EventHandler,
+    package: &str,
+) -> Result<PathBuf, JkError> {
+    let installed_path = destination
+        .unwrap_or(Path::new(DEFAULT_DESTINATION))
+        .join(plugin_path.file_name().unwrap_or_default());
+    if is_elevated() {
+        mv_command(plugin_path, destination)?;
+        return Ok(installed_path);
+    }
+
+    // Detect if we're running in development mode (cargo run -- jk) or production mode (cargo jk)
+    let current_exe = env::current_exe().map_err(JkError::CurrentDir)?;
+    let is_dev_mode = current_exe.to_string_lossy().contains("target");
+
+    let mut command = Command::new("cargo");
+    if is_dev_mode {
+        command.args(["run", "--", "jk"]);
+    } else {
+        command.arg("jk");
+    }
+    command.arg("mv").arg(plugin_path).stdin(Stdio::null());
+    if let Some(destination) = destination {
+        command.arg("--destination").arg(destination);
+    }
+    events.emit(EventKind::ToolStarted {
+        package: package.to_string(),
+        command: format!("{command:?}"),
+    });
+
+    let output = command.output().map_err(JkError::spawn(&command))?;
+    if !output.status.success() {
+        return Err(JkError::Install {
+            path: plugin_path.to_path_buf(),
+            source: std::io::Error::other(String::from_utf8_lossy(&output.stderr).into_owned()),
+        });
+    }
+    Ok(installed_path)
+}
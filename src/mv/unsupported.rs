@@ -0,0 +1,24 @@
+use std::path::Path;
+
+use crate::error::JkError;
+
+/// After Effects does not run on this host, so there is no plugin directory
+pub const DEFAULT_DESTINATION: &str = "";
+
+fn unsupported() -> JkError {
+    JkError::UnsupportedHost {
+        operation: "installing plugins".to_string(),
+    }
+}
+
+pub fn is_elevated() -> bool {
+    false
+}
+
+pub fn elevate_self() -> Result<(), JkError> {
+    Err(unsupported())
+}
+
+pub fn mv_command(_src: &Path, _destination: Option<&Path>) -> Result<(), JkError> {
+    Err(unsupported())
+}
@@ -13,7 +13,11 @@ use windows::{
     core::*,
 };
 
-use crate::command::MV;
+use crate::error::JkError;
+
+/// Adobe's shared plugin directory, used unless `install.destination` is configured
+pub const DEFAULT_DESTINATION: &str =
+    "C:\\Program Files\\Adobe\\Common\\Plug-ins\\7.0\\MediaCore\\";
 
 pub fn is_elevated() -> bool {
     let mut token = HANDLE::default();
@@ -38,8 +42,8 @@ pub fn is_elevated() -> bool {
     false
 }
 
-pub fn elevate_self() {
-    let exe_path = std::env::current_exe().unwrap();
+pub fn elevate_self() -> Result<(), JkError> {
+    let exe_path = std::env::current_exe().map_err(|e| JkError::Elevation(e.to_string()))?;
 
     let args: Vec<String> = std::env::args().skip(1).collect(); // 最初は自分自身なので skip
     let arg_str = args
@@ -54,7 +58,8 @@ pub fn elevate_self() {
         .collect::<Vec<_>>()
         .join(" ");
 
-    let cmd = OsStr::new(exe_path.to_str().unwrap())
+    let cmd = exe_path
+        .as_os_str()
         .encode_wide()
         .chain(once(0))
         .collect::<Vec<u16>>();
@@ -75,32 +80,37 @@ pub fn elevate_self() {
     };
 
     unsafe {
-        if ShellExecuteExW(&mut sei).is_ok() {
-            WaitForSingleObject(sei.hProcess, INFINITE);
-            CloseHandle(sei.hProcess).unwrap();
-        } else {
-            eprintln!("Failed to elevate via UAC");
-        }
+        ShellExecuteExW(&mut sei)
+            .map_err(|e| JkError::Elevation(format!("Failed to elevate via UAC: {e}")))?;
+        WaitForSingleObject(sei.hProcess, INFINITE);
+        let _ = CloseHandle(sei.hProcess);
     }
 
-    std::process::exit(0);
+    Ok(())
 }
 
 // Windows
 // dst は "C:\Program Files\Adobe\Common\Plug-ins\7.0\MediaCore\"
 // src はコマンドライン引数で指定されたパスで、ファイルをコピーする
-pub fn mv_command(mv: &MV) -> io::Result<()> {
-    let target_dir = Path::new("C:\\Program Files\\Adobe\\Common\\Plug-ins\\7.0\\MediaCore\\");
+pub fn mv_command(src: &Path, destination: Option<&Path>) -> Result<(), JkError> {
+    let target_dir = destination.unwrap_or(Path::new(DEFAULT_DESTINATION));
+
+    let install_err = |source| JkError::Install {
+        path: src.to_path_buf(),
+        source,
+    };
 
-    let src = Path::new(&mv.src);
     // get filename from the source path
-    let filename = src.file_name().ok_or(io::Error::new(
-        io::ErrorKind::NotFound,
-        "Source file does not have a valid filename",
-    ))?;
+    let filename = src.file_name().ok_or_else(|| {
+        install_err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Source file does not have a valid filename",
+        ))
+    })?;
     let target = target_dir.join(filename);
 
-    fs::copy(src, target).map_err(|e| io::Error::other(format!("Failed to move file: {e}")))?;
+    fs::copy(src, target)
+        .map_err(|e| install_err(io::Error::other(format!("Failed to move file: {e}"))))?;
 
     Ok(())
 }
@@ -0,0 +1,486 @@
+//! Notarization of signed macOS bundles through Apple's notary REST API.
+//!
+//! The bundle is zipped, submitted with an App Store Connect API key, uploaded to the bucket
+//! the service hands out credentials for, and polled until a verdict is in. Accepted bundles
+//! get their ticket stapled to `Contents/CodeResources`. The log of the submission is reported
+//! either way, since it explains a rejection.
+//!
+//! All three services are reached through configurable URLs, so the whole flow can run against
+//! a local mock server.
+
+mod api;
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use plist::Value;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    EventHandler, EventKind, archive::Archive, config::NotarizeConfig, error::JkError, sign::macho,
+};
+
+const DEFAULT_POLL_INTERVAL: u64 = 15;
+const DEFAULT_TIMEOUT: u64 = 60 * 60;
+
+#[derive(Debug, Clone)]
+pub struct NotarizeOptions {
+    /// The signed `.plugin` bundle
+    pub plugin: PathBuf,
+    pub config: NotarizeConfig,
+    /// Staple the ticket to the bundle once accepted
+    pub staple: bool,
+    /// Receives the progress of the submission and its log
+    pub events: EventHandler,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NotarizeOutput {
+    /// Submission ID assigned by the notary service
+    pub id: String,
+    /// Final status of the submission, `Accepted`
+    pub status: String,
+    /// Whether the ticket was stapled to the bundle
+    pub stapled: bool,
+}
+
+/// Notarizes the bundle at `options.plugin`, stapling the ticket unless told not to.
+pub fn notarize(options: &NotarizeOptions) -> Result<NotarizeOutput, JkError> {
+    let bundle = &options.plugin;
+    let invalid = |message: String| JkError::InvalidBundle {
+        path: bundle.clone(),
+        message,
+    };
+    let executable = executable(bundle).map_err(invalid)?;
+    let data = std::fs::read(&executable)
+        .map_err(|e| invalid(format!("cannot read {}: {e}", executable.display())))?;
+    let directories = macho::code_directories(&data)
+        .map_err(|e| invalid(format!("{} {e}", executable.display())))?;
+    if directories.iter().any(macho::CodeDirectory::is_ad_hoc) {
+        return Err(invalid(
+            "it is signed ad-hoc; notarization requires a Developer ID signature (see [sign.macos])"
+                .to_string(),
+        ));
+    }
+
+    let client = api::NotaryClient::new(&options.config)?;
+    let mut archive = Archive::new();
+    archive.add_top_level(bundle).map_err(invalid)?;
+    let archive = archive.zip().map_err(invalid)?;
+    let name = format!(
+        "{}.zip",
+        bundle
+            .file_stem()
+            .unwrap_or(bundle.as_os_str())
+            .to_string_lossy()
+    );
+    let submission = client.submit(&name, &api::hex(&Sha256::digest(&archive)))?;
+    client.upload(&submission, &archive)?;
+    options.events.emit(EventKind::NotarizationSubmitted {
+        path: bundle.clone(),
+        id: submission.id.clone(),
+        size: archive.len() as u64,
+    });
+
+    let id = submission.id;
+    let interval = Duration::from_secs(
+        options
+            .config
+            .poll_interval
+            .unwrap_or(DEFAULT_POLL_INTERVAL),
+    );
+    let timeout = Duration::from_secs(options.config.timeout.unwrap_or(DEFAULT_TIMEOUT));
+    let started = Instant::now();
+    let status = loop {
+        let status = client.status(&id)?;
+        if status != "In Progress" {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            return Err(JkError::NotarizationTimeout {
+                id,
+                seconds: timeout.as_secs(),
+            });
+        }
+        options.events.emit(EventKind::NotarizationPending {
+            id: id.clone(),
+            status,
+        });
+        std::thread::sleep(interval);
+    };
+
+    // Accepted submissions can still have warnings worth reading. The log is only an
+    // explanation, so failing to fetch it must not hide the verdict.
+    let (log, log_error) = match client.log(&id) {
+        Ok(log) => (Some(log), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    options.events.emit(EventKind::NotarizationFinished {
+        id: id.clone(),
+        status: status.clone(),
+        log,
+        log_error,
+    });
+    if status != "Accepted" {
+        return Err(JkError::NotarizationRejected {
+            path: bundle.clone(),
+            id,
+            status,
+        });
+    }
+
+    if options.staple {
+        let directory = &directories[0];
+        let ticket = client.ticket(directory.hash_type, &directory.cdhash)?;
+        let path = bundle.join("Contents").join("CodeResources");
+        std::fs::write(&path, ticket).map_err(|e| JkError::Staple {
+            path: path.clone(),
+            message: e.to_string(),
+        })?;
+        options.events.emit(EventKind::Stapled {
+            path: bundle.clone(),
+        });
+    }
+    Ok(NotarizeOutput {
+        id,
+        status,
+        stapled: options.staple,
+    })
+}
+
+/// The main executable of a bundle, as its `Info.plist` names it.
+fn executable(bundle: &Path) -> Result<PathBuf, String> {
+    let contents = bundle.join("Contents");
+    let info_plist = contents.join("Info.plist");
+    let info = Value::from_file(&info_plist)
+        .map_err(|e| format!("cannot read {}: {e}", info_plist.display()))?;
+    let name = info
+        .as_dictionary()
+        .and_then(|dict| dict.get("CFBundleExecutable"))
+        .and_then(Value::as_string)
+        .ok_or("Info.plist has no CFBundleExecutable")?;
+    Ok(contents.join("MacOS").join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use plist::Dictionary;
+    use serde_json::json;
+
+    use super::*;
+
+    /// A request the mock server received.
+    #[derive(Debug, Clone)]
+    struct Request {
+        method: String,
+        path: String,
+        /// Lowercase names and values
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Request {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find_map(|(key, value)| (key == name).then_some(value.as_str()))
+        }
+
+        fn json(&self) -> serde_json::Value {
+            serde_json::from_slice(&self.body).unwrap()
+        }
+    }
+
+    /// Serves `respond` over HTTP on a local port, standing in for all three services.
+    /// `respond` is given the request and the server's URL, and returns the status and JSON body.
+    fn serve(
+        respond: impl Fn(&Request, &str) -> (u16, serde_json::Value) + Send + 'static,
+    ) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let (server_url, received) = (url.clone(), requests.clone());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split_whitespace();
+                let (method, path) = (parts.next().unwrap(), parts.next().unwrap());
+                let mut headers = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let Some((name, value)) = line.trim_end().split_once(':') else {
+                        break;
+                    };
+                    headers.push((name.to_lowercase(), value.trim().to_string()));
+                }
+                let mut request = Request {
+                    method: method.to_string(),
+                    path: path.to_string(),
+                    headers,
+                    body: Vec::new(),
+                };
+                let length = request
+                    .header("content-length")
+                    .map_or(0, |length| length.parse().unwrap());
+                request.body.resize(length, 0);
+                reader.read_exact(&mut request.body).unwrap();
+
+                let (status, body) = respond(&request, &server_url);
+                received.lock().unwrap().push(request);
+                let body = body.to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    /// A bundle around `executable`, one of the Mach-O fixtures.
+    fn bundle(dir: &Path, executable: &str) -> PathBuf {
+        let bundle = dir.join("Test.plugin");
+        let macos = bundle.join("Contents").join("MacOS");
+        std::fs::create_dir_all(&macos).unwrap();
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures/macho")
+                .join(executable),
+            macos.join("Test"),
+        )
+        .unwrap();
+        let mut info = Dictionary::new();
+        info.insert("CFBundleExecutable".to_string(), "Test".into());
+        Value::Dictionary(info)
+            .to_file_xml(bundle.join("Contents").join("Info.plist"))
+            .unwrap();
+        bundle
+    }
+
+    /// An event handler that keeps the events it receives, as JSON.
+    fn recorder() -> (EventHandler, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        let events = EventHandler::new(move |event| {
+            sink.lock()
+                .unwrap()
+                .push(serde_json::to_value(event).unwrap());
+        });
+        (events, received)
+    }
+
+    fn options(bundle: PathBuf, url: &str) -> NotarizeOptions {
+        NotarizeOptions {
+            plugin: bundle,
+            config: NotarizeConfig {
+                key_id: Some("TESTKEY123".to_string()),
+                issuer_id: Some("57246542-96fe-1a63-e053-0824d011072a".to_string()),
+                key_file: Some(
+                    Path::new(env!("CARGO_MANIFEST_DIR"))
+                        .join("tests/fixtures/notarize/AuthKey_TEST.p8"),
+                ),
+                base_url: Some(format!("{url}/notary/v2")),
+                upload_url: Some(format!("{url}/s3")),
+                ticket_url: Some(format!("{url}/ticket")),
+                poll_interval: Some(0),
+                timeout: Some(60),
+            },
+            staple: true,
+            events: EventHandler::default(),
+        }
+    }
+
+    /// Responds like the notary service, reporting `verdict` after one `In Progress`.
+    fn notary_service(
+        verdict: &'static str,
+        log_status: u16,
+    ) -> impl Fn(&Request, &str) -> (u16, serde_json::Value) {
+        let polls = AtomicUsize::new(0);
+        move |request, url| match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/notary/v2/submissions") => (
+                200,
+                json!({ "data": { "id": "sub-1", "type": "newSubmissions", "attributes": {
+                    "awsAccessKeyId": "ASIAEXAMPLE",
+                    "awsSecretAccessKey": "secret",
+                    "awsSessionToken": "session-token",
+                    "bucket": "notary-submissions",
+                    "object": "prefix/Test.zip",
+                } } }),
+            ),
+            ("PUT", "/s3/notary-submissions/prefix/Test.zip") => (200, json!({})),
+            ("GET", "/notary/v2/submissions/sub-1") => {
+                let status = match polls.fetch_add(1, Ordering::SeqCst) {
+                    0 => "In Progress",
+                    _ => verdict,
+                };
+                (
+                    200,
+                    json!({ "data": { "attributes": { "status": status } } }),
+                )
+            }
+            ("GET", "/notary/v2/submissions/sub-1/logs") => (
+                log_status,
+                json!({ "data": { "attributes": { "developerLogUrl": format!("{url}/log") } } }),
+            ),
+            ("GET", "/log") => (200, json!({ "status": verdict, "issues": [] })),
+            ("POST", "/ticket") => (
+                200,
+                json!({ "records": [{ "fields": { "signedTicket": { "value": "dGlja2V0" } } }] }),
+            ),
+            _ => (404, json!({ "errors": [{ "detail": "no such mock" }] })),
+        }
+    }
+
+    #[test]
+    fn submits_polls_and_staples() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = bundle(dir.path(), "libmachot.developer-id.dylib");
+        let (url, requests) = serve(notary_service("Accepted", 200));
+
+        let (events, received) = recorder();
+        let output = notarize(&NotarizeOptions {
+            events,
+            ..options(bundle.clone(), &url)
+        })
+        .unwrap();
+        assert_eq!(output.id, "sub-1");
+        assert_eq!(output.status, "Accepted");
+        assert!(output.stapled);
+        assert_eq!(
+            std::fs::read(bundle.join("Contents/CodeResources")).unwrap(),
+            b"ticket"
+        );
+
+        let requests = requests.lock().unwrap();
+        let calls = requests
+            .iter()
+            .map(|request| format!("{} {}", request.method, request.path))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            calls,
+            [
+                "POST /notary/v2/submissions",
+                "PUT /s3/notary-submissions/prefix/Test.zip",
+                "GET /notary/v2/submissions/sub-1",
+                "GET /notary/v2/submissions/sub-1",
+                "GET /notary/v2/submissions/sub-1/logs",
+                "GET /log",
+                "POST /ticket",
+            ]
+        );
+        let [submit, upload, _, _, logs, log, ticket] = &requests[..] else {
+            unreachable!();
+        };
+
+        // The submission names the archive that is then uploaded
+        let archive = &upload.body;
+        assert!(archive.starts_with(b"PK\x03\x04"));
+        assert_eq!(
+            submit.json(),
+            json!({ "submissionName": "Test.zip", "sha256": api::hex(&Sha256::digest(archive)) })
+        );
+        for request in [submit, logs] {
+            assert!(
+                request
+                    .header("authorization")
+                    .unwrap()
+                    .starts_with("Bearer ")
+            );
+        }
+
+        // S3 gets the temporary credentials instead of the API token
+        let authorization = upload.header("authorization").unwrap();
+        assert!(
+            authorization.starts_with("AWS4-HMAC-SHA256 Credential=ASIAEXAMPLE/"),
+            "{authorization}"
+        );
+        assert!(authorization.contains("/us-west-2/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date;x-amz-security-token, Signature="));
+        assert_eq!(upload.header("x-amz-security-token"), Some("session-token"));
+        assert_eq!(
+            upload.header("x-amz-content-sha256"),
+            Some(api::hex(&Sha256::digest(archive)).as_str())
+        );
+        assert_eq!(log.header("authorization"), None);
+
+        // The ticket is looked up by the SHA-256 cdhash of the executable
+        assert_eq!(
+            ticket.json(),
+            json!({ "records": [{ "recordName": "2/2/1fe276bde34c8af0f909e3692357b9051d7cf6d2" }] })
+        );
+
+        let received = received.lock().unwrap();
+        let kinds: Vec<_> = received.iter().map(|event| &event["event"]).collect();
+        assert_eq!(
+            kinds,
+            [
+                "notarization-submitted",
+                "notarization-pending",
+                "notarization-finished",
+                "stapled"
+            ]
+        );
+        assert_eq!(received[0]["size"], json!(archive.len()));
+        assert_eq!(received[1]["status"], "In Progress");
+        assert_eq!(received[2]["status"], "Accepted");
+        assert!(received[2]["log"].is_object());
+    }
+
+    #[test]
+    fn reports_a_rejection_even_without_a_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = bundle(dir.path(), "libmachot.developer-id.dylib");
+        let (url, requests) = serve(notary_service("Invalid", 500));
+
+        let (events, received) = recorder();
+        let error = notarize(&NotarizeOptions {
+            events,
+            ..options(bundle.clone(), &url)
+        })
+        .unwrap_err();
+        let finished = received.lock().unwrap().pop().unwrap();
+        assert_eq!(finished["event"], "notarization-finished");
+        assert_eq!(finished["log"], serde_json::Value::Null);
+        assert!(finished["log_error"].is_string());
+        assert!(
+            matches!(&error, JkError::NotarizationRejected { id, status, .. } if id == "sub-1" && status == "Invalid"),
+            "{error}"
+        );
+        assert!(!bundle.join("Contents/CodeResources").exists());
+        assert!(
+            !requests
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|request| request.path == "/ticket")
+        );
+    }
+
+    #[test]
+    fn refuses_ad_hoc_signatures() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = bundle(dir.path(), "libmachot.rcodesign.dylib");
+        let (url, requests) = serve(notary_service("Accepted", 200));
+
+        let error = notarize(&options(bundle, &url)).unwrap_err();
+        assert!(error.to_string().contains("signed ad-hoc"), "{error}");
+        assert!(requests.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,376 @@
+use std::path::{Path, PathBuf};
+
+use cargo_metadata::{MetadataCommand, Package, Target};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    error::JkError,
+    pipl::{PiplMetadata, PluginKind},
+};
+
+/// The `[package.metadata.jk_plugin]` section of a plugin crate, as written.
+///
+/// See [`PluginManifest`](crate::manifest::PluginManifest) for the section resolved against the
+/// rest of the package, which is what the build reads.
+#[derive(Debug, Clone, Deserialize)]
+pub struct JkPluginMetadata {
+    pub plugin_name: String,
+    pub identifier: String,
+    /// Plugin version; defaults to the package version
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Name shown to users (Effect menu, Finder); defaults to `plugin_name`
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// Effect menu category
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Name After Effects identifies the effect by in projects; defaults to `plugin_name`
+    #[serde(default)]
+    pub match_name: Option<String>,
+    /// `AEEffect` (the default) or `AEGP`
+    #[serde(default)]
+    pub kind: Option<PluginKind>,
+    /// Oldest After Effects version the plugin supports, e.g. `"23.0"`
+    #[serde(default)]
+    pub min_host_version: Option<String>,
+    /// Defaults to a notice naming the package authors
+    #[serde(default)]
+    pub copyright: Option<String>,
+    /// Defaults to the package description
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Extra files shipped with the plugin on every platform, relative to the manifest directory
+    #[serde(default)]
+    pub resources: Vec<PathBuf>,
+    /// README, license and third-party notices put in `cargo jk package` archives, relative to
+    /// the manifest directory; defaults to the files named like them in the manifest directory
+    #[serde(default)]
+    pub documents: Option<Vec<PathBuf>>,
+    /// After Effects SDK used when neither `AESDK_ROOT` nor the cargo-jk config sets one,
+    /// relative to the package's manifest directory
+    #[serde(default)]
+    pub aesdk_root: Option<PathBuf>,
+    /// Settings of the generated PiPL resource; without it the build expects a prebuilt
+    /// `<binary>.rsrc` in the target directory on macOS
+    #[serde(default)]
+    pub pipl: Option<PiplMetadata>,
+    #[serde(default)]
+    pub macos: MacOsMetadata,
+    #[serde(default)]
+    pub windows: WindowsMetadata,
+}
+
+/// The `[package.metadata.jk_plugin.macos]` section.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MacOsMetadata {
+    /// Architectures to build; defaults to `universal` for optimized profiles and the host's
+    /// architecture otherwise
+    #[serde(default)]
+    pub arch: Option<MacArch>,
+    /// `LSMinimumSystemVersion` of the bundle
+    #[serde(default)]
+    pub minimum_system_version: Option<String>,
+    /// Extra files copied into `Contents/Resources`
+    #[serde(default)]
+    pub resources: Vec<PathBuf>,
+    /// `Info.plist` whose keys are merged over the generated ones, relative to the manifest
+    /// directory
+    #[serde(default)]
+    pub info_plist_template: Option<PathBuf>,
+    /// Extra `Info.plist` keys, merged over the generated keys and the template
+    #[serde(default)]
+    pub info_plist: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Architectures a macOS plugin binary is built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+pub enum MacArch {
+    /// Intel only
+    #[serde(rename = "x86_64")]
+    #[value(name = "x86_64")]
+    X86_64,
+    /// Apple Silicon only
+    #[serde(rename = "arm64")]
+    #[value(name = "arm64")]
+    Arm64,
+    /// Both, merged into a universal binary
+    #[serde(rename = "universal")]
+    Universal,
+}
+
+impl MacArch {
+    /// The target triples to build, in the order they are merged.
+    pub fn targets(self) -> &'static [&'static str] {
+        match self {
+            MacArch::X86_64 => &["x86_64-apple-darwin"],
+            MacArch::Arm64 => &["aarch64-apple-darwin"],
+            MacArch::Universal => &["x86_64-apple-darwin", "aarch64-apple-darwin"],
+        }
+    }
+}
+
+/// The `[package.metadata.jk_plugin.windows]` section.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct WindowsMetadata {
+    /// Extra files copied next to the `.aex`
+    #[serde(default)]
+    pub resources: Vec<PathBuf>,
+    /// `CompanyName` of the version resource; defaults to the package authors
+    #[serde(default)]
+    pub company_name: Option<String>,
+}
+
+impl JkPluginMetadata {
+    pub fn from_package(package: &Package) -> Result<Self, JkError> {
+        let value = package
+            .metadata
+            .get("jk_plugin")
+            .ok_or_else(|| JkError::MissingMetadata {
+                package: package.name.to_string(),
+                manifest_path: package.manifest_path.clone().into(),
+            })?
+            .clone();
+        serde_json::from_value(value).map_err(|source| JkError::InvalidMetadata {
+            package: package.name.to_string(),
+            source,
+        })
+    }
+}
+
+/// Returns the package's `cdylib` target, which is what gets bundled as the plugin.
+pub fn cdylib_target(package: &Package) -> Result<&Target, JkError> {
+    package
+        .targets
+        .iter()
+        .find(|t| t.is_cdylib())
+        .ok_or_else(|| JkError::NoCdylib {
+            package: package.name.to_string(),
+        })
+}
+
+/// Which workspace members to build, mirroring cargo's own package selection flags.
+#[derive(Debug, Clone, Default)]
+pub struct PackageSelection {
+    /// Packages to build by name (`--package`)
+    pub packages: Vec<String>,
+    /// Build every workspace member that has a `[package.metadata.jk_plugin]` section
+    pub workspace: bool,
+    /// Path to the `Cargo.toml` to read the workspace from
+    pub manifest_path: Option<PathBuf>,
+}
+
+pub(crate) fn has_plugin_metadata(package: &Package) -> bool {
+    package.metadata.get("jk_plugin").is_some()
+}
+
+/// The packages selected for a build and the workspace they belong to.
+#[derive(Debug, Clone)]
+pub struct SelectedPackages {
+    pub packages: Vec<Package>,
+    /// Every member of the workspace, selected or not
+    pub members: Vec<Package>,
+    /// The workspace's target directory, as cargo reports it
+    pub target_directory: PathBuf,
+}
+
+/// Resolves the packages to build.
+///
+/// Without `--package` or `--workspace`, this is the package at `--manifest-path`, or else the
+/// innermost package whose manifest directory contains the current directory.
+pub fn select_packages(selection: &PackageSelection) -> Result<SelectedPackages, JkError> {
+    let mut command = MetadataCommand::new();
+    command.no_deps();
+    if let Some(manifest_path) = &selection.manifest_path {
+        command.manifest_path(manifest_path);
+    }
+    let meta = command.exec()?;
+    let members: Vec<Package> = meta.workspace_packages().into_iter().cloned().collect();
+    let cwd = std::env::current_dir().map_err(JkError::CurrentDir)?;
+    Ok(SelectedPackages {
+        packages: select_members(selection, members.clone(), &cwd)?,
+        members,
+        target_directory: meta.target_directory.into(),
+    })
+}
+
+fn select_members(
+    selection: &PackageSelection,
+    members: Vec<Package>,
+    cwd: &Path,
+) -> Result<Vec<Package>, JkError> {
+    if !selection.packages.is_empty() {
+        return selection
+            .packages
+            .iter()
+            .map(|name| {
+                members
+                    .iter()
+                    .find(|p| p.name.as_str() == name)
+                    .cloned()
+                    .ok_or_else(|| JkError::UnknownPackage(name.clone()))
+            })
+            .collect();
+    }
+
+    if selection.workspace {
+        let plugins: Vec<Package> = members.into_iter().filter(has_plugin_metadata).collect();
+        if plugins.is_empty() {
+            return Err(JkError::NoPluginPackages);
+        }
+        return Ok(plugins);
+    }
+
+    let candidates = || {
+        members
+            .iter()
+            .filter(|p| has_plugin_metadata(p))
+            .map(|p| p.name.to_string())
+            .collect()
+    };
+
+    if let Some(manifest_path) = &selection.manifest_path {
+        let manifest_path =
+            manifest_path
+                .canonicalize()
+                .map_err(|source| JkError::ManifestPath {
+                    path: manifest_path.clone(),
+                    source,
+                })?;
+        return members
+            .iter()
+            .find(|p| p.manifest_path == manifest_path)
+            .cloned()
+            .map(|p| vec![p])
+            .ok_or_else(|| JkError::NoPackageSelected {
+                candidates: candidates(),
+            });
+    }
+
+    let mut pkgs: Vec<_> = members
+        .iter()
+        .filter(|p| {
+            p.manifest_path
+                .parent()
+                .is_some_and(|manifest_dir| cwd.starts_with(manifest_dir))
+        })
+        .collect();
+
+    pkgs.sort_by_key(|p| p.manifest_path.components().count());
+    pkgs.pop()
+        .cloned()
+        .map(|p| vec![p])
+        .ok_or_else(|| JkError::NoPackageSelected {
+            candidates: candidates(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A workspace at `/work`: a root package, two plugins below it and a library without
+    /// plugin metadata.
+    fn members() -> Vec<Package> {
+        [
+            ("app", "/work/Cargo.toml", false),
+            ("glow", "/work/plugins/glow/Cargo.toml", true),
+            ("blur", "/work/plugins/blur/Cargo.toml", true),
+            ("common", "/work/common/Cargo.toml", false),
+        ]
+        .into_iter()
+        .map(|(name, manifest_path, plugin)| {
+            let metadata = match plugin {
+                true => serde_json::json!({ "jk_plugin": { "plugin_name": name } }),
+                false => serde_json::Value::Null,
+            };
+            serde_json::from_value(serde_json::json!({
+                "name": name,
+                "version": "0.1.0",
+                "id": format!("path+file://{manifest_path}#{name}@0.1.0"),
+                "dependencies": [],
+                "targets": [],
+                "features": {},
+                "manifest_path": manifest_path,
+                "metadata": metadata,
+            }))
+            .unwrap()
+        })
+        .collect()
+    }
+
+    fn names(selected: Result<Vec<Package>, JkError>) -> Vec<String> {
+        selected
+            .unwrap()
+            .iter()
+            .map(|package| package.name.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn selects_the_innermost_package_containing_the_current_directory() {
+        let select = |cwd: &str| {
+            names(select_members(
+                &PackageSelection::default(),
+                members(),
+                Path::new(cwd),
+            ))
+        };
+        assert_eq!(select("/work/plugins/glow/src/shaders"), ["glow"]);
+        assert_eq!(select("/work/plugins/blur"), ["blur"]);
+        // Between the members, the workspace root package contains the directory
+        assert_eq!(select("/work/plugins"), ["app"]);
+        assert_eq!(select("/work/common/src"), ["common"]);
+    }
+
+    #[test]
+    fn lists_the_plugins_when_no_package_contains_the_current_directory() {
+        let selected = select_members(&PackageSelection::default(), members(), Path::new("/tmp"));
+        match selected {
+            Err(JkError::NoPackageSelected { candidates }) => {
+                assert_eq!(candidates, ["glow", "blur"]);
+            }
+            other => panic!("expected NoPackageSelected, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn selects_packages_by_name_or_the_whole_workspace() {
+        let by_name = PackageSelection {
+            packages: vec!["blur".to_string(), "common".to_string()],
+            ..PackageSelection::default()
+        };
+        let cwd = Path::new("/work/plugins/glow");
+        assert_eq!(
+            names(select_members(&by_name, members(), cwd)),
+            ["blur", "common"]
+        );
+
+        let unknown = PackageSelection {
+            packages: vec!["sharpen".to_string()],
+            ..PackageSelection::default()
+        };
+        assert!(matches!(
+            select_members(&unknown, members(), cwd),
+            Err(JkError::UnknownPackage(name)) if name == "sharpen"
+        ));
+
+        let workspace = PackageSelection {
+            workspace: true,
+            ..PackageSelection::default()
+        };
+        assert_eq!(
+            names(select_members(&workspace, members(), cwd)),
+            ["glow", "blur"]
+        );
+        let libraries: Vec<Package> = members()
+            .into_iter()
+            .filter(|package| !has_plugin_metadata(package))
+            .collect();
+        assert!(matches!(
+            select_members(&workspace, libraries, cwd),
+            Err(JkError::NoPluginPackages)
+        ));
+    }
+}
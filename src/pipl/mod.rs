@@ -0,0 +1,1091 @@
+//! PiPL (plug-in property list) resources, generated from `[package.metadata.jk_plugin.pipl]`.
+//!
+//! After Effects reads a plugin's kind, name, entry point and capabilities from its PiPL resource.
+//! The SDK compiles `PiPL.r` with Rez on macOS and with `PiPLtool.exe` and `rc.exe` on Windows;
+//! cargo-jk encodes the same bytes itself. [`rsrc`] writes the Mac resource fork file and [`res`]
+//! the Windows resource script and compiled resource file.
+
+mod pe;
+pub mod res;
+pub mod rsrc;
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use cargo_metadata::semver::Version;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::JkError, manifest::PluginManifest, sdk::AeSdk};
+
+/// Resource ID of the PiPL, as in the SDK samples.
+pub const RESOURCE_ID: u16 = 16000;
+
+/// Vendor code of all properties After Effects reads.
+const ADOBE_VENDOR: FourCC = FourCC(*b"8BIM");
+
+/// A four character code, such as a property key.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FourCC(pub [u8; 4]);
+
+impl FourCC {
+    fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+}
+
+impl fmt::Display for FourCC {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &b in &self.0 {
+            if b.is_ascii_graphic() || b == b' ' {
+                write!(f, "{}", b as char)?;
+            } else {
+                write!(f, "\\x{b:02x}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FourCC {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{self}'")
+    }
+}
+
+impl Serialize for FourCC {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Byte order of the integers in a PiPL: big endian on macOS, little endian on Windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    Big,
+    Little,
+}
+
+/// The platform a PiPL is built for, which decides its byte order and code properties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    MacOs,
+    Windows,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Platform::MacOs => "macOS",
+            Platform::Windows => "Windows",
+        })
+    }
+}
+
+impl Platform {
+    /// The platform of a target triple, if After Effects runs on it.
+    pub fn from_target(triple: &str) -> Option<Platform> {
+        if triple.contains("-apple-darwin") {
+            Some(Platform::MacOs)
+        } else if triple.contains("-windows") {
+            Some(Platform::Windows)
+        } else {
+            None
+        }
+    }
+
+    /// File extension of the dynamic libraries cargo builds for the platform.
+    pub fn dll_extension(self) -> &'static str {
+        match self {
+            Platform::MacOs => "dylib",
+            Platform::Windows => "dll",
+        }
+    }
+
+    pub fn byte_order(self) -> ByteOrder {
+        match self {
+            Platform::MacOs => ByteOrder::Big,
+            Platform::Windows => ByteOrder::Little,
+        }
+    }
+}
+
+/// `Kind` of a plugin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PluginKind {
+    #[default]
+    #[serde(rename = "AEEffect")]
+    Effect,
+    #[serde(rename = "AEGP")]
+    Aegp,
+}
+
+impl PluginKind {
+    fn code(self) -> FourCC {
+        match self {
+            PluginKind::Effect => FourCC(*b"eFKT"),
+            PluginKind::Aegp => FourCC(*b"AEgx"),
+        }
+    }
+}
+
+/// Release stage of an [`EffectVersion`] (`PF_Stage_*`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Develop,
+    Alpha,
+    Beta,
+    #[default]
+    Release,
+}
+
+/// `AE_Effect_Version`, packed into 32 bits like the SDK's `PF_VERSION` macro.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EffectVersion {
+    pub major: u32,
+    pub minor: u32,
+    #[serde(default)]
+    pub bug: u32,
+    #[serde(default)]
+    pub stage: Stage,
+    #[serde(default)]
+    pub build: u32,
+}
+
+impl EffectVersion {
+    /// Fails if a component does not fit its bits.
+    pub fn check(&self) -> Result<(), String> {
+        for (name, value, max) in [
+            ("major", self.major, 127),
+            ("minor", self.minor, 15),
+            ("bug", self.bug, 15),
+            ("build", self.build, 511),
+        ] {
+            if value > max {
+                return Err(format!("version {name} {value} is larger than {max}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Packs the version; components that are too large are truncated, see [`Self::check`].
+    pub fn pack(&self) -> u32 {
+        ((self.major >> 3) & 0xf) << 26
+            | (self.major & 0x7) << 19
+            | (self.minor & 0xf) << 15
+            | (self.bug & 0xf) << 11
+            | (self.stage as u32) << 9
+            | (self.build & 0x1ff)
+    }
+
+    /// The version of a release: `major.minor.patch`, with the stage taken from the pre-release
+    /// (`alpha`, `beta` or `rc`, anything else is `develop`) and the build number from the build
+    /// metadata or the last number of the pre-release (`1.2.0-beta.3` is beta build 3).
+    pub fn from_version(version: &Version) -> Self {
+        let pre = version.pre.as_str();
+        let stage = match pre.split(['.', '-']).next().unwrap_or_default() {
+            "" => Stage::Release,
+            "alpha" => Stage::Alpha,
+            "beta" | "rc" => Stage::Beta,
+            _ => Stage::Develop,
+        };
+        let build = version
+            .build
+            .as_str()
+            .parse()
+            .ok()
+            .or_else(|| pre.rsplit('.').next()?.parse().ok())
+            .unwrap_or(1);
+        EffectVersion {
+            major: version.major.min(u32::MAX as u64) as u32,
+            minor: version.minor.min(u32::MAX as u64) as u32,
+            bug: version.patch.min(u32::MAX as u64) as u32,
+            stage,
+            build,
+        }
+    }
+
+    pub fn unpack(packed: u32) -> Self {
+        EffectVersion {
+            major: ((packed >> 26) & 0xf) << 3 | (packed >> 19) & 0x7,
+            minor: (packed >> 15) & 0xf,
+            bug: (packed >> 11) & 0xf,
+            stage: match (packed >> 9) & 0x3 {
+                0 => Stage::Develop,
+                1 => Stage::Alpha,
+                2 => Stage::Beta,
+                _ => Stage::Release,
+            },
+            build: packed & 0x1ff,
+        }
+    }
+}
+
+impl fmt::Display for EffectVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stage = match self.stage {
+            Stage::Develop => "develop",
+            Stage::Alpha => "alpha",
+            Stage::Beta => "beta",
+            Stage::Release => "release",
+        };
+        write!(
+            f,
+            "{}.{}.{} {stage} build {}",
+            self.major, self.minor, self.bug, self.build
+        )
+    }
+}
+
+/// A `major.minor` pair, as in `AE_PiPL_Version` and `AE_Effect_Spec_Version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpecVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+/// One PiPL property. Everything After Effects reads has vendor `8BIM` and ID 0.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "property", rename_all = "snake_case")]
+pub enum Property {
+    Kind {
+        kind: PluginKind,
+    },
+    Name {
+        name: String,
+    },
+    Category {
+        category: String,
+    },
+    CodeMacIntel64 {
+        entry_point: String,
+    },
+    CodeMacArm64 {
+        entry_point: String,
+    },
+    CodeWin64X86 {
+        entry_point: String,
+    },
+    PiplVersion {
+        version: SpecVersion,
+    },
+    EffectSpecVersion {
+        version: SpecVersion,
+    },
+    EffectVersion {
+        version: EffectVersion,
+    },
+    EffectInfoFlags {
+        flags: u16,
+    },
+    OutFlags {
+        flags: u32,
+    },
+    OutFlags2 {
+        flags: u32,
+    },
+    MatchName {
+        match_name: String,
+    },
+    ReservedInfo {
+        value: u32,
+    },
+    Unknown {
+        vendor: FourCC,
+        key: FourCC,
+        id: u32,
+        data: Vec<u8>,
+    },
+}
+
+impl Property {
+    pub fn vendor(&self) -> FourCC {
+        match self {
+            Property::Unknown { vendor, .. } => *vendor,
+            _ => ADOBE_VENDOR,
+        }
+    }
+
+    pub fn key(&self) -> FourCC {
+        FourCC(*match self {
+            Property::Kind { .. } => b"kind",
+            Property::Name { .. } => b"name",
+            Property::Category { .. } => b"catg",
+            Property::CodeMacIntel64 { .. } => b"mi64",
+            Property::CodeMacArm64 { .. } => b"ma64",
+            Property::CodeWin64X86 { .. } => b"8664",
+            Property::PiplVersion { .. } => b"ePVR",
+            Property::EffectSpecVersion { .. } => b"eSVR",
+            Property::EffectVersion { .. } => b"eVER",
+            Property::EffectInfoFlags { .. } => b"eINF",
+            Property::OutFlags { .. } => b"eGLO",
+            Property::OutFlags2 { .. } => b"eGL2",
+            Property::MatchName { .. } => b"eMNA",
+            Property::ReservedInfo { .. } => b"aeRD",
+            Property::Unknown { key, .. } => return *key,
+        })
+    }
+
+    fn id(&self) -> u32 {
+        match self {
+            Property::Unknown { id, .. } => *id,
+            _ => 0,
+        }
+    }
+
+    /// The property's data, before padding.
+    fn data(&self, order: ByteOrder) -> Vec<u8> {
+        let mut w = Writer::new(order);
+        match self {
+            Property::Kind { kind } => w.four_cc(kind.code()),
+            Property::Name { name: s }
+            | Property::Category { category: s }
+            | Property::MatchName { match_name: s } => {
+                // Pascal string; the length was checked when the PiPL was built
+                w.u8(s.len() as u8);
+                w.bytes(s.as_bytes());
+            }
+            Property::CodeMacIntel64 { entry_point }
+            | Property::CodeMacArm64 { entry_point }
+            | Property::CodeWin64X86 { entry_point } => {
+                w.bytes(entry_point.as_bytes());
+                w.u8(0);
+            }
+            Property::PiplVersion { version } | Property::EffectSpecVersion { version } => {
+                w.u16(version.major);
+                w.u16(version.minor);
+            }
+            Property::EffectInfoFlags { flags } => w.u16(*flags),
+            Property::EffectVersion { version } => w.u32(version.pack()),
+            Property::OutFlags { flags: value }
+            | Property::OutFlags2 { flags: value }
+            | Property::ReservedInfo { value } => w.u32(*value),
+            Property::Unknown { data, .. } => w.bytes(data),
+        }
+        w.into_inner()
+    }
+
+    /// Decodes a property. Anything that is not a well-formed property After Effects reads is
+    /// kept as [`Property::Unknown`].
+    fn decode(vendor: FourCC, key: FourCC, id: u32, data: &[u8], order: ByteOrder) -> Property {
+        let mut r = Reader::new(data, order);
+        let decoded = match (vendor == ADOBE_VENDOR && id == 0).then_some(&key.0) {
+            Some(b"kind") => match r.four_cc().as_ref().map(|code| &code.0) {
+                Ok(b"eFKT") => Ok(Property::Kind {
+                    kind: PluginKind::Effect,
+                }),
+                Ok(b"AEgx") => Ok(Property::Kind {
+                    kind: PluginKind::Aegp,
+                }),
+                _ => Err(String::new()),
+            },
+            Some(b"name") => r.pascal().map(|name| Property::Name { name }),
+            Some(b"catg") => r.pascal().map(|category| Property::Category { category }),
+            Some(b"eMNA") => r
+                .pascal()
+                .map(|match_name| Property::MatchName { match_name }),
+            Some(b"mi64") => r
+                .c_string()
+                .map(|entry_point| Property::CodeMacIntel64 { entry_point }),
+            Some(b"ma64") => r
+                .c_string()
+                .map(|entry_point| Property::CodeMacArm64 { entry_point }),
+            Some(b"8664") => r
+                .c_string()
+                .map(|entry_point| Property::CodeWin64X86 { entry_point }),
+            Some(b"ePVR") => r
+                .spec_version()
+                .map(|version| Property::PiplVersion { version }),
+            Some(b"eSVR") => r
+                .spec_version()
+                .map(|version| Property::EffectSpecVersion { version }),
+            Some(b"eVER") => r.u32().map(|packed| Property::EffectVersion {
+                version: EffectVersion::unpack(packed),
+            }),
+            Some(b"eINF") => r.u16().map(|flags| Property::EffectInfoFlags { flags }),
+            Some(b"eGLO") => r.u32().map(|flags| Property::OutFlags { flags }),
+            Some(b"eGL2") => r.u32().map(|flags| Property::OutFlags2 { flags }),
+            Some(b"aeRD") => r.u32().map(|value| Property::ReservedInfo { value }),
+            _ => Err(String::new()),
+        };
+        decoded.unwrap_or_else(|_| Property::Unknown {
+            vendor,
+            key,
+            id,
+            data: data.to_vec(),
+        })
+    }
+}
+
+/// Prints the property like `PiPL.r` spells it, e.g. `AE_Effect_Match_Name  "ADBE Skeleton"`.
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (label, value) = match self {
+            Property::Kind { kind } => (
+                "Kind",
+                match kind {
+                    PluginKind::Effect => "AEEffect".to_string(),
+                    PluginKind::Aegp => "AEGP".to_string(),
+                },
+            ),
+            Property::Name { name } => ("Name", format!("{name:?}")),
+            Property::Category { category } => ("Category", format!("{category:?}")),
+            Property::CodeMacIntel64 { entry_point } => {
+                ("CodeMacIntel64", format!("{entry_point:?}"))
+            }
+            Property::CodeMacArm64 { entry_point } => ("CodeMacARM64", format!("{entry_point:?}")),
+            Property::CodeWin64X86 { entry_point } => ("CodeWin64X86", format!("{entry_point:?}")),
+            Property::PiplVersion { version } => (
+                "AE_PiPL_Version",
+                format!("{}.{}", version.major, version.minor),
+            ),
+            Property::EffectSpecVersion { version } => (
+                "AE_Effect_Spec_Version",
+                format!("{}.{}", version.major, version.minor),
+            ),
+            Property::EffectVersion { version } => (
+                "AE_Effect_Version",
+                format!("{version} (0x{:08x})", version.pack()),
+            ),
+            Property::EffectInfoFlags { flags } => {
+                ("AE_Effect_Info_Flags", format!("0x{flags:04x}"))
+            }
+            Property::OutFlags { flags } => ("AE_Effect_Global_OutFlags", format!("0x{flags:08x}")),
+            Property::OutFlags2 { flags } => {
+                ("AE_Effect_Global_OutFlags_2", format!("0x{flags:08x}"))
+            }
+            Property::MatchName { match_name } => {
+                ("AE_Effect_Match_Name", format!("{match_name:?}"))
+            }
+            Property::ReservedInfo { value } => ("AE_Reserved_Info", value.to_string()),
+            Property::Unknown {
+                vendor,
+                key,
+                id,
+                data,
+            } => {
+                let bytes: Vec<String> = data.iter().map(|b| format!("{b:02x}")).collect();
+                return write!(f, "{vendor:?} {key:?} (ID {id}): {}", bytes.join(" "));
+            }
+        };
+        write!(f, "{label:<28}{value}")
+    }
+}
+
+/// A complete PiPL resource.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Pipl {
+    pub properties: Vec<Property>,
+}
+
+impl Pipl {
+    /// Encodes the resource data.
+    ///
+    /// The layout is a version (0) and a property count, then for each property its vendor, key,
+    /// ID, length and data padded to four bytes. The length does not include the padding: `PiPL.r`
+    /// takes it from labels placed before the `align long`. Windows PiPLs start with an extra
+    /// 16-bit word (1) that `PiPLtool` emits.
+    pub fn encode(&self, order: ByteOrder) -> Vec<u8> {
+        let mut w = Writer::new(order);
+        if order == ByteOrder::Little {
+            w.u16(1);
+        }
+        w.u32(0);
+        w.u32(self.properties.len() as u32);
+        for property in &self.properties {
+            let mut data = property.data(order);
+            w.four_cc(property.vendor());
+            w.four_cc(property.key());
+            w.u32(property.id());
+            w.u32(data.len() as u32);
+            data.resize(data.len().next_multiple_of(4), 0);
+            w.bytes(&data);
+        }
+        w.into_inner()
+    }
+
+    /// Decodes resource data written by [`Self::encode`], Rez or `PiPLtool`.
+    pub fn decode(data: &[u8], order: ByteOrder) -> Result<Pipl, String> {
+        let mut r = Reader::new(data, order);
+        if order == ByteOrder::Little {
+            r.u16()?;
+        }
+        let version = r.u32()?;
+        if version != 0 {
+            return Err(format!("unsupported PiPL version {version}"));
+        }
+        let count = r.u32()?;
+        let mut properties = Vec::new();
+        for _ in 0..count {
+            let vendor = r.four_cc()?;
+            let key = r.four_cc()?;
+            let id = r.u32()?;
+            let len = r.u32()? as usize;
+            let data = r.bytes(len)?;
+            // Lengths that include the padding are accepted too
+            r.skip(len.next_multiple_of(4) - len);
+            properties.push(Property::decode(vendor, key, id, data, order));
+        }
+        Ok(Pipl { properties })
+    }
+}
+
+/// Appends integers in a fixed byte order.
+struct Writer {
+    order: ByteOrder,
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn new(order: ByteOrder) -> Self {
+        Writer {
+            order,
+            buf: Vec::new(),
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        match self.order {
+            ByteOrder::Big => self.buf.extend(value.to_be_bytes()),
+            ByteOrder::Little => self.buf.extend(value.to_le_bytes()),
+        }
+    }
+
+    fn u32(&mut self, value: u32) {
+        match self.order {
+            ByteOrder::Big => self.buf.extend(value.to_be_bytes()),
+            ByteOrder::Little => self.buf.extend(value.to_le_bytes()),
+        }
+    }
+
+    /// Four character codes are integers, so they come out reversed on Windows (`MIB8`).
+    fn four_cc(&mut self, code: FourCC) {
+        self.u32(code.to_u32());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads integers in a fixed byte order, failing instead of reading past the end.
+struct Reader<'a> {
+    order: ByteOrder,
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], order: ByteOrder) -> Self {
+        Reader {
+            order,
+            data,
+            pos: 0,
+        }
+    }
+
+    /// A reader over the same data, starting at `offset`.
+    fn at(&self, offset: usize) -> Self {
+        Reader {
+            order: self.order,
+            data: self.data,
+            pos: offset,
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| format!("unexpected end of data at offset {}", self.pos))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Skips up to `len` bytes.
+    fn skip(&mut self, len: usize) {
+        self.pos = (self.pos + len).min(self.data.len());
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?.try_into().unwrap();
+        Ok(match self.order {
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?.try_into().unwrap();
+        Ok(match self.order {
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn four_cc(&mut self) -> Result<FourCC, String> {
+        Ok(FourCC(self.u32()?.to_be_bytes()))
+    }
+
+    fn spec_version(&mut self) -> Result<SpecVersion, String> {
+        Ok(SpecVersion {
+            major: self.u16()?,
+            minor: self.u16()?,
+        })
+    }
+
+    fn pascal(&mut self) -> Result<String, String> {
+        let len = self.u8()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn c_string(&mut self) -> Result<String, String> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or("unterminated string")?;
+        let string = String::from_utf8_lossy(self.bytes(len)?).into_owned();
+        self.skip(1);
+        Ok(string)
+    }
+}
+
+/// The `[package.metadata.jk_plugin.pipl]` section of a plugin crate.
+///
+/// The kind, names, category and version come from the plugin manifest; this section holds what
+/// only the PiPL needs.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PiplMetadata {
+    /// Exported entry point symbol, for every architecture not set in `entry_points`
+    #[serde(default = "default_entry_point")]
+    pub entry_point: String,
+    #[serde(default)]
+    pub entry_points: EntryPoints,
+    /// `AE_Effect_Spec_Version`; defaults to the SDK's `PF_PLUG_IN_VERSION.PF_PLUG_IN_SUBVERS`
+    pub spec_version: Option<SpecVersion>,
+    /// `AE_Effect_Info_Flags`
+    #[serde(default)]
+    pub info_flags: u16,
+    /// `AE_Effect_Global_OutFlags`
+    #[serde(default)]
+    pub out_flags: u32,
+    /// `AE_Effect_Global_OutFlags_2`
+    #[serde(default)]
+    pub out_flags2: u32,
+    /// `AE_Reserved_Info`
+    #[serde(default)]
+    pub reserved_info: u32,
+}
+
+/// Per-architecture entry point symbols.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EntryPoints {
+    pub macos_x86_64: Option<String>,
+    pub macos_arm64: Option<String>,
+    pub windows_x86_64: Option<String>,
+}
+
+fn default_entry_point() -> String {
+    "EffectMain".to_string()
+}
+
+impl PiplMetadata {
+    /// Builds the PiPL for one platform, in the property order of the SDK samples.
+    pub fn to_pipl(
+        &self,
+        platform: Platform,
+        manifest: &PluginManifest,
+        sdk: &AeSdk,
+    ) -> Result<Pipl, JkError> {
+        let invalid = |message: String| JkError::InvalidPipl {
+            package: manifest.package.clone(),
+            message,
+        };
+        let pascal = |field: &str, value: &str| {
+            if value.len() > u8::MAX as usize {
+                Err(invalid(format!("{field} is longer than {} bytes", u8::MAX)))
+            } else {
+                Ok(value.to_string())
+            }
+        };
+        let category = manifest.category.as_deref().ok_or_else(|| {
+            invalid("`category` must be set in [package.metadata.jk_plugin]".to_string())
+        })?;
+
+        let mut properties = vec![
+            Property::Kind {
+                kind: manifest.kind,
+            },
+            Property::Name {
+                name: pascal("display_name", &manifest.display_name)?,
+            },
+            Property::Category {
+                category: pascal("category", category)?,
+            },
+        ];
+        let entry_point = |specific: &Option<String>| {
+            specific.clone().unwrap_or_else(|| self.entry_point.clone())
+        };
+        match platform {
+            Platform::MacOs => {
+                properties.push(Property::CodeMacIntel64 {
+                    entry_point: entry_point(&self.entry_points.macos_x86_64),
+                });
+                properties.push(Property::CodeMacArm64 {
+                    entry_point: entry_point(&self.entry_points.macos_arm64),
+                });
+            }
+            Platform::Windows => properties.push(Property::CodeWin64X86 {
+                entry_point: entry_point(&self.entry_points.windows_x86_64),
+            }),
+        }
+        if manifest.kind == PluginKind::Aegp {
+            return Ok(Pipl { properties });
+        }
+
+        let spec_version = match (self.spec_version, &sdk.version) {
+            (Some(version), _) => version,
+            (None, Some(version)) => SpecVersion {
+                major: version.major as u16,
+                minor: version.minor as u16,
+            },
+            (None, None) => {
+                return Err(invalid(
+                    "spec_version is not set and the SDK version is unknown".to_string(),
+                ));
+            }
+        };
+        let version = EffectVersion::from_version(&manifest.version);
+        version.check().map_err(invalid)?;
+        properties.extend([
+            Property::PiplVersion {
+                version: SpecVersion { major: 2, minor: 0 },
+            },
+            Property::EffectSpecVersion {
+                version: spec_version,
+            },
+            Property::EffectVersion { version },
+            Property::EffectInfoFlags {
+                flags: self.info_flags,
+            },
+            Property::OutFlags {
+                flags: self.out_flags,
+            },
+            Property::OutFlags2 {
+                flags: self.out_flags2,
+            },
+            Property::MatchName {
+                match_name: pascal("match_name", &manifest.match_name)?,
+            },
+            Property::ReservedInfo {
+                value: self.reserved_info,
+            },
+        ]);
+        Ok(Pipl { properties })
+    }
+}
+
+/// Files written by [`generate`].
+#[derive(Debug, Clone)]
+pub struct PiplResources {
+    /// Mac resource fork file, copied into the bundle's `Contents/Resources`
+    pub rsrc: PathBuf,
+    /// Windows resource script
+    pub rc: PathBuf,
+    /// Windows compiled resource file, linked into the DLL
+    pub res: PathBuf,
+}
+
+/// Writes the PiPL of a plugin for both platforms into `out_dir`.
+pub fn generate(
+    out_dir: &Path,
+    manifest: &PluginManifest,
+    metadata: &PiplMetadata,
+    sdk: &AeSdk,
+) -> Result<PiplResources, JkError> {
+    let mac = metadata.to_pipl(Platform::MacOs, manifest, sdk)?;
+    let windows = metadata.to_pipl(Platform::Windows, manifest, sdk)?;
+    let windows_data = windows.encode(ByteOrder::Little);
+
+    let write = |path: PathBuf, contents: Vec<u8>| {
+        std::fs::write(&path, contents)
+            .map(|()| path.clone())
+            .map_err(|source| JkError::Pipl { path, source })
+    };
+    std::fs::create_dir_all(out_dir).map_err(|source| JkError::Pipl {
+        path: out_dir.to_path_buf(),
+        source,
+    })?;
+    let file = |extension: &str| out_dir.join(format!("{}.{extension}", manifest.plugin_name));
+    Ok(PiplResources {
+        rsrc: write(
+            file("rsrc"),
+            rsrc::write_resource_fork(*b"PiPL", RESOURCE_ID, &mac.encode(ByteOrder::Big)),
+        )?,
+        rc: write(
+            file("rc"),
+            res::resource_script(&windows, &windows_data).into_bytes(),
+        )?,
+        res: write(file("res"), res::resource_file(&windows_data))?,
+    })
+}
+
+/// A PiPL read out of a built plugin by [`read_plugin`].
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbeddedPipl {
+    /// The file the PiPL was read from
+    pub path: PathBuf,
+    pub platform: Platform,
+    #[serde(flatten)]
+    pub pipl: Pipl,
+}
+
+/// Reads the PiPL out of a `.plugin` bundle, an `.aex` (or any other PE file), or a `.rsrc` or
+/// `.res` file.
+pub fn read_plugin(path: &Path) -> Result<EmbeddedPipl, JkError> {
+    let failed = |path: &Path, message: String| JkError::ReadPipl {
+        path: path.to_path_buf(),
+        message,
+    };
+    let path = if path.is_dir() {
+        let resources = path.join("Contents/Resources");
+        std::fs::read_dir(&resources)
+            .map_err(|e| failed(&resources, e.to_string()))?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .find(|p| p.extension().is_some_and(|ext| ext == "rsrc"))
+            .ok_or_else(|| failed(&resources, "no .rsrc file in the bundle".to_string()))?
+    } else {
+        path.to_path_buf()
+    };
+    let file = std::fs::read(&path).map_err(|e| failed(&path, e.to_string()))?;
+
+    let (platform, data) = if file.starts_with(b"MZ") {
+        (
+            Platform::Windows,
+            pe::find_resource(&file, res::RESOURCE_TYPE),
+        )
+    } else if res::is_resource_file(&file) {
+        (
+            Platform::Windows,
+            res::find_resource(&file, res::RESOURCE_TYPE),
+        )
+    } else {
+        (Platform::MacOs, rsrc::find_resource(&file, *b"PiPL"))
+    };
+    let pipl = data
+        .and_then(|data| Pipl::decode(data, platform.byte_order()))
+        .map_err(|message| failed(&path, message))?;
+    Ok(EmbeddedPipl {
+        path,
+        platform,
+        pipl,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The PiPL of the SDK's Skeleton sample.
+    fn skeleton(platform: Platform) -> Pipl {
+        let entry_point = "EffectMain".to_string();
+        let mut properties = vec![
+            Property::Kind {
+                kind: PluginKind::Effect,
+            },
+            Property::Name {
+                name: "Skeleton".to_string(),
+            },
+            Property::Category {
+                category: "Sample Plug-ins".to_string(),
+            },
+        ];
+        match platform {
+            Platform::MacOs => properties.extend([
+                Property::CodeMacIntel64 {
+                    entry_point: entry_point.clone(),
+                },
+                Property::CodeMacArm64 { entry_point },
+            ]),
+            Platform::Windows => properties.push(Property::CodeWin64X86 { entry_point }),
+        }
+        properties.extend([
+            Property::PiplVersion {
+                version: SpecVersion { major: 2, minor: 0 },
+            },
+            Property::EffectSpecVersion {
+                version: SpecVersion {
+                    major: 13,
+                    minor: 28,
+                },
+            },
+            Property::EffectVersion {
+                version: EffectVersion {
+                    major: 1,
+                    minor: 0,
+                    bug: 0,
+                    stage: Stage::Develop,
+                    build: 1,
+                },
+            },
+            Property::EffectInfoFlags { flags: 0 },
+            Property::OutFlags { flags: 0x0200_0000 },
+            Property::OutFlags2 { flags: 0x0800_0000 },
+            Property::MatchName {
+                match_name: "ADBE Skeleton".to_string(),
+            },
+            Property::ReservedInfo { value: 0 },
+        ]);
+        Pipl { properties }
+    }
+
+    fn skeleton_version_info() -> res::VersionInfo {
+        res::VersionInfo {
+            version: [1, 0, 0, 0],
+            prerelease: false,
+            strings: vec![
+                ("CompanyName", "Adobe".to_string()),
+                ("FileDescription", "Skeleton".to_string()),
+                ("FileVersion", "1.0.0".to_string()),
+                ("InternalName", "Skeleton".to_string()),
+                ("LegalCopyright", "Copyright \"2024\" Adobe".to_string()),
+                ("OriginalFilename", "Skeleton.aex".to_string()),
+                ("ProductName", "Skeleton".to_string()),
+                ("ProductVersion", "1.0.0".to_string()),
+            ],
+        }
+    }
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pipl");
+        std::fs::read(path.join(name)).unwrap()
+    }
+
+    /// `Skeleton.res` was compiled by llvm-rc from the script cargo-jk writes, so the `.res`
+    /// cargo-jk writes itself must come out the same.
+    #[test]
+    fn res_matches_llvm_rc() {
+        let pipl = skeleton(Platform::Windows);
+        let data = pipl.encode(ByteOrder::Little);
+        let script = res::resource_script(&pipl, &data);
+        assert_eq!(script.as_bytes(), fixture("Skeleton.rc"));
+        assert_eq!(res::resource_file(&data), fixture("Skeleton.res"));
+    }
+
+    #[test]
+    fn reads_the_pipl_linked_into_an_aex() {
+        // Skeleton.res and Skeleton.version.res linked into a DLL by lld-link
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pe/Skeleton.aex");
+        let embedded = read_plugin(&path).unwrap();
+        assert_eq!(embedded.platform, Platform::Windows);
+        assert_eq!(embedded.pipl, skeleton(Platform::Windows));
+    }
+
+    #[test]
+    fn version_info_matches_llvm_rc() {
+        let version_info = skeleton_version_info();
+        assert_eq!(
+            version_info.resource_script().as_bytes(),
+            fixture("Skeleton.version.rc")
+        );
+        let file = version_info.resource_file();
+        assert!(res::is_resource_file(&file));
+        assert_eq!(file, fixture("Skeleton.version.res"));
+        assert!(res::find_resource(&file, res::RESOURCE_TYPE).is_err());
+    }
+
+    #[test]
+    fn lengths_exclude_padding() {
+        let data = skeleton(Platform::MacOs).encode(ByteOrder::Big);
+        // After the header and `kind`: vendor, key, ID, then the length of the 9-byte Pascal string
+        let name = 8 + 20;
+        assert_eq!(&data[name..name + 8], b"8BIMname");
+        assert_eq!(&data[name + 12..name + 16], &9u32.to_be_bytes());
+        assert_eq!(&data[name + 16..name + 28], b"\x08Skeleton\0\0\0");
+    }
+
+    #[test]
+    fn round_trips() {
+        for platform in [Platform::MacOs, Platform::Windows] {
+            let pipl = skeleton(platform);
+            let data = pipl.encode(platform.byte_order());
+            assert_eq!(Pipl::decode(&data, platform.byte_order()), Ok(pipl));
+        }
+
+        let data = skeleton(Platform::MacOs).encode(ByteOrder::Big);
+        let fork = rsrc::write_resource_fork(*b"PiPL", RESOURCE_ID, &data);
+        assert_eq!(rsrc::find_resource(&fork, *b"PiPL"), Ok(data.as_slice()));
+
+        let data = skeleton(Platform::Windows).encode(ByteOrder::Little);
+        let file = res::resource_file(&data);
+        assert!(res::is_resource_file(&file));
+        assert_eq!(
+            res::find_resource(&file, res::RESOURCE_TYPE),
+            Ok(data.as_slice())
+        );
+    }
+
+    #[test]
+    fn decodes_padded_lengths_and_unknown_properties() {
+        let mut w = Writer::new(ByteOrder::Big);
+        w.u32(0);
+        w.u32(2);
+        w.bytes(b"8BIMname");
+        w.u32(0);
+        w.u32(4);
+        w.bytes(b"\x03Abc");
+        w.bytes(b"ABCDxtra");
+        w.u32(7);
+        w.u32(2);
+        w.bytes(&[1, 2, 0, 0]);
+        let pipl = Pipl::decode(&w.into_inner(), ByteOrder::Big).unwrap();
+        assert_eq!(
+            pipl.properties,
+            [
+                Property::Name {
+                    name: "Abc".to_string()
+                },
+                Property::Unknown {
+                    vendor: FourCC(*b"ABCD"),
+                    key: FourCC(*b"xtra"),
+                    id: 7,
+                    data: vec![1, 2],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn effect_version_packs_like_pf_version() {
+        // PF_VERSION(1, 0, 0, PF_Stage_DEVELOP, 1) and PF_VERSION(13, 5, 2, PF_Stage_RELEASE, 7)
+        let version = |major, minor, bug, stage, build| EffectVersion {
+            major,
+            minor,
+            bug,
+            stage,
+            build,
+        };
+        assert_eq!(version(1, 0, 0, Stage::Develop, 1).pack(), 0x0008_0001);
+        let packed = version(13, 5, 2, Stage::Release, 7);
+        assert_eq!(packed.pack(), 0x042a_9607);
+        assert_eq!(EffectVersion::unpack(packed.pack()), packed);
+    }
+}
@@ -0,0 +1,185 @@
+//! Just enough of the PE format to pull a resource out of an `.aex`.
+
+use super::{ByteOrder, Reader};
+
+/// Returns the data of the first resource of the named type, comparing names case-insensitively
+/// as Windows does.
+pub fn find_resource<'a>(image: &'a [u8], kind: &str) -> Result<&'a [u8], String> {
+    let file = Reader::new(image, ByteOrder::Little);
+    let pe_offset = file.at(0x3c).u32()? as usize;
+    let mut coff = file.at(pe_offset);
+    if coff.bytes(4)? != b"PE\0\0" {
+        return Err("not a PE file".to_string());
+    }
+    coff.u16()?; // Machine
+    let sections = coff.u16()? as usize;
+    coff.bytes(12)?; // TimeDateStamp, PointerToSymbolTable, NumberOfSymbols
+    let optional_size = coff.u16()? as usize;
+
+    let optional = pe_offset + 24;
+    let directories = match file.at(optional).u16()? {
+        0x10b => optional + 96,
+        0x20b => optional + 112,
+        magic => return Err(format!("unknown optional header magic 0x{magic:x}")),
+    };
+    // The resource table is the third data directory
+    let mut resource_directory = file.at(directories + 2 * 8);
+    let resource_rva = match file.at(directories - 4).u32()? {
+        count if count > 2 => resource_directory.u32()?,
+        _ => 0,
+    };
+    if resource_rva == 0 {
+        return Err("the file has no resources".to_string());
+    }
+
+    let section_table = optional + optional_size;
+    let rva_to_offset = |rva: u32| -> Result<usize, String> {
+        for i in 0..sections {
+            let mut section = file.at(section_table + i * 40 + 8);
+            let virtual_size = section.u32()?;
+            let virtual_address = section.u32()?;
+            let raw_size = section.u32()?;
+            let raw_offset = section.u32()?;
+            if rva >= virtual_address && rva - virtual_address < virtual_size.max(raw_size) {
+                return (rva - virtual_address)
+                    .checked_add(raw_offset)
+                    .map(|offset| offset as usize)
+                    .ok_or_else(|| format!("RVA 0x{rva:x} maps past the end of the file"));
+            }
+        }
+        Err(format!("RVA 0x{rva:x} is outside every section"))
+    };
+
+    // The resource tree has three levels: type, name and language
+    let root = rva_to_offset(resource_rva)?;
+    let mut entry =
+        find_entry(&file, root, root, Some(kind))?.ok_or_else(|| format!("no {kind} resource"))?;
+    for _ in 0..2 {
+        let directory = subdirectory(root, entry)?;
+        entry = find_entry(&file, root, directory, None)?
+            .ok_or_else(|| format!("empty {kind} resource directory"))?;
+    }
+    if entry & 0x8000_0000 != 0 {
+        return Err("resource tree is deeper than expected".to_string());
+    }
+    let mut data_entry = file.at(root + entry as usize);
+    let data_rva = data_entry.u32()?;
+    let size = data_entry.u32()? as usize;
+    file.at(rva_to_offset(data_rva)?).bytes(size)
+}
+
+/// Offset of the subdirectory an entry points to.
+fn subdirectory(root: usize, entry: u32) -> Result<usize, String> {
+    if entry & 0x8000_0000 == 0 {
+        return Err("resource tree is shallower than expected".to_string());
+    }
+    Ok(root + (entry & 0x7fff_ffff) as usize)
+}
+
+/// Returns the `OffsetToData` of the entry named `name`, or of the first entry.
+fn find_entry(
+    file: &Reader,
+    root: usize,
+    directory: usize,
+    name: Option<&str>,
+) -> Result<Option<u32>, String> {
+    let mut entries = file.at(directory + 12);
+    let count = entries.u16()? as usize + entries.u16()? as usize;
+    for _ in 0..count {
+        let entry_name = entries.u32()?;
+        let offset = entries.u32()?;
+        let Some(wanted) = name else {
+            return Ok(Some(offset));
+        };
+        // Named entries point at a length-prefixed UTF-16 string
+        if entry_name & 0x8000_0000 != 0 {
+            let mut string = file.at(root + (entry_name & 0x7fff_ffff) as usize);
+            let len = string.u16()?;
+            let units = (0..len)
+                .map(|_| string.u16())
+                .collect::<Result<Vec<_>, _>>()?;
+            if String::from_utf16_lossy(&units).eq_ignore_ascii_case(wanted) {
+                return Ok(Some(offset));
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures/pe")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    /// Offset of the header of the section named `name`.
+    fn section_header(image: &[u8], name: &[u8]) -> usize {
+        let pe_offset = u32::from_le_bytes(image[0x3c..0x40].try_into().unwrap()) as usize;
+        let sections = u16::from_le_bytes([image[pe_offset + 6], image[pe_offset + 7]]) as usize;
+        let optional_size =
+            u16::from_le_bytes([image[pe_offset + 20], image[pe_offset + 21]]) as usize;
+        (0..sections)
+            .map(|i| pe_offset + 24 + optional_size + i * 40)
+            .find(|&header| image[header..header + 8].starts_with(name))
+            .unwrap()
+    }
+
+    #[test]
+    fn finds_resources_by_type_name() {
+        let image = fixture("Skeleton.aex");
+        let res = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pipl/Skeleton.res"),
+        )
+        .unwrap();
+        let linked = find_resource(&image, "PiPL").unwrap();
+        assert_eq!(
+            linked,
+            crate::pipl::res::find_resource(&res, "PIPL").unwrap()
+        );
+        assert_eq!(find_resource(&image, "pipl"), Ok(linked));
+        assert_eq!(
+            find_resource(&image, "RCDATA"),
+            Err("no RCDATA resource".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_files_without_resources() {
+        assert_eq!(
+            find_resource(&fixture("machot.dll"), "PiPL"),
+            Err("the file has no resources".to_string())
+        );
+        assert!(
+            find_resource(b"MZ\0\0", "PiPL")
+                .unwrap_err()
+                .starts_with("unexpected end of data")
+        );
+    }
+
+    #[test]
+    fn rejects_sections_past_the_end_of_the_address_space() {
+        let mut image = fixture("Skeleton.aex");
+        // A section listed first that claims the resource data, from just after the resource
+        // directory, at the very end of the file offsets
+        let rsrc = section_header(&image, b".rsrc");
+        let text = section_header(&image, b".text");
+        let resources = u32::from_le_bytes(image[rsrc + 12..rsrc + 16].try_into().unwrap());
+        image[text + 8..text + 12].copy_from_slice(&0x1000u32.to_le_bytes());
+        image[text + 12..text + 16].copy_from_slice(&(resources + 0x10).to_le_bytes());
+        image[text + 20..text + 24].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+        assert!(
+            find_resource(&image, "PiPL")
+                .unwrap_err()
+                .contains("past the end of the file")
+        );
+    }
+}
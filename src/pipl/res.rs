@@ -0,0 +1,387 @@
+//! Windows resource scripts (`.rc`) and compiled resource files (`.res`).
+//!
+//! The PiPL is written under the resource type `PiPL` and ID 16000, and the `VERSIONINFO`
+//! resource, with the plugin's version, name and copyright for Explorer's Details tab, into files
+//! of its own: every Windows plugin gets one, with or without a PiPL table. The script spells the
+//! little-endian PiPL data out as 16-bit words, so `rc.exe` reproduces it exactly; the `.res`
+//! files are what `rc.exe` would compile the scripts to, and are what cargo-jk links.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use super::{ByteOrder, Pipl, RESOURCE_ID, Reader};
+use crate::{error::JkError, manifest::PluginManifest};
+
+/// Type name of the PiPL resource. `rc.exe` stores named types in upper case, and resource
+/// lookups are case-insensitive.
+pub const RESOURCE_TYPE: &str = "PIPL";
+/// `MOVEABLE | PURE | DISCARDABLE`, the flags `rc.exe` gives a `DISCARDABLE` resource
+const MEMORY_FLAGS: u16 = 0x1030;
+/// English (United States), the language `rc.exe` uses without a `LANGUAGE` statement
+const LANGUAGE: u16 = 0x0409;
+/// `MOVEABLE | PURE`, the flags `rc.exe` gives a `VERSIONINFO` resource
+const VERSION_MEMORY_FLAGS: u16 = 0x0030;
+/// `RT_VERSION`, and the ID version resources always have
+const RT_VERSION: u16 = 16;
+const VERSION_ID: u16 = 1;
+/// Unicode (UTF-16), the code page of the version strings
+const CODE_PAGE: u16 = 1200;
+
+const VS_FFI_SIGNATURE: u32 = 0xfeef_04bd;
+const VS_FFI_STRUCVERSION: u32 = 0x0001_0000;
+const VS_FFI_FILEFLAGSMASK: u32 = 0x3f;
+const VS_FF_PRERELEASE: u32 = 0x2;
+const VOS_NT_WINDOWS32: u32 = 0x0004_0004;
+const VFT_DLL: u32 = 0x2;
+
+/// The `VERSIONINFO` resource of a plugin.
+#[derive(Debug, Clone)]
+pub struct VersionInfo {
+    /// File and product version: major, minor, patch and build
+    pub version: [u16; 4],
+    /// Whether the version has a pre-release part, such as `1.0.0-beta.1`
+    pub prerelease: bool,
+    /// The `StringFileInfo` values, in order
+    pub strings: Vec<(&'static str, String)>,
+}
+
+impl VersionInfo {
+    pub fn new(manifest: &PluginManifest) -> VersionInfo {
+        let version = &manifest.version;
+        let part = |value: u64| u16::try_from(value).unwrap_or(u16::MAX);
+        let strings = [
+            ("CompanyName", manifest.windows.company_name.clone()),
+            (
+                "FileDescription",
+                Some(
+                    manifest
+                        .description
+                        .clone()
+                        .unwrap_or_else(|| manifest.display_name.clone()),
+                ),
+            ),
+            ("FileVersion", Some(version.to_string())),
+            ("InternalName", Some(manifest.plugin_name.clone())),
+            ("LegalCopyright", manifest.copyright.clone()),
+            (
+                "OriginalFilename",
+                Some(format!("{}.aex", manifest.plugin_name)),
+            ),
+            ("ProductName", Some(manifest.display_name.clone())),
+            ("ProductVersion", Some(version.to_string())),
+        ];
+        VersionInfo {
+            version: [
+                part(version.major),
+                part(version.minor),
+                part(version.patch),
+                0,
+            ],
+            prerelease: !version.pre.is_empty(),
+            strings: strings
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, value?)))
+                .collect(),
+        }
+    }
+
+    /// Encodes the resource data: a `VS_VERSIONINFO` with the fixed file info, the strings in
+    /// one US English table and the translation they are in.
+    pub fn encode(&self) -> Vec<u8> {
+        let [major, minor, patch, build] = self.version.map(u32::from);
+        let mut fixed = Vec::new();
+        for value in [
+            VS_FFI_SIGNATURE,
+            VS_FFI_STRUCVERSION,
+            (major << 16) | minor,
+            (patch << 16) | build,
+            (major << 16) | minor,
+            (patch << 16) | build,
+            VS_FFI_FILEFLAGSMASK,
+            if self.prerelease { VS_FF_PRERELEASE } else { 0 },
+            VOS_NT_WINDOWS32,
+            VFT_DLL,
+            0, // subtype
+            0, // date
+            0,
+        ] {
+            fixed.extend(value.to_le_bytes());
+        }
+
+        let strings = self
+            .strings
+            .iter()
+            .map(|(key, value)| {
+                let value: Vec<u16> = value.encode_utf16().chain([0]).collect();
+                version_node(key, NodeValue::Text(&value), &[])
+            })
+            .collect::<Vec<_>>();
+        let table = version_node(
+            &format!("{LANGUAGE:04x}{CODE_PAGE:04x}"),
+            NodeValue::None,
+            &strings,
+        );
+        let string_file_info = version_node("StringFileInfo", NodeValue::None, &[table]);
+        let translation = [LANGUAGE.to_le_bytes(), CODE_PAGE.to_le_bytes()].concat();
+        let var = version_node("Translation", NodeValue::Binary(&translation), &[]);
+        let var_file_info = version_node("VarFileInfo", NodeValue::None, &[var]);
+        version_node(
+            "VS_VERSION_INFO",
+            NodeValue::Binary(&fixed),
+            &[string_file_info, var_file_info],
+        )
+    }
+
+    /// Writes a resource script with the `VERSIONINFO` statement compiling to the resource.
+    pub fn resource_script(&self) -> String {
+        let version = self.version.map(|part| part.to_string()).join(",");
+        let flags = if self.prerelease { VS_FF_PRERELEASE } else { 0 };
+        let mut script = String::new();
+        script.push_str("// Generated by cargo-jk from the package metadata\n\n");
+        script.push_str("#pragma code_page(65001)\n\n");
+        writeln!(script, "{VERSION_ID} VERSIONINFO").unwrap();
+        writeln!(script, "FILEVERSION {version}").unwrap();
+        writeln!(script, "PRODUCTVERSION {version}").unwrap();
+        writeln!(script, "FILEFLAGSMASK {VS_FFI_FILEFLAGSMASK:#x}").unwrap();
+        writeln!(script, "FILEFLAGS {flags:#x}").unwrap();
+        writeln!(script, "FILEOS {VOS_NT_WINDOWS32:#x}").unwrap();
+        writeln!(script, "FILETYPE {VFT_DLL:#x}").unwrap();
+        script.push_str("FILESUBTYPE 0x0\nBEGIN\n");
+        script.push_str("    BLOCK \"StringFileInfo\"\n    BEGIN\n");
+        writeln!(script, "        BLOCK \"{LANGUAGE:04x}{CODE_PAGE:04x}\"").unwrap();
+        script.push_str("        BEGIN\n");
+        for (key, value) in &self.strings {
+            // Quotes are doubled in resource scripts; rc.exe adds the NUL itself
+            let value = value.replace('"', "\"\"");
+            writeln!(script, "            VALUE \"{key}\", \"{value}\"").unwrap();
+        }
+        script.push_str("        END\n    END\n");
+        script.push_str("    BLOCK \"VarFileInfo\"\n    BEGIN\n");
+        writeln!(
+            script,
+            "        VALUE \"Translation\", {LANGUAGE:#x}, {CODE_PAGE}"
+        )
+        .unwrap();
+        script.push_str("    END\nEND\n");
+        script
+    }
+
+    /// Writes a compiled resource file holding the version resource.
+    pub fn resource_file(&self) -> Vec<u8> {
+        let mut file = empty_resource_file();
+        push_entry(
+            &mut file,
+            &[0xffff, RT_VERSION],
+            &[0xffff, VERSION_ID],
+            VERSION_MEMORY_FLAGS,
+            LANGUAGE,
+            &self.encode(),
+        );
+        file
+    }
+}
+
+/// The value of a node of a `VS_VERSIONINFO` tree.
+enum NodeValue<'a> {
+    None,
+    /// UTF-16 text, whose length is counted in characters
+    Text(&'a [u16]),
+    /// Bytes, whose length is counted in bytes
+    Binary(&'a [u8]),
+}
+
+/// Encodes a node of a `VS_VERSIONINFO` tree: its length, value length, type and key, then its
+/// value and children, each aligned to four bytes.
+fn version_node(key: &str, value: NodeValue, children: &[Vec<u8>]) -> Vec<u8> {
+    let (value_length, kind, value): (usize, u16, Vec<u8>) = match value {
+        NodeValue::None => (0, 1, Vec::new()),
+        NodeValue::Text(text) => (
+            text.len(),
+            1,
+            text.iter().flat_map(|unit| unit.to_le_bytes()).collect(),
+        ),
+        NodeValue::Binary(bytes) => (bytes.len(), 0, bytes.to_vec()),
+    };
+    let mut node = vec![0, 0];
+    node.extend((value_length as u16).to_le_bytes());
+    node.extend(kind.to_le_bytes());
+    for unit in key.encode_utf16().chain([0]) {
+        node.extend(unit.to_le_bytes());
+    }
+    if !value.is_empty() {
+        node.resize(node.len().next_multiple_of(4), 0);
+        node.extend(value);
+    }
+    for child in children {
+        node.resize(node.len().next_multiple_of(4), 0);
+        node.extend(child);
+    }
+    let length = node.len() as u16;
+    node[..2].copy_from_slice(&length.to_le_bytes());
+    node
+}
+
+/// Writes a resource script declaring `data`, the little-endian encoding of `pipl`.
+pub fn resource_script(pipl: &Pipl, data: &[u8]) -> String {
+    let mut script = String::new();
+    script.push_str("// Generated by cargo-jk from [package.metadata.jk_plugin.pipl]\n\n");
+    script.push_str("#pragma code_page(65001)\n\n");
+    writeln!(script, "{RESOURCE_ID} PiPL DISCARDABLE").unwrap();
+    script.push_str("BEGIN\n");
+
+    // The header, then one line per property
+    let mut chunks = vec![("header".to_string(), 10)];
+    for property in &pipl.properties {
+        let len = property.data(ByteOrder::Little).len().next_multiple_of(4);
+        chunks.push((
+            format!("{} {}", property.vendor(), property.key()),
+            16 + len,
+        ));
+    }
+
+    let mut words = data
+        .chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], *pair.get(1).unwrap_or(&0)]));
+    let lines: Vec<String> = chunks
+        .into_iter()
+        .map(|(label, len)| {
+            let words: Vec<String> = words
+                .by_ref()
+                .take(len / 2)
+                .map(|word| format!("0x{word:04x}"))
+                .collect();
+            format!("    /* {label} */\n    {}", words.join(", "))
+        })
+        .collect();
+    script.push_str(&lines.join(",\n"));
+    script.push('\n');
+    script.push_str("END\n");
+    script
+}
+
+/// Writes a compiled resource file holding `data` as the PiPL resource.
+pub fn resource_file(data: &[u8]) -> Vec<u8> {
+    let mut file = empty_resource_file();
+    let kind: Vec<u16> = RESOURCE_TYPE.encode_utf16().chain([0]).collect();
+    push_entry(
+        &mut file,
+        &kind,
+        &[0xffff, RESOURCE_ID],
+        MEMORY_FLAGS,
+        LANGUAGE,
+        data,
+    );
+    file
+}
+
+/// A `.res` file without resources yet.
+fn empty_resource_file() -> Vec<u8> {
+    let mut file = Vec::new();
+    // Every .res file starts with an empty resource marking it as 32-bit
+    push_entry(&mut file, &[0xffff, 0], &[0xffff, 0], 0, 0, &[]);
+    file
+}
+
+/// Files written by [`generate_version_info`].
+#[derive(Debug, Clone)]
+pub struct VersionResources {
+    /// Resource script
+    pub rc: PathBuf,
+    /// Compiled resource file, linked into the DLL
+    pub res: PathBuf,
+}
+
+/// Writes the `VERSIONINFO` resource of a plugin into `out_dir`.
+pub fn generate_version_info(
+    out_dir: &Path,
+    manifest: &PluginManifest,
+) -> Result<VersionResources, JkError> {
+    let version_info = VersionInfo::new(manifest);
+    let write = |path: PathBuf, contents: Vec<u8>| {
+        std::fs::write(&path, contents)
+            .map(|()| path.clone())
+            .map_err(|source| JkError::VersionInfo { path, source })
+    };
+    std::fs::create_dir_all(out_dir).map_err(|source| JkError::VersionInfo {
+        path: out_dir.to_path_buf(),
+        source,
+    })?;
+    Ok(VersionResources {
+        rc: write(
+            out_dir.join(format!("{}.rc", manifest.plugin_name)),
+            version_info.resource_script().into_bytes(),
+        )?,
+        res: write(
+            out_dir.join(format!("{}.res", manifest.plugin_name)),
+            version_info.resource_file(),
+        )?,
+    })
+}
+
+/// Appends a `RESOURCEHEADER` and the data, both padded to four bytes.
+fn push_entry(
+    file: &mut Vec<u8>,
+    kind: &[u16],
+    name: &[u16],
+    memory_flags: u16,
+    language: u16,
+    data: &[u8],
+) {
+    let mut header = Vec::new();
+    for unit in kind.iter().chain(name) {
+        header.extend(unit.to_le_bytes());
+    }
+    header.resize(header.len().next_multiple_of(4), 0);
+    header.extend(0u32.to_le_bytes()); // DataVersion
+    header.extend(memory_flags.to_le_bytes());
+    header.extend(language.to_le_bytes());
+    header.extend(0u32.to_le_bytes()); // Version
+    header.extend(0u32.to_le_bytes()); // Characteristics
+
+    file.extend((data.len() as u32).to_le_bytes());
+    file.extend((8 + header.len() as u32).to_le_bytes());
+    file.extend(header);
+    file.extend(data);
+    file.resize(file.len().next_multiple_of(4), 0);
+}
+
+/// Whether `file` starts with the empty resource every `.res` file begins with.
+pub fn is_resource_file(file: &[u8]) -> bool {
+    file.starts_with(&[
+        0, 0, 0, 0, 0x20, 0, 0, 0, 0xff, 0xff, 0, 0, 0xff, 0xff, 0, 0,
+    ])
+}
+
+/// Returns the data of the first resource of the named type in a `.res` file.
+pub fn find_resource<'a>(file: &'a [u8], kind: &str) -> Result<&'a [u8], String> {
+    let reader = Reader::new(file, ByteOrder::Little);
+    let mut pos = 0;
+    while pos < file.len() {
+        let mut entry = reader.at(pos);
+        let data_size = entry.u32()? as usize;
+        let header_size = entry.u32()? as usize;
+        if read_name(&mut entry)?.is_some_and(|name| name.eq_ignore_ascii_case(kind)) {
+            return reader.at(pos + header_size).bytes(data_size);
+        }
+        pos = (pos + header_size + data_size).next_multiple_of(4);
+    }
+    Err(format!("no {kind} resource"))
+}
+
+/// Reads a resource type or name: either `0xffff` and a numeric ID, or a NUL-terminated UTF-16
+/// string. Returns the string, if it is one.
+fn read_name(entry: &mut Reader) -> Result<Option<String>, String> {
+    let mut unit = entry.u16()?;
+    if unit == 0xffff {
+        entry.u16()?;
+        return Ok(None);
+    }
+    let mut units = Vec::new();
+    while unit != 0 {
+        units.push(unit);
+        unit = entry.u16()?;
+    }
+    Ok(Some(String::from_utf16_lossy(&units)))
+}
@@ -0,0 +1,82 @@
+//! Mac resource fork files (`.rsrc`), as Rez writes them.
+//!
+//! The file is a 256-byte header area (offsets and lengths of the data and map, then zeros), the
+//! resource data, each prefixed with its length, and the resource map: a copy of the header,
+//! reserved fields, then the type list and the reference lists.
+
+use super::{ByteOrder, FourCC, Reader};
+
+/// Offset of the resource data; the header and the reserved system/application area come first.
+const DATA_OFFSET: u32 = 256;
+/// Size of the map header: header copy (16), next map handle (4), file ref (2), attributes (2)
+/// and the type and name list offsets (2 each).
+const MAP_HEADER_LEN: u16 = 28;
+
+/// Builds a resource fork holding one resource of type `kind` and ID `id`.
+pub fn write_resource_fork(kind: [u8; 4], id: u16, data: &[u8]) -> Vec<u8> {
+    let data_len = 4 + data.len() as u32;
+
+    let mut map = Vec::new();
+    // Type list: count - 1, then type, resources of the type - 1 and the reference list offset
+    // (from the start of the type list)
+    let type_list_len = 2 + 8;
+    map.extend(0u16.to_be_bytes());
+    map.extend(kind);
+    map.extend(0u16.to_be_bytes());
+    map.extend((type_list_len as u16).to_be_bytes());
+    // Reference list: ID, name offset (none), attributes and 24-bit data offset, reserved handle
+    map.extend(id.to_be_bytes());
+    map.extend(0xffffu16.to_be_bytes());
+    map.extend(0u32.to_be_bytes());
+    map.extend(0u32.to_be_bytes());
+    let map_len = MAP_HEADER_LEN as u32 + map.len() as u32;
+    let map_offset = DATA_OFFSET + data_len;
+
+    let mut header = Vec::with_capacity(16);
+    header.extend(DATA_OFFSET.to_be_bytes());
+    header.extend(map_offset.to_be_bytes());
+    header.extend(data_len.to_be_bytes());
+    header.extend(map_len.to_be_bytes());
+
+    let mut fork = header.clone();
+    fork.resize(DATA_OFFSET as usize, 0);
+    fork.extend((data.len() as u32).to_be_bytes());
+    fork.extend(data);
+    fork.extend(&header);
+    fork.extend([0; 8]); // next map handle, file reference number, attributes
+    fork.extend(MAP_HEADER_LEN.to_be_bytes());
+    // The name list is empty and starts right after the reference list
+    fork.extend((map_len as u16).to_be_bytes());
+    fork.extend(map);
+    fork
+}
+
+/// Returns the data of the first resource of type `kind` in a resource fork.
+pub fn find_resource(fork: &[u8], kind: [u8; 4]) -> Result<&[u8], String> {
+    let file = Reader::new(fork, ByteOrder::Big);
+    let mut header = file.at(0);
+    let data_offset = header.u32()? as usize;
+    let map_offset = header.u32()? as usize;
+    let data_len = header.u32()? as usize;
+    let map_len = header.u32()? as usize;
+    if data_offset + data_len > fork.len() || map_offset + map_len > fork.len() {
+        return Err("not a resource fork".to_string());
+    }
+    let type_list = map_offset + file.at(map_offset + 24).u16()? as usize;
+
+    let mut types = file.at(type_list);
+    let count = types.u16()?.wrapping_add(1);
+    for _ in 0..count {
+        let found = types.four_cc()?;
+        types.u16()?; // resources of this type - 1
+        let references = type_list + types.u16()? as usize;
+        if found.0 == kind {
+            // Skip the ID and name offset; the data offset is the low 24 bits after them
+            let offset = file.at(references + 4).u32()? & 0x00ff_ffff;
+            let mut data = file.at(data_offset + offset as usize);
+            let len = data.u32()? as usize;
+            return data.bytes(len);
+        }
+    }
+    Err(format!("no {:?} resource", FourCC(kind)))
+}
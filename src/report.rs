@@ -0,0 +1,70 @@
+//! The JSON result documents printed by `--format json`.
+//!
+//! Both documents carry a `schema_version`, which is bumped whenever a field is removed or
+//! changes meaning; new fields may be added without a bump. `cargo jk schema` prints the
+//! JSON Schema of each document.
+
+use std::{fs::File, io, path::Path};
+
+use schemars::{JsonSchema, Schema};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{BuildOutput, InstallOutput};
+
+/// Version of the result document schema.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Result of `cargo jk build --format json`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BuildReport {
+    pub schema_version: u32,
+    /// One entry per built plugin
+    pub plugins: Vec<BuildOutput>,
+}
+
+/// Result of `cargo jk install --format json`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct InstallReport {
+    pub schema_version: u32,
+    /// One entry per installed plugin
+    pub plugins: Vec<InstallOutput>,
+}
+
+impl BuildReport {
+    pub fn new(plugins: Vec<BuildOutput>) -> Self {
+        BuildReport {
+            schema_version: SCHEMA_VERSION,
+            plugins,
+        }
+    }
+
+    pub fn schema() -> Schema {
+        schemars::schema_for!(BuildReport)
+    }
+}
+
+impl InstallReport {
+    pub fn new(plugins: Vec<InstallOutput>) -> Self {
+        InstallReport {
+            schema_version: SCHEMA_VERSION,
+            plugins,
+        }
+    }
+
+    pub fn schema() -> Schema {
+        schemars::schema_for!(InstallReport)
+    }
+}
+
+/// Returns the hex SHA-256 digest and size in bytes of a file.
+pub fn digest_file(path: &Path) -> io::Result<(String, u64)> {
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut File::open(path)?, &mut hasher)?;
+    let digest = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    Ok((digest, size))
+}
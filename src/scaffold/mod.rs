@@ -0,0 +1,467 @@
+//! `cargo jk new` and `cargo jk init`: plugin crates ready for `cargo jk build`.
+//!
+//! A new crate gets a `cdylib` target, the `[package.metadata.jk_plugin]` section with a `pipl`
+//! table, an entry point skeleton, a `.cargo-jk.toml` and a `.gitignore`. `init` adds the same to
+//! an existing crate, keeping the files it already has.
+
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::{check, config::WORKSPACE_CONFIG_FILE, error::JkError};
+
+const EFFECT_RS: &str = include_str!("templates/effect.rs");
+const SMART_RENDER_RS: &str = include_str!("templates/smart_render.rs");
+const AEGP_RS: &str = include_str!("templates/aegp.rs");
+const CONFIG_TOML: &str = include_str!("templates/cargo-jk.toml");
+const GITIGNORE: &str = include_str!("templates/gitignore");
+
+/// Effect menu category of new plugins.
+const DEFAULT_CATEGORY: &str = "Custom";
+
+/// Kind of plugin a new crate starts as.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Template {
+    /// An effect rendering with `PF_Cmd_RENDER`
+    #[default]
+    Effect,
+    /// An effect rendering with SmartFX (`PF_Cmd_SMART_RENDER`), without GPU rendering
+    SmartRender,
+    /// A general plugin (AEGP)
+    Aegp,
+}
+
+impl Template {
+    fn lib_rs(self) -> &'static str {
+        match self {
+            Template::Effect => EFFECT_RS,
+            Template::SmartRender => SMART_RENDER_RS,
+            Template::Aegp => AEGP_RS,
+        }
+    }
+
+    /// Symbol After Effects calls into the plugin through.
+    pub fn entry_point(self) -> &'static str {
+        match self {
+            Template::Effect | Template::SmartRender => "EffectMain",
+            Template::Aegp => "EntryPointFunc",
+        }
+    }
+
+    /// The `[package.metadata.jk_plugin]` section, with the `pipl` table matching the skeleton.
+    fn metadata(self, plugin_name: &str, identifier: &str) -> String {
+        let mut metadata = format!(
+            "[package.metadata.jk_plugin]\n\
+             plugin_name = {plugin_name:?}\n\
+             identifier = {identifier:?}\n\
+             category = {DEFAULT_CATEGORY:?}\n"
+        );
+        if self == Template::Aegp {
+            metadata.push_str("kind = \"AEGP\"\n");
+        }
+        metadata.push_str("\n[package.metadata.jk_plugin.pipl]\n");
+        metadata.push_str(match self {
+            Template::Effect => "out_flags = 0x02000000  # PF_OutFlag_DEEP_COLOR_AWARE\n",
+            Template::SmartRender => {
+                "out_flags = 0x02000000  # PF_OutFlag_DEEP_COLOR_AWARE\n\
+                 out_flags2 = 0x00001400 # PF_OutFlag2_SUPPORTS_SMART_RENDER | PF_OutFlag2_FLOAT_COLOR_AWARE\n"
+            }
+            Template::Aegp => "entry_point = \"EntryPointFunc\"\n",
+        });
+        metadata
+    }
+}
+
+/// Options for [`new_package`] and [`init_package`].
+#[derive(Debug, Clone, Default)]
+pub struct ScaffoldOptions {
+    /// Directory of the crate
+    pub path: PathBuf,
+    /// Package name of a new crate; defaults to the directory name
+    pub name: Option<String>,
+    /// Defaults to the package name in title case (`my-effect` is `My Effect`)
+    pub plugin_name: Option<String>,
+    /// Defaults to `com.example.<package name>`
+    pub identifier: Option<String>,
+    pub template: Template,
+}
+
+/// Creates a plugin crate in `options.path`, which must not exist yet. Returns the files written.
+pub fn new_package(options: &ScaffoldOptions) -> Result<Vec<PathBuf>, JkError> {
+    let dir = &options.path;
+    if dir.exists() {
+        return Err(JkError::DestinationExists(dir.clone()));
+    }
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| JkError::InvalidPackageName {
+                name: dir.display().to_string(),
+                reason: "the path has no directory name; use --name".to_string(),
+            })?,
+    };
+    check_package_name(&name)?;
+    let (plugin_name, identifier) = plugin_names(options, &name)?;
+
+    let manifest = format!(
+        "[package]\n\
+         name = {name:?}\n\
+         version = \"0.1.0\"\n\
+         edition = \"2024\"\n\
+         \n\
+         [lib]\n\
+         crate-type = [\"cdylib\"]\n\
+         \n\
+         [dependencies]\n\
+         \n\
+         {}",
+        options.template.metadata(&plugin_name, &identifier)
+    );
+    let lib_rs = options
+        .template
+        .lib_rs()
+        .replace("{{plugin_name}}", &plugin_name);
+
+    let mut written = Vec::new();
+    for (file, contents) in [
+        ("Cargo.toml", manifest.as_str()),
+        ("src/lib.rs", &lib_rs),
+        (WORKSPACE_CONFIG_FILE, CONFIG_TOML),
+        (".gitignore", GITIGNORE),
+    ] {
+        written.push(write(&dir.join(file), contents)?);
+    }
+    Ok(written)
+}
+
+/// Makes the crate in `options.path` a plugin: adds the metadata section (and a `cdylib` `[lib]`
+/// if it has none) to its `Cargo.toml`, and writes the files `new` would that it is missing.
+/// Returns the files written or changed.
+pub fn init_package(options: &ScaffoldOptions) -> Result<Vec<PathBuf>, JkError> {
+    let dir = &options.path;
+    let manifest_path = dir.join("Cargo.toml");
+    let invalid = |message: &str| JkError::InitManifest {
+        path: manifest_path.clone(),
+        message: message.to_string(),
+    };
+    let mut manifest = std::fs::read_to_string(&manifest_path)
+        .map_err(|e| invalid(&format!("failed to read it: {e}")))?;
+    let document: toml::Table = manifest
+        .parse()
+        .map_err(|e: toml::de::Error| invalid(e.message()))?;
+
+    let package = document
+        .get("package")
+        .and_then(toml::Value::as_table)
+        .ok_or_else(|| invalid("it has no [package] section"))?;
+    if package
+        .get("metadata")
+        .and_then(|metadata| metadata.get("jk_plugin"))
+        .is_some()
+    {
+        return Err(invalid(
+            "it already has a [package.metadata.jk_plugin] section",
+        ));
+    }
+    let name = package
+        .get("name")
+        .and_then(toml::Value::as_str)
+        .ok_or_else(|| invalid("[package] has no name"))?;
+    let (plugin_name, identifier) = plugin_names(options, name)?;
+
+    let mut additions = String::new();
+    match document.get("lib").map(|lib| lib.get("crate-type")) {
+        None => additions.push_str("[lib]\ncrate-type = [\"cdylib\"]\n\n"),
+        Some(Some(types))
+            if types
+                .as_array()
+                .is_some_and(|types| types.iter().any(|t| t.as_str() == Some("cdylib"))) => {}
+        Some(_) => {
+            return Err(invalid(
+                "its [lib] section does not build a cdylib; add \"cdylib\" to `crate-type`",
+            ));
+        }
+    }
+    additions.push_str(&options.template.metadata(&plugin_name, &identifier));
+    if !manifest.ends_with('\n') {
+        manifest.push('\n');
+    }
+    manifest.push('\n');
+    manifest.push_str(&additions);
+    // A `package.metadata` inline table cannot take a section below it
+    if let Err(e) = manifest.parse::<toml::Table>() {
+        return Err(invalid(&format!(
+            "adding [package.metadata.jk_plugin] would make it invalid ({}); \
+             write `package.metadata` as a [package.metadata] section instead of an inline table",
+            e.message()
+        )));
+    }
+
+    let mut written = vec![write(&manifest_path, &manifest)?];
+    let lib_rs = options
+        .template
+        .lib_rs()
+        .replace("{{plugin_name}}", &plugin_name);
+    for (file, contents) in [
+        ("src/lib.rs", lib_rs.as_str()),
+        (WORKSPACE_CONFIG_FILE, CONFIG_TOML),
+        (".gitignore", GITIGNORE),
+    ] {
+        let path = dir.join(file);
+        if !path.exists() {
+            written.push(write(&path, contents)?);
+        }
+    }
+    Ok(written)
+}
+
+/// The plugin name and identifier, from the options or derived from the package name.
+fn plugin_names(options: &ScaffoldOptions, package: &str) -> Result<(String, String), JkError> {
+    let plugin_name = options.plugin_name.clone().unwrap_or_else(|| {
+        package
+            .split(['-', '_'])
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                let mut chars = word.chars();
+                chars
+                    .next()
+                    .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    });
+    check::check_file_name(&plugin_name).map_err(|reason| JkError::InvalidPluginName {
+        name: plugin_name.clone(),
+        reason,
+    })?;
+    let identifier = options.identifier.clone().unwrap_or_else(|| {
+        let name: String = package
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect();
+        format!("com.example.{}", name.to_ascii_lowercase())
+    });
+    if !check::is_reverse_dns(&identifier) {
+        return Err(JkError::InvalidIdentifier(identifier));
+    }
+    Ok((plugin_name, identifier))
+}
+
+/// Rejects names cargo would not accept for a package.
+fn check_package_name(name: &str) -> Result<(), JkError> {
+    let invalid = |reason: &str| {
+        Err(JkError::InvalidPackageName {
+            name: name.to_string(),
+            reason: reason.to_string(),
+        })
+    };
+    match name.chars().next() {
+        None => invalid("it is empty"),
+        Some(c) if c.is_ascii_digit() => invalid("it starts with a digit"),
+        _ if !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+        {
+            invalid("only ASCII letters, digits, `-` and `_` are allowed")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn write(path: &Path, contents: &str) -> Result<PathBuf, JkError> {
+    let scaffold = |source| JkError::Scaffold {
+        path: path.to_path_buf(),
+        source,
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(scaffold)?;
+    }
+    std::fs::write(path, contents).map_err(scaffold)?;
+    Ok(path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use cargo_metadata::{MetadataCommand, Package};
+
+    use super::*;
+    use crate::{PluginManifest, pipl::PluginKind};
+
+    /// The crate in `dir` as cargo reads it, after checking its plugin metadata.
+    fn checked_package(dir: &Path) -> Package {
+        let metadata = MetadataCommand::new()
+            .manifest_path(dir.join("Cargo.toml"))
+            .no_deps()
+            .exec()
+            .unwrap();
+        let package = metadata.root_package().unwrap().clone();
+        let problems = check::check_packages(std::slice::from_ref(&package), &[]);
+        assert!(problems.is_empty(), "{problems:?}");
+        crate::package::cdylib_target(&package).unwrap();
+        package
+    }
+
+    fn options(path: PathBuf, template: Template) -> ScaffoldOptions {
+        ScaffoldOptions {
+            path,
+            template,
+            ..ScaffoldOptions::default()
+        }
+    }
+
+    #[test]
+    fn creates_a_checked_crate_for_every_template() {
+        let root = tempfile::tempdir().unwrap();
+        for template in Template::value_variants() {
+            let dir = root
+                .path()
+                .join(format!("{template:?}-glow").to_lowercase());
+            let written = new_package(&options(dir.clone(), *template)).unwrap();
+            assert_eq!(
+                written,
+                [
+                    "Cargo.toml",
+                    "src/lib.rs",
+                    WORKSPACE_CONFIG_FILE,
+                    ".gitignore"
+                ]
+                .map(|f| dir.join(f))
+            );
+            // The template build script is gone; cargo-jk links the Windows resources itself
+            assert!(!dir.join("build.rs").exists());
+
+            let manifest = PluginManifest::resolve(&checked_package(&dir)).unwrap();
+            let name = dir.file_name().unwrap().to_str().unwrap();
+            assert_eq!(manifest.package, name);
+            let lib_rs = std::fs::read_to_string(dir.join("src/lib.rs")).unwrap();
+            assert!(lib_rs.contains(template.entry_point()), "{template:?}");
+            assert!(!lib_rs.contains("{{plugin_name}}"));
+            match template {
+                Template::Aegp => assert_eq!(manifest.kind, PluginKind::Aegp),
+                _ => assert_eq!(manifest.kind, PluginKind::Effect),
+            }
+            assert!(manifest.pipl.is_some());
+        }
+
+        let glow = root.path().join("smartrender-glow");
+        let manifest = PluginManifest::resolve(&checked_package(&glow)).unwrap();
+        assert_eq!(manifest.plugin_name, "Smartrender Glow");
+        assert_eq!(manifest.identifier, "com.example.smartrenderglow");
+        assert_eq!(manifest.category.as_deref(), Some(DEFAULT_CATEGORY));
+    }
+
+    #[test]
+    fn does_not_create_over_an_existing_directory_or_with_bad_names() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            new_package(&options(root.path().to_path_buf(), Template::Effect)),
+            Err(JkError::DestinationExists(_))
+        ));
+        assert!(matches!(
+            new_package(&options(root.path().join("2glow"), Template::Effect)),
+            Err(JkError::InvalidPackageName { .. })
+        ));
+        let options = ScaffoldOptions {
+            identifier: Some("glow".to_string()),
+            ..options(root.path().join("glow"), Template::Effect)
+        };
+        assert!(matches!(
+            new_package(&options),
+            Err(JkError::InvalidIdentifier(identifier)) if identifier == "glow"
+        ));
+        assert!(!root.path().join("glow").exists());
+    }
+
+    /// A crate with the given `Cargo.toml` and a `src/lib.rs`.
+    fn existing_crate(root: &Path, name: &str, manifest: &str) -> PathBuf {
+        let dir = root.join(name);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "// existing\n").unwrap();
+        std::fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+        dir
+    }
+
+    const PACKAGE: &str = "[package]\nname = \"glow\"\nversion = \"0.1.0\"\nedition = \"2024\"\n";
+
+    #[test]
+    fn initializes_crates_with_and_without_a_lib_section() {
+        let root = tempfile::tempdir().unwrap();
+
+        let dir = existing_crate(root.path(), "plain", PACKAGE);
+        let written = init_package(&options(dir.clone(), Template::Effect)).unwrap();
+        assert_eq!(
+            written,
+            [
+                dir.join("Cargo.toml"),
+                dir.join(WORKSPACE_CONFIG_FILE),
+                dir.join(".gitignore")
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("src/lib.rs")).unwrap(),
+            "// existing\n"
+        );
+        let manifest = std::fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+        assert!(manifest.starts_with(PACKAGE));
+        assert!(manifest.contains("[lib]\ncrate-type = [\"cdylib\"]\n"));
+        checked_package(&dir);
+
+        let with_lib = format!("{PACKAGE}\n[lib]\ncrate-type = [\"rlib\", \"cdylib\"]");
+        let dir = existing_crate(root.path(), "lib", &with_lib);
+        init_package(&options(dir.clone(), Template::Aegp)).unwrap();
+        let manifest = std::fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+        assert_eq!(manifest.matches("[lib]").count(), 1);
+        assert!(manifest.contains("kind = \"AEGP\""));
+        checked_package(&dir);
+
+        // Dotted keys leave `package.metadata` open for a section below it
+        let dotted = format!("{PACKAGE}metadata.docs.rs.all-features = true\n");
+        let dir = existing_crate(root.path(), "dotted", &dotted);
+        init_package(&options(dir.clone(), Template::Effect)).unwrap();
+        checked_package(&dir);
+    }
+
+    #[test]
+    fn refuses_manifests_it_cannot_extend() {
+        let root = tempfile::tempdir().unwrap();
+        for (name, manifest, reason) in [
+            (
+                "rlib",
+                format!("{PACKAGE}\n[lib]\ncrate-type = [\"rlib\"]\n"),
+                "does not build a cdylib",
+            ),
+            (
+                "plugin",
+                format!("{PACKAGE}\n[package.metadata.jk_plugin]\nplugin_name = \"Glow\"\n"),
+                "already has a [package.metadata.jk_plugin] section",
+            ),
+            (
+                "inline",
+                format!("{PACKAGE}metadata = {{ docs = {{ rs = {{ all-features = true }} }} }}\n"),
+                "instead of an inline table",
+            ),
+            (
+                "workspace",
+                "[workspace]\n".to_string(),
+                "no [package] section",
+            ),
+        ] {
+            let dir = existing_crate(root.path(), name, &manifest);
+            match init_package(&options(dir.clone(), Template::Effect)) {
+                Err(JkError::InitManifest { message, .. }) => {
+                    assert!(message.contains(reason), "{name}: {message}")
+                }
+                other => panic!("{name}: expected InitManifest, got {other:?}"),
+            }
+            // Nothing is written
+            assert_eq!(
+                std::fs::read_to_string(dir.join("Cargo.toml")).unwrap(),
+                manifest
+            );
+            assert!(!dir.join(WORKSPACE_CONFIG_FILE).exists());
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! {{plugin_name}}, an After Effects general plugin (AEGP).
+//!
+//! `cargo jk build` generates the PiPL from `[package.metadata.jk_plugin]` in Cargo.toml.
+
+use std::ffi::c_void;
+
+const A_ERR_NONE: i32 = 0;
+
+/// Called once when After Effects loads the plugin.
+///
+/// # Safety
+///
+/// Only After Effects calls this, with the pointers its SDK documents.
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn EntryPointFunc(
+    _pica_basic: *mut c_void,
+    _major_version: i32,
+    _minor_version: i32,
+    _aegp_plugin_id: i32,
+    _global_refcon: *mut *mut c_void,
+) -> i32 {
+    // TODO: acquire suites through `pica_basic` (an `SPBasicSuite`) and register commands or
+    // hooks with `AEGP_RegisterSuites`
+    A_ERR_NONE
+}
@@ -0,0 +1,109 @@
+//! {{plugin_name}}, an After Effects effect.
+//!
+//! `cargo jk build` generates the PiPL from `[package.metadata.jk_plugin]` in Cargo.toml. The
+//! flags set in `PF_Cmd_GLOBAL_SETUP` must match `out_flags` in its `pipl` table.
+
+use std::ffi::{c_char, c_void};
+
+const PF_ERR_NONE: i32 = 0;
+
+const PF_CMD_ABOUT: i32 = 0;
+const PF_CMD_GLOBAL_SETUP: i32 = 1;
+const PF_CMD_PARAMS_SETUP: i32 = 4;
+const PF_CMD_RENDER: i32 = 11;
+
+const PF_OUT_FLAG_DEEP_COLOR_AWARE: i32 = 0x0200_0000;
+
+const PF_STAGE_RELEASE: u32 = 3;
+
+/// `PF_VERSION` of the package version, which is what cargo-jk writes into the PiPL. Update the
+/// stage and build here if the plugin gets a pre-release or `version` in its metadata.
+const MY_VERSION: u32 = pf_version(
+    number(env!("CARGO_PKG_VERSION_MAJOR")),
+    number(env!("CARGO_PKG_VERSION_MINOR")),
+    number(env!("CARGO_PKG_VERSION_PATCH")),
+    PF_STAGE_RELEASE,
+    1,
+);
+
+const ABOUT: &str = concat!("{{plugin_name}} ", env!("CARGO_PKG_VERSION"));
+
+/// `PF_OutData`, up to the fields this plugin sets.
+#[allow(dead_code)]
+#[repr(C)]
+struct OutData {
+    my_version: u32,
+    name: [c_char; 32],
+    global_data: *mut c_void,
+    num_params: i32,
+    sequence_data: *mut c_void,
+    flat_sdata_size: i32,
+    frame_data: *mut c_void,
+    width: i32,
+    height: i32,
+    origin: [i32; 2],
+    out_flags: i32,
+    return_msg: [c_char; 256],
+}
+
+/// Called by After Effects for every command.
+///
+/// # Safety
+///
+/// Only After Effects calls this, with the pointers its SDK documents.
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn EffectMain(
+    cmd: i32,
+    _in_data: *mut c_void,
+    out_data: *mut c_void,
+    _params: *mut *mut c_void,
+    _output: *mut c_void,
+    _extra: *mut c_void,
+) -> i32 {
+    let out_data = unsafe { &mut *out_data.cast::<OutData>() };
+    match cmd {
+        PF_CMD_ABOUT => set_message(&mut out_data.return_msg, ABOUT),
+        PF_CMD_GLOBAL_SETUP => {
+            out_data.my_version = MY_VERSION;
+            out_data.out_flags = PF_OUT_FLAG_DEEP_COLOR_AWARE;
+        }
+        PF_CMD_PARAMS_SETUP => {
+            // The input layer; add parameters with `in_data->inter.add_param`
+            out_data.num_params = 1;
+        }
+        PF_CMD_RENDER => {
+            // TODO: render `params[0]` (the input layer) into `output`
+        }
+        _ => {}
+    }
+    PF_ERR_NONE
+}
+
+fn set_message(buffer: &mut [c_char], message: &str) {
+    let len = message.len().min(buffer.len() - 1);
+    for (dst, &src) in buffer.iter_mut().zip(&message.as_bytes()[..len]) {
+        *dst = src as c_char;
+    }
+    buffer[len] = 0;
+}
+
+const fn pf_version(major: u32, minor: u32, bug: u32, stage: u32, build: u32) -> u32 {
+    ((major >> 3) & 0xf) << 26
+        | (major & 0x7) << 19
+        | (minor & 0xf) << 15
+        | (bug & 0xf) << 11
+        | stage << 9
+        | (build & 0x1ff)
+}
+
+const fn number(digits: &str) -> u32 {
+    let digits = digits.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        value = value * 10 + (digits[i] - b'0') as u32;
+        i += 1;
+    }
+    value
+}
@@ -0,0 +1,170 @@
+//! Locating and validating the Adobe After Effects SDK.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{PluginManifest, error::JkError};
+
+/// Header that every After Effects SDK ships, relative to the SDK root.
+const EFFECT_HEADER: &str = "Examples/Headers/AE_Effect.h";
+/// Directory with the PiPL resource definitions and tools, relative to the SDK root.
+const RESOURCES_DIR: &str = "Examples/Resources";
+
+/// An After Effects SDK whose layout has been checked.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AeSdk {
+    /// The SDK root directory (`AESDK_ROOT`)
+    pub root: PathBuf,
+    /// Version parsed from `AE_Effect.h`, if its defines could be found
+    pub version: Option<SdkVersion>,
+    /// `PiPLtool.exe`, if the SDK ships it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pipl_tool: Option<PathBuf>,
+}
+
+/// Version of the effect API an SDK declares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SdkVersion {
+    /// `PF_PLUG_IN_VERSION`
+    pub major: u32,
+    /// `PF_PLUG_IN_SUBVERS`
+    pub minor: u32,
+    /// `AE_SDK_VERSION`, for SDKs that define it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sdk: Option<u32>,
+}
+
+impl fmt::Display for SdkVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if let Some(sdk) = self.sdk {
+            write!(f, " (AE_SDK_VERSION {sdk})")?;
+        }
+        Ok(())
+    }
+}
+
+impl AeSdk {
+    /// Finds the SDK for a plugin: `AESDK_ROOT` or `aesdk_root` from the cargo-jk config, or
+    /// else `aesdk_root` in `[package.metadata.jk_plugin]`, relative to the package's manifest.
+    pub fn resolve(configured: Option<&Path>, manifest: &PluginManifest) -> Result<AeSdk, JkError> {
+        let root = match (configured, &manifest.aesdk_root) {
+            (Some(root), _) => root.to_path_buf(),
+            (None, Some(root)) => root.clone(),
+            (None, None) => return Err(JkError::MissingAesdkRoot),
+        };
+        AeSdk::open(&root)
+    }
+
+    /// Checks that `root` looks like an After Effects SDK and reads its version.
+    pub fn open(root: &Path) -> Result<AeSdk, JkError> {
+        let invalid = |message: String| JkError::InvalidAesdk {
+            root: root.to_path_buf(),
+            message,
+        };
+        if !root.is_dir() {
+            return Err(invalid("which is not a directory".to_string()));
+        }
+
+        let header_path = root.join(EFFECT_HEADER);
+        let header = std::fs::read_to_string(&header_path)
+            .map_err(|_| invalid(format!("but no {EFFECT_HEADER} was found")))?;
+        let resources = root.join(RESOURCES_DIR);
+        if !resources.is_dir() {
+            return Err(invalid(format!(
+                "but no {RESOURCES_DIR} directory was found"
+            )));
+        }
+
+        let pipl_tool = Some(resources.join("PiPLtool.exe")).filter(|p| p.is_file());
+        Ok(AeSdk {
+            root: root.to_path_buf(),
+            version: parse_version(&header),
+            pipl_tool,
+        })
+    }
+}
+
+/// Reads the `PF_PLUG_IN_VERSION`/`PF_PLUG_IN_SUBVERS` (and `AE_SDK_VERSION`) defines.
+fn parse_version(header: &str) -> Option<SdkVersion> {
+    let define = |name: &str| {
+        header.lines().find_map(|line| {
+            let mut words = line.split_whitespace();
+            (words.next()? == "#define" && words.next()? == name)
+                .then(|| {
+                    words
+                        .next()?
+                        .trim_end_matches(['L', 'l'])
+                        .parse::<u32>()
+                        .ok()
+                })
+                .flatten()
+        })
+    };
+    Some(SdkVersion {
+        major: define("PF_PLUG_IN_VERSION")?,
+        minor: define("PF_PLUG_IN_SUBVERS")?,
+        sdk: define("AE_SDK_VERSION"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_version_defines() {
+        // Laid out like AE_Effect.h, with tabs, comments and a long suffix
+        let header = "\
+#ifndef _H_AE_EFFECT
+#define _H_AE_EFFECT
+
+#define PF_PLUG_IN_VERSION\t\t\t13\t\t/* manifest version */
+#define PF_PLUG_IN_SUBVERS\t\t\t28L\t\t/* protocol version */
+
+#define AE_SDK_VERSION 2500
+";
+        assert_eq!(
+            parse_version(header),
+            Some(SdkVersion {
+                major: 13,
+                minor: 28,
+                sdk: Some(2500),
+            })
+        );
+        assert_eq!(
+            parse_version(header).unwrap().to_string(),
+            "13.28 (AE_SDK_VERSION 2500)"
+        );
+
+        // Older SDKs do not define AE_SDK_VERSION
+        let header = "#define PF_PLUG_IN_VERSION 13\n#define PF_PLUG_IN_SUBVERS 17\n";
+        assert_eq!(
+            parse_version(header),
+            Some(SdkVersion {
+                major: 13,
+                minor: 17,
+                sdk: None,
+            })
+        );
+    }
+
+    #[test]
+    fn has_no_version_without_the_defines() {
+        assert_eq!(parse_version(""), None);
+        // Mentioning a define is not defining it
+        assert_eq!(
+            parse_version("#define PF_PLUG_IN_VERSION 13\n#ifdef PF_PLUG_IN_SUBVERS\n#endif\n"),
+            None
+        );
+        assert_eq!(
+            parse_version("#define PF_PLUG_IN_VERSION X\n#define PF_PLUG_IN_SUBVERS 28\n"),
+            None
+        );
+    }
+}
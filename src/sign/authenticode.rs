@@ -0,0 +1,178 @@
+//! Checks that a PE file carries an Authenticode signature over its current contents.
+//!
+//! The PKCS#7 blob is not parsed: the check hashes the file the way Authenticode does and looks
+//! for the digest in the signature, which catches signers that silently did nothing and files
+//! modified after signing. Whether the certificate is trusted is left to `signtool` and
+//! `osslsigncode`.
+
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+const PE_SIGNATURE: &[u8] = b"PE\0\0";
+const COFF_HEADER_SIZE: usize = 20;
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+/// Offset of `CheckSum` in the optional header, the same for PE32 and PE32+
+const CHECKSUM_OFFSET: usize = 64;
+/// Index of the certificate table among the data directories
+const CERTIFICATE_TABLE: usize = 4;
+const WIN_CERT_TYPE_PKCS_SIGNED_DATA: u16 = 2;
+
+/// Fails with a reason unless `data` is a PE file whose Authenticode signature hashes it as
+/// it is now.
+pub fn verify(data: &[u8]) -> Result<(), String> {
+    let u16_at = |offset: usize| {
+        data.get(offset..offset + 2)
+            .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or("it is truncated")
+    };
+    let u32_at = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or("it is truncated")
+    };
+    if !data.starts_with(b"MZ") {
+        return Err("it is not a PE file".to_string());
+    }
+    let pe = u32_at(0x3c)? as usize;
+    if data.get(pe..pe + 4) != Some(PE_SIGNATURE) {
+        return Err("it is not a PE file".to_string());
+    }
+    let optional_header = pe + 4 + COFF_HEADER_SIZE;
+    let (directory_count, directories) = match u16_at(optional_header)? {
+        PE32_MAGIC => (optional_header + 92, optional_header + 96),
+        PE32_PLUS_MAGIC => (optional_header + 108, optional_header + 112),
+        magic => {
+            return Err(format!(
+                "it has an unknown optional header magic {magic:#x}"
+            ));
+        }
+    };
+    if (u32_at(directory_count)? as usize) <= CERTIFICATE_TABLE {
+        return Err("it has no Authenticode signature".to_string());
+    }
+    let entry = directories + CERTIFICATE_TABLE * 8;
+    let (table, table_size) = (u32_at(entry)? as usize, u32_at(entry + 4)? as usize);
+    if table == 0 || table_size == 0 {
+        return Err("it has no Authenticode signature".to_string());
+    }
+    let certificates = data
+        .get(table..table + table_size)
+        .filter(|_| table >= entry + 8)
+        .ok_or("its certificate table is outside the file")?;
+
+    // Everything but the checksum, the certificate table entry and the table itself
+    let checksum = optional_header + CHECKSUM_OFFSET;
+    let hashed = [
+        &data[..checksum],
+        &data[checksum + 4..entry],
+        &data[entry + 8..table],
+        &data[table + table_size..],
+    ];
+    let digests = [
+        digest::<Sha256>(&hashed),
+        digest::<Sha1>(&hashed),
+        digest::<Sha384>(&hashed),
+        digest::<Sha512>(&hashed),
+    ];
+
+    let mut offset = 0;
+    let mut signatures = 0;
+    while offset + 8 <= certificates.len() {
+        let length = u32::from_le_bytes(certificates[offset..offset + 4].try_into().unwrap());
+        let kind = u16::from_le_bytes(certificates[offset + 6..offset + 8].try_into().unwrap());
+        let certificate = certificates
+            .get(offset + 8..offset + length as usize)
+            .ok_or("its certificate table is truncated")?;
+        if kind == WIN_CERT_TYPE_PKCS_SIGNED_DATA {
+            signatures += 1;
+            if digests.iter().any(|digest| contains(certificate, digest)) {
+                return Ok(());
+            }
+        }
+        // Entries are aligned to 8 bytes
+        offset += (length as usize).max(8).next_multiple_of(8);
+    }
+    match signatures {
+        0 => Err("it has no Authenticode signature".to_string()),
+        _ => Err("its Authenticode signature does not match its contents".to_string()),
+    }
+}
+
+fn digest<D: Digest>(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = D::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pe");
+        std::fs::read(path.join(name)).unwrap()
+    }
+
+    #[test]
+    fn accepts_files_signed_over_their_contents() {
+        verify(&fixture("machot.signed.dll")).unwrap();
+        verify(&fixture("machot.sha1-signed.dll")).unwrap();
+    }
+
+    #[test]
+    fn rejects_unsigned_files() {
+        assert_eq!(
+            verify(&fixture("machot.dll")),
+            Err("it has no Authenticode signature".to_string())
+        );
+        assert_eq!(
+            verify(&fixture("machot.dll")[..0x100]),
+            Err("it is truncated".to_string())
+        );
+        let dylib = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/macho/libmachot.dylib");
+        assert_eq!(
+            verify(&std::fs::read(dylib).unwrap()),
+            Err("it is not a PE file".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_files_modified_after_signing() {
+        for name in ["machot.signed.dll", "machot.sha1-signed.dll"] {
+            let mut data = fixture(name);
+            // A byte of `.text`, which starts at 0x400
+            data[0x400] ^= 1;
+            assert_eq!(
+                verify(&data),
+                Err("its Authenticode signature does not match its contents".to_string()),
+                "{name}"
+            );
+        }
+
+        // Neither the checksum nor the certificate table are covered by the digest
+        let mut data = fixture("machot.signed.dll");
+        let pe = u32::from_le_bytes(data[0x3c..0x40].try_into().unwrap()) as usize;
+        data[pe + 4 + COFF_HEADER_SIZE + CHECKSUM_OFFSET] ^= 1;
+        verify(&data).unwrap();
+    }
+
+    #[test]
+    fn rejects_certificate_tables_past_the_end_of_the_file() {
+        let mut data = fixture("machot.signed.dll");
+        data.truncate(data.len() - 8);
+        assert_eq!(
+            verify(&data),
+            Err("its certificate table is outside the file".to_string())
+        );
+    }
+}
@@ -0,0 +1,349 @@
+//! `Contents/_CodeSignature/CodeResources`: the hashes of a bundle's files besides its
+//! executable, with the rules `codesign` writes by default.
+
+use std::{
+    collections::BTreeMap,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use plist::{Dictionary, Value};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// A rule: a path regex, and either `true` or its options.
+type Rule = (&'static str, &'static [(&'static str, RuleValue)]);
+
+#[derive(Clone, Copy)]
+enum RuleValue {
+    Flag,
+    Weight(i64),
+}
+
+const RULES: &[Rule] = &[
+    ("^Resources/", &[]),
+    (
+        "^Resources/.*\\.lproj/",
+        &[
+            ("optional", RuleValue::Flag),
+            ("weight", RuleValue::Weight(1000)),
+        ],
+    ),
+    (
+        "^Resources/.*\\.lproj/locversion.plist$",
+        &[
+            ("omit", RuleValue::Flag),
+            ("weight", RuleValue::Weight(1100)),
+        ],
+    ),
+    (
+        "^Resources/Base\\.lproj/",
+        &[("weight", RuleValue::Weight(1010))],
+    ),
+    ("^version.plist$", &[]),
+];
+
+const RULES2: &[Rule] = &[
+    (".*\\.dSYM($|/)", &[("weight", RuleValue::Weight(11))]),
+    (
+        "^(.*/)?\\.DS_Store$",
+        &[
+            ("omit", RuleValue::Flag),
+            ("weight", RuleValue::Weight(2000)),
+        ],
+    ),
+    (
+        "^(Frameworks|SharedFrameworks|PlugIns|Plug-ins|XPCServices|Helpers|MacOS|Library/(Automator|Spotlight|LoginItems))/",
+        &[
+            ("nested", RuleValue::Flag),
+            ("weight", RuleValue::Weight(10)),
+        ],
+    ),
+    ("^.*", &[]),
+    (
+        "^Info\\.plist$",
+        &[("omit", RuleValue::Flag), ("weight", RuleValue::Weight(20))],
+    ),
+    (
+        "^PkgInfo$",
+        &[("omit", RuleValue::Flag), ("weight", RuleValue::Weight(20))],
+    ),
+    ("^Resources/", &[("weight", RuleValue::Weight(20))]),
+    (
+        "^Resources/.*\\.lproj/",
+        &[
+            ("optional", RuleValue::Flag),
+            ("weight", RuleValue::Weight(1000)),
+        ],
+    ),
+    (
+        "^Resources/.*\\.lproj/locversion.plist$",
+        &[
+            ("omit", RuleValue::Flag),
+            ("weight", RuleValue::Weight(1100)),
+        ],
+    ),
+    (
+        "^Resources/Base\\.lproj/",
+        &[("weight", RuleValue::Weight(1010))],
+    ),
+    (
+        "^[^/]+$",
+        &[
+            ("nested", RuleValue::Flag),
+            ("weight", RuleValue::Weight(10)),
+        ],
+    ),
+    (
+        "^embedded\\.provisionprofile$",
+        &[("weight", RuleValue::Weight(20))],
+    ),
+    ("^version\\.plist$", &[("weight", RuleValue::Weight(20))]),
+];
+
+/// Builds the `CodeResources` of the bundle whose `Contents` directory is `contents`.
+///
+/// Every file is sealed except `Info.plist` and `PkgInfo` (which the CodeDirectory covers or
+/// the rules omit), `.DS_Store` and `locversion.plist` files, and the main executable. Files in
+/// localizations other than `Base.lproj` are optional. Nested code in `Contents/MacOS` is not
+/// supported.
+pub fn code_resources(contents: &Path, executable: &Path) -> Result<Vec<u8>, String> {
+    let mut files = BTreeMap::new();
+    collect(contents, contents, &mut files)?;
+
+    let mut files1 = Dictionary::new();
+    let mut files2 = Dictionary::new();
+    for (relative, path) in files {
+        if matches!(relative.as_str(), "Info.plist" | "PkgInfo")
+            || relative.starts_with("_CodeSignature/")
+            || relative.ends_with("/.DS_Store")
+            || relative == ".DS_Store"
+            || (relative.starts_with("Resources/") && relative.ends_with(".lproj/locversion.plist"))
+            || path == executable
+        {
+            continue;
+        }
+        if relative.starts_with("MacOS/") {
+            return Err(format!(
+                "{relative} is nested code, which the builtin signer cannot seal; use the codesign or rcodesign backend"
+            ));
+        }
+        let optional = relative.starts_with("Resources/")
+            && relative.contains(".lproj/")
+            && !relative.starts_with("Resources/Base.lproj/");
+        let metadata = std::fs::symlink_metadata(&path)
+            .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+        if metadata.is_symlink() {
+            let target = std::fs::read_link(&path)
+                .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+            let mut entry = Dictionary::new();
+            entry.insert(
+                "symlink".to_string(),
+                Value::String(target.to_string_lossy().into_owned()),
+            );
+            files2.insert(relative, Value::Dictionary(entry));
+            continue;
+        }
+        let data =
+            std::fs::read(&path).map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+        let sha1 = Value::Data(Sha1::digest(&data).to_vec());
+        let mut entry = Dictionary::new();
+        entry.insert(
+            "hash2".to_string(),
+            Value::Data(Sha256::digest(&data).to_vec()),
+        );
+        if optional {
+            entry.insert("optional".to_string(), Value::Boolean(true));
+        }
+        files2.insert(relative.clone(), Value::Dictionary(entry));
+
+        // The version 1 seal only covers `Resources` and `version.plist`
+        if relative.starts_with("Resources/") || relative == "version.plist" {
+            let resource = match optional {
+                true => {
+                    let mut entry = Dictionary::new();
+                    entry.insert("hash".to_string(), sha1);
+                    entry.insert("optional".to_string(), Value::Boolean(true));
+                    Value::Dictionary(entry)
+                }
+                false => sha1,
+            };
+            files1.insert(relative, resource);
+        }
+    }
+
+    let mut root = Dictionary::new();
+    root.insert("files".to_string(), Value::Dictionary(files1));
+    root.insert("files2".to_string(), Value::Dictionary(files2));
+    root.insert("rules".to_string(), rules(RULES));
+    root.insert("rules2".to_string(), rules(RULES2));
+    let mut xml = Cursor::new(Vec::new());
+    Value::Dictionary(root)
+        .to_writer_xml(&mut xml)
+        .map_err(|e| e.to_string())?;
+    let mut xml = xml.into_inner();
+    xml.push(b'\n');
+    Ok(xml)
+}
+
+fn rules(rules: &[Rule]) -> Value {
+    let mut dict = Dictionary::new();
+    for (pattern, options) in rules {
+        let value = match options {
+            [] => Value::Boolean(true),
+            options => Value::Dictionary(
+                options
+                    .iter()
+                    .map(|(key, value)| {
+                        let value = match value {
+                            RuleValue::Flag => Value::Boolean(true),
+                            RuleValue::Weight(weight) => Value::Real(*weight as f64),
+                        };
+                        (key.to_string(), value)
+                    })
+                    .collect(),
+            ),
+        };
+        dict.insert(pattern.to_string(), value);
+    }
+    // Sorted like the property lists `codesign` writes
+    dict.sort_keys();
+    Value::Dictionary(dict)
+}
+
+/// Collects the files below `dir`, keyed by their `/`-separated path relative to `root`.
+fn collect(root: &Path, dir: &Path, files: &mut BTreeMap<String, PathBuf>) -> Result<(), String> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| format!("cannot read {}: {e}", dir.display()))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("cannot read {}: {e}", dir.display()))?
+            .path();
+        let metadata = std::fs::symlink_metadata(&path)
+            .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+        if metadata.is_dir() {
+            collect(root, &path, files)?;
+        } else {
+            let relative = path
+                .strip_prefix(root)
+                .expect("collected paths are below the root")
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.insert(relative, path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lays out the `Contents` of the bundle `tests/fixtures/sign/CodeResources` was written for.
+    fn bundle(dir: &Path) -> (PathBuf, PathBuf) {
+        let contents = dir.join("Glow.plugin/Contents");
+        for (path, data) in [
+            ("MacOS/Glow", "not hashed"),
+            ("Info.plist", "not hashed"),
+            ("PkgInfo", "eFKTFXTC"),
+            ("version.plist", "version"),
+            ("Resources/Glow.rsrc", "PiPL"),
+            ("Resources/Presets/Soft.ffx", "preset"),
+            (
+                "Resources/en.lproj/Localizable.strings",
+                "\"Glow\" = \"Glow\";\n",
+            ),
+            (
+                "Resources/Base.lproj/Localizable.strings",
+                "\"Glow\" = \"Base\";\n",
+            ),
+        ] {
+            let path = contents.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("Glow.rsrc", contents.join("Resources/Current.rsrc")).unwrap();
+        let executable = contents.join("MacOS/Glow");
+        (contents, executable)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn matches_rcodesign() {
+        let dir = tempfile::tempdir().unwrap();
+        let (contents, executable) = bundle(dir.path());
+        // Left over from an earlier signature, and files the rules omit
+        std::fs::create_dir(contents.join("_CodeSignature")).unwrap();
+        std::fs::write(contents.join("_CodeSignature/CodeResources"), "old").unwrap();
+        std::fs::write(contents.join("Resources/.DS_Store"), "finder").unwrap();
+        std::fs::write(contents.join("Resources/en.lproj/locversion.plist"), "loc").unwrap();
+
+        let golden =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sign/CodeResources");
+        assert_eq!(
+            String::from_utf8(code_resources(&contents, &executable).unwrap()).unwrap(),
+            std::fs::read_to_string(golden).unwrap()
+        );
+    }
+
+    #[test]
+    fn seals_localizations_but_base_as_optional() {
+        let dir = tempfile::tempdir().unwrap();
+        let (contents, executable) = bundle(dir.path());
+        let xml = code_resources(&contents, &executable).unwrap();
+        let root = Value::from_reader_xml(xml.as_slice()).unwrap();
+        let root = root.as_dictionary().unwrap();
+        let table = |key: &str| root.get(key).and_then(Value::as_dictionary).unwrap();
+
+        let files = table("files");
+        let files2 = table("files2");
+        let lproj = "Resources/en.lproj/Localizable.strings";
+        let optional = |entry: &Value| {
+            entry
+                .as_dictionary()
+                .and_then(|entry| entry.get("optional"))
+                .and_then(Value::as_boolean)
+        };
+        assert_eq!(optional(files.get(lproj).unwrap()), Some(true));
+        assert_eq!(
+            files.get("Resources/Glow.rsrc"),
+            Some(&Value::Data(Sha1::digest(b"PiPL").to_vec()))
+        );
+        // Base.lproj is a localization too, but the rules weigh it as required
+        let base = "Resources/Base.lproj/Localizable.strings";
+        assert_eq!(optional(files.get(base).unwrap()), None);
+        assert_eq!(optional(files2.get(base).unwrap()), None);
+        assert!(files.contains_key("version.plist"));
+
+        assert_eq!(optional(files2.get(lproj).unwrap()), Some(true));
+        assert!(files2.contains_key("version.plist"));
+        for omitted in ["Info.plist", "PkgInfo", "MacOS/Glow"] {
+            assert!(
+                !files.contains_key(omitted) && !files2.contains_key(omitted),
+                "{omitted}"
+            );
+        }
+
+        let rules = table("rules");
+        assert_eq!(rules.get("^Resources/"), Some(&Value::Boolean(true)));
+        let rules2 = table("rules2");
+        assert_eq!(rules2.len(), RULES2.len());
+        let lproj_rule = rules2
+            .get("^Resources/.*\\.lproj/")
+            .and_then(Value::as_dictionary)
+            .unwrap();
+        assert_eq!(lproj_rule.get("weight"), Some(&Value::Real(1000.0)));
+    }
+
+    #[test]
+    fn refuses_nested_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let (contents, executable) = bundle(dir.path());
+        std::fs::write(contents.join("MacOS/helper"), "helper").unwrap();
+        let error = code_resources(&contents, &executable).unwrap_err();
+        assert!(error.starts_with("MacOS/helper is nested code"), "{error}");
+    }
+}
@@ -0,0 +1,629 @@
+//! Ad-hoc code signatures of Mach-O files, written in-process.
+//!
+//! The signature is a superblob at the end of `__LINKEDIT`, which `LC_CODE_SIGNATURE` points
+//! to. Like `codesign --sign -`, it holds a SHA-256 CodeDirectory hashing every 4 KiB page before
+//! it together with the bundle's `Info.plist` and `CodeResources`, an empty requirement set and
+//! an empty CMS blob.
+//!
+//! Signatures made by any signer can also be read back far enough to identify the code, as
+//! notarization tickets do.
+
+use sha2::{Digest, Sha256};
+
+use crate::build::fat;
+
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const MH_EXECUTE: u32 = 2;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_CODE_SIGNATURE: u32 = 0x1d;
+const LC_VERSION_MIN_MACOSX: u32 = 0x24;
+const LC_BUILD_VERSION: u32 = 0x32;
+const MACH_HEADER_SIZE: usize = 32;
+const LINKEDIT_DATA_COMMAND_SIZE: usize = 16;
+
+const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade_0cc0;
+const CSMAGIC_CODEDIRECTORY: u32 = 0xfade_0c02;
+const CSMAGIC_BLOBWRAPPER: u32 = 0xfade_0b01;
+const CSSLOT_CODEDIRECTORY: u32 = 0;
+const CSSLOT_REQUIREMENTS: u32 = 2;
+const CSSLOT_SIGNATURESLOT: u32 = 0x10000;
+
+const CS_ADHOC: u32 = 0x2;
+const CS_RUNTIME: u32 = 0x1_0000;
+const CS_EXECSEG_MAIN_BINARY: u64 = 0x1;
+const CS_HASHTYPE_SHA256: u8 = 2;
+const HASH_SIZE: usize = 32;
+const PAGE_SHIFT: u8 = 12;
+const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+
+/// CodeDirectory version with the executable segment and runtime version fields, and the size
+/// of its header.
+const CODE_DIRECTORY_VERSION: u32 = 0x2_0500;
+const CODE_DIRECTORY_SIZE: usize = 96;
+
+/// Special slots hashed by the CodeDirectory: `Info.plist` (1), requirements (2) and
+/// `CodeResources` (3).
+const SPECIAL_SLOTS: usize = 3;
+
+/// An empty requirement set: magic `0xfade0c01`, length 12, no requirements.
+const REQUIREMENTS: [u8; 12] = [0xfa, 0xde, 0x0c, 0x01, 0, 0, 0, 12, 0, 0, 0, 0];
+
+/// What the signature binds the executable to besides its own pages.
+#[derive(Debug, Clone, Copy)]
+pub struct SignInfo<'a> {
+    /// Code signing identifier, the bundle identifier for bundles
+    pub identifier: &'a str,
+    pub info_plist: Option<&'a [u8]>,
+    pub code_resources: Option<&'a [u8]>,
+    /// Opt in to the hardened runtime
+    pub hardened_runtime: bool,
+}
+
+/// Signs a thin or fat Mach-O file ad-hoc, replacing any signature it has.
+pub fn sign(data: &[u8], info: &SignInfo) -> Result<Vec<u8>, String> {
+    match fat::read(data) {
+        Some(slices) => {
+            let mut slices = slices?;
+            for slice in &mut slices {
+                slice.data = sign_thin(&slice.data, info)?;
+            }
+            fat::write(&slices)
+        }
+        None => sign_thin(data, info),
+    }
+}
+
+/// The CodeDirectory a signed Mach-O file is identified by.
+#[derive(Debug, Clone)]
+pub struct CodeDirectory {
+    /// `CS_*` flags of the signature
+    pub flags: u32,
+    /// Hash type the CodeDirectory is hashed with, `2` for SHA-256
+    pub hash_type: u8,
+    /// The cdhash: the CodeDirectory's hash, truncated to 20 bytes
+    pub cdhash: Vec<u8>,
+    /// Whether the signature carries a CMS signature, i.e. was signed with a certificate
+    pub has_cms: bool,
+}
+
+impl CodeDirectory {
+    pub fn is_ad_hoc(&self) -> bool {
+        self.flags & CS_ADHOC != 0 || !self.has_cms
+    }
+}
+
+/// Reads the preferred CodeDirectory of every architecture of a thin or fat Mach-O file, the
+/// one notarization tickets are recorded under. Fails if an architecture is not signed.
+pub fn code_directories(data: &[u8]) -> Result<Vec<CodeDirectory>, String> {
+    match fat::read(data) {
+        Some(slices) => slices?
+            .iter()
+            .map(|slice| code_directory(&slice.data))
+            .collect(),
+        None => code_directory(data).map(|directory| vec![directory]),
+    }
+}
+
+fn code_directory(data: &[u8]) -> Result<CodeDirectory, String> {
+    if data.len() < MACH_HEADER_SIZE || read_u32(data, 0) != MH_MAGIC_64 {
+        return Err("it is not a 64-bit Mach-O file".to_string());
+    }
+    let ncmds = read_u32(data, 16);
+    let commands_end = (MACH_HEADER_SIZE + read_u32(data, 20) as usize).min(data.len());
+    let mut offset = MACH_HEADER_SIZE;
+    let mut signature = None;
+    for _ in 0..ncmds {
+        if offset + 8 > commands_end {
+            break;
+        }
+        let cmdsize = read_u32(data, offset + 4) as usize;
+        if read_u32(data, offset) == LC_CODE_SIGNATURE && offset + 16 <= commands_end {
+            let start = read_u32(data, offset + 8) as usize;
+            signature = data.get(start..start + read_u32(data, offset + 12) as usize);
+        }
+        offset += cmdsize.max(8);
+    }
+    let signature = signature.ok_or("it is not signed")?;
+    let blob = |offset: usize| -> Option<&[u8]> {
+        let length = u32::from_be_bytes(signature.get(offset + 4..offset + 8)?.try_into().ok()?);
+        signature.get(offset..offset + length as usize)
+    };
+    let be_u32 = |data: &[u8], offset: usize| {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    };
+    if signature.len() < 12 || be_u32(signature, 0) != CSMAGIC_EMBEDDED_SIGNATURE {
+        return Err("its code signature is not an embedded signature".to_string());
+    }
+    let count = be_u32(signature, 8) as usize;
+    let mut directories = Vec::new();
+    let mut has_cms = false;
+    for index in (0..count).map(|i| 12 + i * 8) {
+        let slot = signature
+            .get(index..index + 8)
+            .ok_or("its code signature is truncated")?;
+        let (slot_type, offset) = (be_u32(slot, 0), be_u32(slot, 4) as usize);
+        let blob = blob(offset).ok_or("its code signature is truncated")?;
+        match slot_type {
+            // The CodeDirectory, and the alternate ones
+            CSSLOT_CODEDIRECTORY | 0x1000..=0x1004 => {
+                if blob.len() < 44 || be_u32(blob, 0) != CSMAGIC_CODEDIRECTORY {
+                    return Err("its CodeDirectory is invalid".to_string());
+                }
+                directories.push(blob);
+            }
+            CSSLOT_SIGNATURESLOT => has_cms = blob.len() > 8,
+            _ => {}
+        }
+    }
+    // SHA-256 is preferred over SHA-1, and over the truncated and SHA-384 hashes
+    let directory = directories
+        .iter()
+        .max_by_key(|directory| directory[37] == CS_HASHTYPE_SHA256)
+        .ok_or("its code signature has no CodeDirectory")?;
+    let hash_type = directory[37];
+    let cdhash = match hash_type {
+        1 => sha1::Sha1::digest(directory).to_vec(),
+        CS_HASHTYPE_SHA256 => Sha256::digest(directory).to_vec(),
+        other => {
+            return Err(format!(
+                "its CodeDirectory uses unsupported hash type {other}"
+            ));
+        }
+    };
+    Ok(CodeDirectory {
+        flags: be_u32(directory, 12),
+        hash_type,
+        cdhash: cdhash[..20].to_vec(),
+        has_cms,
+    })
+}
+
+/// A `LC_SEGMENT_64` command: where it is, and where its segment is in the file.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    command: usize,
+    fileoff: u64,
+    filesize: u64,
+    vmsize: u64,
+}
+
+fn sign_thin(data: &[u8], info: &SignInfo) -> Result<Vec<u8>, String> {
+    let mut file = data.to_vec();
+    if file.len() < MACH_HEADER_SIZE || read_u32(&file, 0) != MH_MAGIC_64 {
+        return Err("it is not a 64-bit Mach-O file".to_string());
+    }
+    let cpu_type = read_u32(&file, 4);
+    let file_type = read_u32(&file, 12);
+    let ncmds = read_u32(&file, 16);
+    let sizeofcmds = read_u32(&file, 20) as usize;
+    let commands_end = MACH_HEADER_SIZE + sizeofcmds;
+    if commands_end > file.len() {
+        return Err("the load commands run past the end of the file".to_string());
+    }
+
+    let mut text = None;
+    let mut linkedit = None;
+    let mut code_signature = None;
+    // SDK the file was built with, which hardened runtime policies are versioned by
+    let mut sdk = 0;
+    // Load commands can grow up to the first section's contents
+    let mut first_section = file.len();
+    let mut offset = MACH_HEADER_SIZE;
+    for _ in 0..ncmds {
+        if offset + 8 > commands_end {
+            return Err("a load command runs past the load commands".to_string());
+        }
+        let (cmd, cmdsize) = (
+            read_u32(&file, offset),
+            read_u32(&file, offset + 4) as usize,
+        );
+        if cmdsize < 8 || offset + cmdsize > commands_end {
+            return Err(format!("load command {cmd:#x} has an invalid size"));
+        }
+        match cmd {
+            // The command is inside the file from here on, but may be shorter than its kind
+            LC_SEGMENT_64 => {
+                if cmdsize < 72 {
+                    return Err("a segment command is too short".to_string());
+                }
+                let sections = read_u32(&file, offset + 64) as usize;
+                if cmdsize < 72 + sections * 80 {
+                    return Err("a segment command is too short for its sections".to_string());
+                }
+                let segment = Segment {
+                    command: offset,
+                    fileoff: read_u64(&file, offset + 40),
+                    filesize: read_u64(&file, offset + 48),
+                    vmsize: read_u64(&file, offset + 32),
+                };
+                match &file[offset + 8..offset + 24] {
+                    name if name.starts_with(b"__TEXT\0") => text = Some(segment),
+                    name if name.starts_with(b"__LINKEDIT\0") => linkedit = Some(segment),
+                    _ => {}
+                }
+                for section in (0..sections).map(|i| offset + 72 + i * 80) {
+                    let section_offset = read_u32(&file, section + 48) as usize;
+                    if section_offset != 0 && read_u64(&file, section + 40) != 0 {
+                        first_section = first_section.min(section_offset);
+                    }
+                }
+            }
+            LC_CODE_SIGNATURE if cmdsize < LINKEDIT_DATA_COMMAND_SIZE => {
+                return Err("the code signature command is too short".to_string());
+            }
+            LC_CODE_SIGNATURE => code_signature = Some(offset),
+            LC_BUILD_VERSION if cmdsize >= 24 => sdk = read_u32(&file, offset + 16),
+            LC_VERSION_MIN_MACOSX if cmdsize >= 16 => sdk = read_u32(&file, offset + 12),
+            _ => {}
+        }
+        offset += cmdsize;
+    }
+    let text = text.ok_or("it has no __TEXT segment")?;
+    let mut linkedit = linkedit.ok_or("it has no __LINKEDIT segment")?;
+    let linkedit_end = linkedit
+        .fileoff
+        .checked_add(linkedit.filesize)
+        .filter(|&end| end <= file.len() as u64)
+        .ok_or("__LINKEDIT runs past the end of the file")? as usize;
+    if linkedit_end != file.len() && code_signature.is_none() {
+        return Err("__LINKEDIT is not at the end of the file".to_string());
+    }
+
+    let (command, code_limit) = match code_signature {
+        Some(command) => (command, read_u32(&file, command + 8) as usize),
+        None => {
+            if commands_end + LINKEDIT_DATA_COMMAND_SIZE > first_section
+                || file[commands_end..commands_end + LINKEDIT_DATA_COMMAND_SIZE]
+                    .iter()
+                    .any(|&b| b != 0)
+            {
+                return Err(
+                    "there is no room for LC_CODE_SIGNATURE after the load commands; \
+                     link with `-C link-arg=-Wl,-headerpad,0x100`"
+                        .to_string(),
+                );
+            }
+            write_u32(&mut file, commands_end, LC_CODE_SIGNATURE);
+            write_u32(
+                &mut file,
+                commands_end + 4,
+                LINKEDIT_DATA_COMMAND_SIZE as u32,
+            );
+            write_u32(&mut file, 16, ncmds + 1);
+            write_u32(
+                &mut file,
+                20,
+                (sizeofcmds + LINKEDIT_DATA_COMMAND_SIZE) as u32,
+            );
+            (commands_end, linkedit_end.next_multiple_of(16))
+        }
+    };
+    if code_limit < linkedit.fileoff as usize || code_limit > file.len() {
+        return Err("the code signature is not in __LINKEDIT".to_string());
+    }
+    let code_limit_u32 =
+        u32::try_from(code_limit).map_err(|_| "the file is too large to sign".to_string())?;
+    file.resize(code_limit, 0);
+
+    let code_slots = code_limit.div_ceil(PAGE_SIZE);
+    let hash_offset = CODE_DIRECTORY_SIZE + info.identifier.len() + 1 + SPECIAL_SLOTS * HASH_SIZE;
+    let code_directory_size = hash_offset + code_slots * HASH_SIZE;
+    let index_size = 12 + 3 * 8;
+    let signature_size = index_size + code_directory_size + REQUIREMENTS.len() + 8;
+    let data_size = signature_size.next_multiple_of(16);
+
+    // The header page is hashed too, so the load commands are final before hashing
+    write_u32(&mut file, command + 8, code_limit_u32);
+    write_u32(&mut file, command + 12, data_size as u32);
+    linkedit.filesize = (code_limit + data_size) as u64 - linkedit.fileoff;
+    let vm_page = if cpu_type == 0x0100_000c {
+        0x4000
+    } else {
+        0x1000
+    };
+    linkedit.vmsize = linkedit
+        .vmsize
+        .max(linkedit.filesize.next_multiple_of(vm_page));
+    write_u64(&mut file, linkedit.command + 32, linkedit.vmsize);
+    write_u64(&mut file, linkedit.command + 48, linkedit.filesize);
+
+    let mut flags = CS_ADHOC;
+    if info.hardened_runtime {
+        flags |= CS_RUNTIME;
+    }
+    let mut cd = Vec::with_capacity(code_directory_size);
+    for word in [
+        CSMAGIC_CODEDIRECTORY,
+        code_directory_size as u32,
+        CODE_DIRECTORY_VERSION,
+        flags,
+        hash_offset as u32,
+        CODE_DIRECTORY_SIZE as u32,
+        SPECIAL_SLOTS as u32,
+        code_slots as u32,
+        code_limit_u32,
+    ] {
+        cd.extend(word.to_be_bytes());
+    }
+    cd.extend([HASH_SIZE as u8, CS_HASHTYPE_SHA256, 0, PAGE_SHIFT]);
+    cd.extend([0u8; 4]); // spare2
+    cd.extend([0u8; 4]); // scatterOffset
+    cd.extend([0u8; 4]); // teamOffset
+    cd.extend([0u8; 4]); // spare3
+    cd.extend([0u8; 8]); // codeLimit64
+    cd.extend(text.fileoff.to_be_bytes());
+    cd.extend(text.filesize.to_be_bytes());
+    let exec_seg_flags = match file_type {
+        MH_EXECUTE => CS_EXECSEG_MAIN_BINARY,
+        _ => 0,
+    };
+    cd.extend(exec_seg_flags.to_be_bytes());
+    let runtime = if info.hardened_runtime { sdk } else { 0 };
+    cd.extend(runtime.to_be_bytes());
+    cd.extend([0u8; 4]); // preEncryptOffset
+    cd.extend(info.identifier.as_bytes());
+    cd.push(0);
+    // Special slots are stored in reverse, ending right before code slot 0
+    for slot in [
+        info.code_resources,
+        Some(REQUIREMENTS.as_slice()),
+        info.info_plist,
+    ] {
+        match slot {
+            Some(data) => cd.extend(Sha256::digest(data)),
+            None => cd.extend([0u8; HASH_SIZE]),
+        }
+    }
+    for page in file.chunks(PAGE_SIZE) {
+        cd.extend(Sha256::digest(page));
+    }
+
+    let mut signature = Vec::with_capacity(data_size);
+    let requirements_offset = index_size + cd.len();
+    let cms_offset = requirements_offset + REQUIREMENTS.len();
+    for word in [
+        CSMAGIC_EMBEDDED_SIGNATURE,
+        signature_size as u32,
+        3,
+        CSSLOT_CODEDIRECTORY,
+        index_size as u32,
+        CSSLOT_REQUIREMENTS,
+        requirements_offset as u32,
+        CSSLOT_SIGNATURESLOT,
+        cms_offset as u32,
+    ] {
+        signature.extend(word.to_be_bytes());
+    }
+    signature.extend(cd);
+    signature.extend(REQUIREMENTS);
+    signature.extend(CSMAGIC_BLOBWRAPPER.to_be_bytes());
+    signature.extend(8u32.to_be_bytes());
+    signature.resize(data_size, 0);
+
+    file.extend(signature);
+    Ok(file)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    const IDENTIFIER: &str = "com.example.machot";
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/macho");
+        std::fs::read(dir.join(name)).unwrap()
+    }
+
+    fn sign_fixture(data: &[u8]) -> Result<Vec<u8>, String> {
+        sign(
+            data,
+            &SignInfo {
+                identifier: IDENTIFIER,
+                info_plist: Some(b"plist"),
+                code_resources: Some(b"resources"),
+                hardened_runtime: true,
+            },
+        )
+    }
+
+    fn be(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// The signature of the fixture, as `rcodesign print-signature-info` and
+    /// `rcodesign compute-code-hashes` read it: a 331-byte superblob at 0x1040 with a 275-byte
+    /// CodeDirectory, the empty requirement set and an empty CMS blob.
+    #[test]
+    fn writes_the_signature_codesign_writes() {
+        let signed = sign_fixture(&fixture("libmachot.dylib")).unwrap();
+        assert_eq!(signed.len(), 4496);
+        assert_eq!(
+            hex(&Sha256::digest(&signed)),
+            "ea8986fbba4b3bd4cc7f0e2879b22475d8ec977773a5c181c42888fcd8499df5"
+        );
+
+        let signature = &signed[0x1040..];
+        assert_eq!(be(signature, 0), CSMAGIC_EMBEDDED_SIGNATURE);
+        assert_eq!(be(signature, 4), 331);
+        let index: Vec<u32> = (0..7).map(|i| be(signature, 8 + i * 4)).collect();
+        assert_eq!(index, [3, 0, 36, 2, 311, 0x10000, 323]);
+        assert_eq!(&signature[311..323], REQUIREMENTS);
+        assert_eq!(&signature[323..331], [0xfa, 0xde, 0x0b, 0x01, 0, 0, 0, 8]);
+
+        let cd = &signature[36..311];
+        let header: Vec<u32> = (0..9).map(|i| be(cd, i * 4)).collect();
+        // magic, length, version, flags (adhoc, runtime), hashOffset, identOffset,
+        // nSpecialSlots, nCodeSlots, codeLimit
+        assert_eq!(
+            header,
+            [
+                CSMAGIC_CODEDIRECTORY,
+                275,
+                0x2_0500,
+                0x1_0002,
+                211,
+                96,
+                3,
+                2,
+                4160
+            ]
+        );
+        // hashSize, hashType (SHA-256), platform, pageSize (4 KiB)
+        assert_eq!(cd[36..40], [32, 2, 0, 12]);
+        // execSegBase and execSegLimit (__TEXT), execSegFlags, runtime (10.12, from the SDK)
+        assert_eq!(&cd[64..72], 0u64.to_be_bytes());
+        assert_eq!(&cd[72..80], 4096u64.to_be_bytes());
+        assert_eq!(&cd[80..88], 0u64.to_be_bytes());
+        assert_eq!(be(cd, 88), 0x000a_0c00);
+        assert_eq!(&cd[96..115], b"com.example.machot\0");
+
+        let slots: Vec<String> = cd[115..].chunks(HASH_SIZE).map(hex).collect();
+        assert_eq!(
+            slots,
+            [
+                // Resources (3), RequirementSet (2), Info (1)
+                "41d311a605520fc7b8b9a980a79437a26e26cebcbfdd569dd78d30f7cb3e7237",
+                "987920904eab650e75788c054aa0b0524e6a80bfc71aa32df8d237a61743f986",
+                "9ceec13202afbf12ee3abb994c669c711749c18e194326734db6123e94947e04",
+                // Pages 0 and 1
+                "c44d38a79011757631f2f5549eb11c8d21df978aba2dc8aafc5bb150d2d17b77",
+                "42a2710a51b689c940f4df2d7246db65eb8146049b40071938e60f2db092c950",
+            ]
+        );
+        assert_eq!(slots[3], hex(&Sha256::digest(&signed[..4096])));
+        assert_eq!(slots[4], hex(&Sha256::digest(&signed[4096..4160])));
+
+        let [directory] = code_directories(&signed).unwrap().try_into().unwrap();
+        assert_eq!(
+            hex(&directory.cdhash),
+            "c8294ff184eee3af3c2c259302862937c038a8f8"
+        );
+        assert_eq!(directory.flags, CS_ADHOC | CS_RUNTIME);
+        assert!(directory.is_ad_hoc());
+    }
+
+    #[test]
+    fn resigning_replaces_the_signature() {
+        let signed = sign_fixture(&fixture("libmachot.dylib")).unwrap();
+        assert_eq!(sign_fixture(&signed).unwrap(), signed);
+        let signed = sign_fixture(&fixture("libmachot.rcodesign.dylib")).unwrap();
+        assert_eq!(
+            code_directories(&signed).unwrap()[0].flags,
+            CS_ADHOC | CS_RUNTIME
+        );
+    }
+
+    #[test]
+    fn signs_every_slice_of_a_fat_binary() {
+        let thin = fixture("libmachot.dylib");
+        let slice = |data: Vec<u8>| fat::ThinSlice {
+            cpu_type: 0x0100_0007,
+            cpu_subtype: 3,
+            align: 12,
+            data,
+        };
+        let signed = sign_fixture(&fat::write(&[slice(thin.clone())]).unwrap()).unwrap();
+        let expected = fat::write(&[slice(sign_fixture(&thin).unwrap())]).unwrap();
+        assert_eq!(signed, expected);
+    }
+
+    #[test]
+    fn reads_the_signature_of_another_signer() {
+        // Signed by `rcodesign sign --code-signature-flags runtime`
+        let [directory] = code_directories(&fixture("libmachot.rcodesign.dylib"))
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            hex(&directory.cdhash),
+            "d4dd6061139d2bdd90451f74f437554fcc40f3dd"
+        );
+        assert_eq!(directory.flags, CS_ADHOC | CS_RUNTIME);
+        assert_eq!(directory.hash_type, CS_HASHTYPE_SHA256);
+        assert!(!directory.has_cms);
+        assert_eq!(
+            code_directories(&fixture("libmachot.dylib")).unwrap_err(),
+            "it is not signed"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_load_commands() {
+        // A header with one load command of `cmdsize` bytes, followed by `body`
+        let file = |cmd: u32, cmdsize: u32, body: &[u8]| {
+            let mut file = Vec::new();
+            for word in [MH_MAGIC_64, 0x0100_0007, 3, 6, 1, cmdsize, 0, 0] {
+                file.extend(word.to_le_bytes());
+            }
+            file.extend(cmd.to_le_bytes());
+            file.extend(cmdsize.to_le_bytes());
+            file.extend(body);
+            file
+        };
+        let info = SignInfo {
+            identifier: IDENTIFIER,
+            info_plist: None,
+            code_resources: None,
+            hardened_runtime: false,
+        };
+        for (data, expected) in [
+            (
+                file(LC_SEGMENT_64, 16, &[0; 8]),
+                "a segment command is too short",
+            ),
+            (
+                file(LC_SEGMENT_64, 72, &[0; 8]),
+                "the load commands run past the end of the file",
+            ),
+            (
+                file(LC_CODE_SIGNATURE, 8, &[]),
+                "the code signature command is too short",
+            ),
+            (
+                // nsects = 2, which need 232 bytes
+                file(
+                    LC_SEGMENT_64,
+                    200,
+                    &[[0; 56].as_slice(), &[2], &[0; 135]].concat(),
+                ),
+                "a segment command is too short for its sections",
+            ),
+        ] {
+            assert_eq!(sign(&data, &info).unwrap_err(), expected);
+        }
+
+        // __LINKEDIT claiming to run far past the end of the file
+        let mut data = fixture("libmachot.dylib");
+        let linkedit = MACH_HEADER_SIZE + 152;
+        write_u64(&mut data, linkedit + 48, u64::MAX);
+        assert_eq!(
+            sign(&data, &info).unwrap_err(),
+            "__LINKEDIT runs past the end of the file"
+        );
+        let truncated = &fixture("libmachot.dylib")[..100];
+        assert!(sign(truncated, &info).is_err());
+    }
+}
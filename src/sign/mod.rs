@@ -0,0 +1,344 @@
+//! Code signing of finished plugins.
+//!
+//! macOS bundles are signed by one of three backends, chosen by `sign.macos.backend`:
+//!
+//! - `builtin` writes an ad-hoc signature in-process, so bundles built on any host are signed;
+//! - `codesign` runs Apple's tool with a keychain identity, on macOS only;
+//! - `rcodesign` signs with a PKCS#12 certificate, on any host.
+//!
+//! `auto` (the default) uses `rcodesign` when a PKCS#12 file is configured, `codesign` when a
+//! keychain identity is, and `builtin` otherwise. Signing with a certificate needs the CMS
+//! signature only those tools write, so they must be installed for it; cargo-jk itself signs
+//! ad-hoc only.
+//!
+//! Windows plugins get an Authenticode signature from the backend `sign.windows.backend` names:
+//!
+//! - `signtool` signs with a certificate in the store or a PFX file, on Windows only;
+//! - `osslsigncode` signs with a PFX file, on any host;
+//! - `command` runs a configured program, for HSM and cloud signing services.
+//!
+//! `auto` picks one from what is configured, and leaves the plugin unsigned if nothing is. The
+//! signature is checked after signing unless `sign.windows.verify` is `false`.
+
+mod authenticode;
+mod code_resources;
+pub mod macho;
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use plist::Value;
+
+use crate::{
+    build::SigningStatus,
+    config::{MacSignBackend, MacSignConfig, WindowsSignBackend, WindowsSignConfig},
+    error::JkError,
+};
+
+/// Identity of ad-hoc signatures, as `codesign` spells it.
+const AD_HOC: &str = "-";
+
+/// Signs the macOS bundle at `bundle` as `config` says.
+pub fn sign_mac_bundle(config: &MacSignConfig, bundle: &Path) -> Result<SigningStatus, JkError> {
+    let failed = |reason: String| JkError::Signing {
+        path: bundle.to_path_buf(),
+        reason,
+    };
+    let identity = config
+        .identity
+        .as_deref()
+        .filter(|identity| *identity != AD_HOC);
+    let backend = match config.backend {
+        MacSignBackend::Auto if config.p12_file.is_some() => MacSignBackend::Rcodesign,
+        MacSignBackend::Auto if identity.is_some() => MacSignBackend::Codesign,
+        MacSignBackend::Auto => MacSignBackend::Builtin,
+        backend => backend,
+    };
+    let hardened_runtime = config.hardened_runtime.unwrap_or(true);
+    let timestamp = config.timestamp.unwrap_or(true);
+
+    let (backend_name, signed_as) = match backend {
+        MacSignBackend::Auto | MacSignBackend::Builtin => {
+            if identity.is_some() || config.p12_file.is_some() {
+                return Err(failed(
+                    "the builtin signer only signs ad-hoc; use the codesign or rcodesign backend to sign with a certificate".to_string(),
+                ));
+            }
+            if config.entitlements.is_some() {
+                return Err(failed(
+                    "the builtin signer cannot embed entitlements; use the codesign or rcodesign backend".to_string(),
+                ));
+            }
+            sign_ad_hoc(bundle, hardened_runtime).map_err(failed)?;
+            ("builtin", AD_HOC.to_string())
+        }
+        MacSignBackend::Codesign => {
+            if !cfg!(target_os = "macos") {
+                return Err(failed(
+                    "codesign is only available on macOS; use the builtin or rcodesign backend"
+                        .to_string(),
+                ));
+            }
+            let identity = identity.unwrap_or(AD_HOC);
+            let mut command = Command::new("codesign");
+            command.arg("--force").arg("--sign").arg(identity);
+            if hardened_runtime {
+                command.arg("--options").arg("runtime");
+            }
+            // Ad-hoc signatures are never timestamped
+            if identity != AD_HOC {
+                command.arg(if timestamp {
+                    "--timestamp"
+                } else {
+                    "--timestamp=none"
+                });
+            }
+            if let Some(entitlements) = &config.entitlements {
+                command.arg("--entitlements").arg(entitlements);
+            }
+            run(command.arg(bundle)).map_err(failed)?;
+            ("codesign", identity.to_string())
+        }
+        MacSignBackend::Rcodesign => {
+            let mut command = Command::new("rcodesign");
+            command.arg("sign");
+            if let Some(p12_file) = &config.p12_file {
+                command.arg("--p12-file").arg(p12_file);
+                if let Some(password_file) = &config.p12_password_file {
+                    command.arg("--p12-password-file").arg(password_file);
+                }
+                if !timestamp {
+                    command.arg("--timestamp-url").arg("none");
+                }
+            }
+            if hardened_runtime {
+                command.arg("--code-signature-flags").arg("runtime");
+            }
+            if let Some(entitlements) = &config.entitlements {
+                command.arg("--entitlements-xml-file").arg(entitlements);
+            }
+            run(command.arg(bundle)).map_err(failed)?;
+            let identity = match &config.p12_file {
+                Some(p12_file) => p12_file.display().to_string(),
+                None => AD_HOC.to_string(),
+            };
+            ("rcodesign", identity)
+        }
+    };
+    Ok(SigningStatus {
+        signed: true,
+        backend: Some(backend_name.to_string()),
+        identity: Some(signed_as),
+        verified: false,
+    })
+}
+
+/// Signs the Windows plugin at `path` as `config` says, then checks the signature.
+pub fn sign_windows_plugin(
+    config: &WindowsSignConfig,
+    path: &Path,
+) -> Result<SigningStatus, JkError> {
+    let failed = |reason: String| JkError::Signing {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let backend = match config.backend {
+        WindowsSignBackend::Auto if config.command.is_some() => WindowsSignBackend::Command,
+        WindowsSignBackend::Auto if config.thumbprint.is_some() => WindowsSignBackend::Signtool,
+        WindowsSignBackend::Auto if config.pfx_file.is_some() => match cfg!(windows) {
+            true => WindowsSignBackend::Signtool,
+            false => WindowsSignBackend::Osslsigncode,
+        },
+        WindowsSignBackend::Auto | WindowsSignBackend::None => return Ok(SigningStatus::default()),
+        backend => backend,
+    };
+    let password = || match &config.pfx_password_file {
+        Some(file) => std::fs::read_to_string(file)
+            .map(|password| Some(password.trim_end_matches(['\r', '\n']).to_string()))
+            .map_err(|e| failed(format!("cannot read {}: {e}", file.display()))),
+        None => Ok(None),
+    };
+    let verify = config.verify.unwrap_or(true);
+
+    let (backend_name, identity, verify_command) = match backend {
+        WindowsSignBackend::Auto | WindowsSignBackend::None => unreachable!("resolved above"),
+        WindowsSignBackend::Signtool => {
+            if !cfg!(windows) {
+                return Err(failed(
+                    "signtool is only available on Windows; use the osslsigncode or command backend"
+                        .to_string(),
+                ));
+            }
+            let mut command = Command::new("signtool");
+            command.args(["sign", "/fd", "sha256"]);
+            let identity = match (&config.thumbprint, &config.pfx_file) {
+                (Some(thumbprint), _) => {
+                    command.arg("/sha1").arg(thumbprint);
+                    Some(thumbprint.clone())
+                }
+                (None, Some(pfx_file)) => {
+                    command.arg("/f").arg(pfx_file);
+                    if let Some(password) = password()? {
+                        command.arg("/p").arg(password);
+                    }
+                    Some(pfx_file.display().to_string())
+                }
+                (None, None) => {
+                    return Err(failed(
+                        "signtool needs `sign.windows.thumbprint` or `sign.windows.pfx_file`"
+                            .to_string(),
+                    ));
+                }
+            };
+            if let Some(url) = &config.timestamp_url {
+                command.arg("/tr").arg(url).args(["/td", "sha256"]);
+            }
+            run(command.arg(path)).map_err(failed)?;
+            let mut verify = Command::new("signtool");
+            verify.args(["verify", "/pa"]).arg(path);
+            ("signtool", identity, Some(verify))
+        }
+        WindowsSignBackend::Osslsigncode => {
+            let pfx_file = config
+                .pfx_file
+                .as_ref()
+                .ok_or_else(|| failed("osslsigncode needs `sign.windows.pfx_file`".to_string()))?;
+            // osslsigncode cannot sign in place
+            let mut signed = path.as_os_str().to_owned();
+            signed.push(".signed");
+            let signed = PathBuf::from(signed);
+            let mut command = Command::new("osslsigncode");
+            command.arg("sign").arg("-pkcs12").arg(pfx_file);
+            if let Some(password_file) = &config.pfx_password_file {
+                command.arg("-readpass").arg(password_file);
+            }
+            command.args(["-h", "sha256"]);
+            if let Some(url) = &config.timestamp_url {
+                command.arg("-ts").arg(url);
+            }
+            command.arg("-in").arg(path).arg("-out").arg(&signed);
+            run(&mut command).map_err(failed)?;
+            std::fs::rename(&signed, path)
+                .map_err(|e| failed(format!("cannot replace the unsigned file: {e}")))?;
+            let mut verify = Command::new("osslsigncode");
+            verify.arg("verify").arg("-in").arg(path);
+            (
+                "osslsigncode",
+                Some(pfx_file.display().to_string()),
+                Some(verify),
+            )
+        }
+        WindowsSignBackend::Command => {
+            let template = config
+                .command
+                .as_deref()
+                .filter(|template| !template.is_empty())
+                .ok_or_else(|| failed("`sign.windows.command` is not set".to_string()))?;
+            let args = template
+                .iter()
+                .map(|arg| expand(arg, path, config.timestamp_url.as_deref()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(failed)?;
+            run(Command::new(&args[0]).args(&args[1..])).map_err(failed)?;
+            ("command", None, None)
+        }
+    };
+
+    if verify {
+        let data =
+            std::fs::read(path).map_err(|e| failed(format!("cannot read the signed file: {e}")))?;
+        authenticode::verify(&data).map_err(|e| failed(format!("verification failed: {e}")))?;
+        if let Some(mut command) = verify_command {
+            run(&mut command).map_err(|e| failed(format!("verification failed: {e}")))?;
+        }
+    }
+    Ok(SigningStatus {
+        signed: true,
+        backend: Some(backend_name.to_string()),
+        identity,
+        verified: verify,
+    })
+}
+
+/// Seals the bundle's resources in `_CodeSignature/CodeResources` and signs its executable
+/// ad-hoc, in-process.
+fn sign_ad_hoc(bundle: &Path, hardened_runtime: bool) -> Result<(), String> {
+    let contents = bundle.join("Contents");
+    let info_plist_path = contents.join("Info.plist");
+    let info_plist = std::fs::read(&info_plist_path)
+        .map_err(|e| format!("cannot read {}: {e}", info_plist_path.display()))?;
+    let info = Value::from_reader(std::io::Cursor::new(&info_plist))
+        .map_err(|e| format!("cannot read {}: {e}", info_plist_path.display()))?;
+    let string = |key: &str| {
+        info.as_dictionary()
+            .and_then(|dict| dict.get(key))
+            .and_then(Value::as_string)
+            .ok_or_else(|| format!("Info.plist has no {key}"))
+    };
+    let identifier = string("CFBundleIdentifier")?;
+    let executable = contents.join("MacOS").join(string("CFBundleExecutable")?);
+
+    let code_resources = code_resources::code_resources(&contents, &executable)?;
+    let signature_dir = contents.join("_CodeSignature");
+    std::fs::create_dir_all(&signature_dir)
+        .and_then(|()| std::fs::write(signature_dir.join("CodeResources"), &code_resources))
+        .map_err(|e| format!("cannot write {}: {e}", signature_dir.display()))?;
+
+    let data = std::fs::read(&executable)
+        .map_err(|e| format!("cannot read {}: {e}", executable.display()))?;
+    let signed = macho::sign(
+        &data,
+        &macho::SignInfo {
+            identifier,
+            info_plist: Some(&info_plist),
+            code_resources: Some(&code_resources),
+            hardened_runtime,
+        },
+    )
+    .map_err(|e| format!("cannot sign {}: {e}", executable.display()))?;
+    std::fs::write(&executable, signed)
+        .map_err(|e| format!("cannot write {}: {e}", executable.display()))
+}
+
+/// Runs a signing tool, failing with a reason if it cannot start or does not succeed.
+fn run(command: &mut Command) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command.status().map_err(|e| match e.kind() {
+        ErrorKind::NotFound => format!("{program} was not found; {}", install_hint(&program)),
+        _ => format!("failed to execute {program}: {e}"),
+    })?;
+    if !status.success() {
+        return Err(format!("{program} exited with {status}"));
+    }
+    Ok(())
+}
+
+/// Where to get a signing tool that is not installed.
+fn install_hint(program: &str) -> &'static str {
+    match program {
+        "rcodesign" => {
+            "signing with a PKCS#12 certificate needs rcodesign (`cargo install apple-codesign`), \
+             since the builtin signer only signs ad-hoc"
+        }
+        "codesign" => "install the Xcode command line tools with `xcode-select --install`",
+        "signtool" => "install the Windows SDK and add its bin directory to PATH",
+        "osslsigncode" => "install osslsigncode, e.g. from your package manager",
+        _ => "check that it is installed and on PATH",
+    }
+}
+
+/// Replaces the placeholders of a `sign.windows.command` argument.
+fn expand(arg: &str, path: &Path, timestamp_url: Option<&str>) -> Result<String, String> {
+    if arg.contains("{timestamp_url}") && timestamp_url.is_none() {
+        return Err(
+            "the signing command uses {timestamp_url}, but `sign.windows.timestamp_url` is not set"
+                .to_string(),
+        );
+    }
+    Ok(arg
+        .replace("{timestamp_url}", timestamp_url.unwrap_or_default())
+        .replace("{file}", &path.to_string_lossy()))
+}
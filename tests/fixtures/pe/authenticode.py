@@ -0,0 +1,100 @@
+"""Authenticode-signs a PE file, laid out as signtool and osslsigncode do, without a timestamp.
+
+    python3 authenticode.py <input> <output> sha256|sha1 <key.pem> <cert.pem>
+"""
+import hashlib, struct, sys
+from cryptography import x509
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import padding
+
+def der_len(n):
+    if n < 0x80: return bytes([n])
+    b = n.to_bytes((n.bit_length() + 7) // 8, 'big')
+    return bytes([0x80 | len(b)]) + b
+def tlv(tag, body): return bytes([tag]) + der_len(len(body)) + body
+def seq(*parts): return tlv(0x30, b''.join(parts))
+def set_(*parts): return tlv(0x31, b''.join(sorted(parts)))
+def oid(dotted):
+    a = [int(x) for x in dotted.split('.')]
+    body = bytes([40 * a[0] + a[1]])
+    for n in a[2:]:
+        enc = [n & 0x7f]; n >>= 7
+        while n: enc.append(0x80 | (n & 0x7f)); n >>= 7
+        body += bytes(reversed(enc))
+    return tlv(0x06, body)
+def integer(n):
+    b = n.to_bytes((n.bit_length() + 8) // 8, 'big')
+    return tlv(0x02, b)
+NULL = b'\x05\x00'
+HASHES = {'sha256': ('2.16.840.1.101.3.4.2.1', hashlib.sha256, hashes.SHA256()),
+          'sha1': ('1.3.14.3.2.26', hashlib.sha1, hashes.SHA1())}
+
+src, dst, algorithm, key_path, cert_path = sys.argv[1:6]
+digest_oid, hasher, signature_hash = HASHES[algorithm]
+data = bytearray(open(src, 'rb').read())
+data += b'\0' * (-len(data) % 8)
+pe = struct.unpack_from('<I', data, 0x3c)[0]
+opt = pe + 24
+magic = struct.unpack_from('<H', data, opt)[0]
+dirs = opt + (96 if magic == 0x10b else 112)
+entry = dirs + 4 * 8
+checksum = opt + 64
+assert struct.unpack_from('<II', data, entry) == (0, 0)
+
+image_digest = hasher(bytes(data[:checksum] + data[checksum + 4:entry] + data[entry + 8:])).digest()
+
+# SpcIndirectDataContent
+obsolete = '<<<Obsolete>>>'.encode('utf-16-be')
+pe_image_data = seq(tlv(0x03, b'\x00'), tlv(0xa0, tlv(0xa2, tlv(0x80, obsolete))))
+indirect = seq(
+    seq(oid('1.3.6.1.4.1.311.2.1.15'), pe_image_data),
+    seq(seq(oid(digest_oid), NULL), tlv(0x04, image_digest)),
+)
+# The message digest covers the content without its outer tag and length
+inner = indirect[2:] if indirect[1] < 0x80 else indirect[2 + (indirect[1] & 0x7f):]
+content_digest = hasher(inner).digest()
+
+cert = x509.load_pem_x509_certificate(open(cert_path, 'rb').read())
+key = serialization.load_pem_private_key(open(key_path, 'rb').read(), None)
+cert_der = cert.public_bytes(serialization.Encoding.DER)
+
+attributes = [
+    seq(oid('1.2.840.113549.1.9.3'), set_(oid('1.3.6.1.4.1.311.2.1.4'))),
+    seq(oid('1.2.840.113549.1.9.4'), set_(tlv(0x04, content_digest))),
+    seq(oid('1.3.6.1.4.1.311.2.1.12'), set_(seq())),
+    seq(oid('1.3.6.1.4.1.311.2.1.11'), set_(seq(oid('1.3.6.1.4.1.311.2.1.21')))),
+]
+signed_attributes = set_(*attributes)
+signature = key.sign(signed_attributes, padding.PKCS1v15(), signature_hash)
+
+signer_info = seq(
+    integer(1),
+    seq(cert.issuer.public_bytes(), integer(cert.serial_number)),
+    seq(oid(digest_oid), NULL),
+    b'\xa0' + signed_attributes[1:],
+    seq(oid('1.2.840.113549.1.1.1'), NULL),
+    tlv(0x04, signature),
+)
+signed_data = seq(
+    integer(1),
+    set_(seq(oid(digest_oid), NULL)),
+    seq(oid('1.3.6.1.4.1.311.2.1.4'), tlv(0xa0, indirect)),
+    tlv(0xa0, cert_der),
+    set_(signer_info),
+)
+pkcs7 = seq(oid('1.2.840.113549.1.7.2'), tlv(0xa0, signed_data))
+
+certificate = struct.pack('<IHH', 8 + len(pkcs7), 0x0200, 2) + pkcs7
+certificate += b'\0' * (-len(certificate) % 8)
+struct.pack_into('<II', data, entry, len(data), len(certificate))
+data += certificate
+
+# PE checksum, as imagehlp's CheckSumMappedFile computes it
+struct.pack_into('<I', data, checksum, 0)
+total = 0
+padded = bytes(data) + b'\0' * (len(data) % 2)
+for (word,) in struct.iter_unpack('<H', padded):
+    total += word
+    total = (total & 0xffff) + (total >> 16)
+struct.pack_into('<I', data, checksum, (total & 0xffff) + len(data))
+open(dst, 'wb').write(data)